@@ -0,0 +1,47 @@
+-- also the init script of the docker-compose database, so every statement is idempotent
+-- and RUN_MIGRATIONS=1 only records it as applied there
+
+-- array_to_string is only STABLE while a generated column needs an IMMUTABLE expression
+CREATE OR REPLACE FUNCTION person_search_text(nickname VARCHAR, "name" VARCHAR, stacks VARCHAR[])
+RETURNS TEXT
+LANGUAGE sql IMMUTABLE
+AS $$ SELECT nickname || ' ' || "name" || ' ' || COALESCE(array_to_string(stacks, ' '), '') $$;
+
+CREATE TABLE IF NOT EXISTS person (
+    id BIGSERIAL PRIMARY KEY,
+    nickname VARCHAR NOT NULL UNIQUE,
+    "name" VARCHAR NOT NULL,
+    dob DATE NOT NULL,
+    stacks VARCHAR[],
+    -- only written when STACKS_STORAGE=jsonb, `stacks` always keeps the flat names
+    stacks_detail JSONB,
+    photo VARCHAR,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- bumped by GET /pessoas/:id?track=true
+    view_count BIGINT NOT NULL DEFAULT 0,
+    -- bumped by every update, for `If-Match` conditional deletes
+    version BIGINT NOT NULL DEFAULT 1,
+    -- everything the substring search looks at, so it can use a single trigram index
+    search TEXT GENERATED ALWAYS AS (person_search_text(nickname, "name", stacks)) STORED,
+    -- set by soft deletes, rows are only removed for good by the admin purge
+    deleted_at TIMESTAMPTZ
+);
+
+-- curated details of the free-form stacks, keyed by the name people use
+CREATE TABLE IF NOT EXISTS stacks_meta (
+    name VARCHAR PRIMARY KEY,
+    display_name VARCHAR,
+    icon VARCHAR
+);
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- used by the fuzzy search
+CREATE INDEX IF NOT EXISTS person_nickname_trgm_idx ON person USING GIN (nickname gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS person_name_trgm_idx ON person USING GIN (name gin_trgm_ops);
+
+-- keyset pagination of the timeline
+CREATE INDEX IF NOT EXISTS person_created_at_idx ON person (created_at DESC, id DESC);
+
+-- used by the substring search, for both LIKE and ILIKE
+CREATE INDEX IF NOT EXISTS person_search_trgm_idx ON person USING GIN (search gin_trgm_ops);
\ No newline at end of file
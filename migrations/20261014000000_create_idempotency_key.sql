@@ -0,0 +1,9 @@
+-- creations made with an `Idempotency-Key`, replayed to the retries of the same payload
+CREATE TABLE IF NOT EXISTS idempotency_key (
+    key VARCHAR PRIMARY KEY,
+    -- SHA-256 of the payload, a retry with another one is rejected
+    fingerprint VARCHAR NOT NULL,
+    -- unset while the creation runs
+    person_id BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
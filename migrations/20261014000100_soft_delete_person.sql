@@ -0,0 +1,7 @@
+-- bumped along with `version` by every update
+ALTER TABLE person ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ;
+UPDATE person SET updated_at = created_at WHERE updated_at IS NULL;
+ALTER TABLE person ALTER COLUMN updated_at SET DEFAULT now(), ALTER COLUMN updated_at SET NOT NULL;
+
+-- what every read and update goes through, soft deleted people are only seen by admins and the purge
+CREATE OR REPLACE VIEW active_person AS SELECT * FROM person WHERE deleted_at IS NULL;
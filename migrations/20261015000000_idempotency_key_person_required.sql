@@ -0,0 +1,4 @@
+-- a key is now only stored along with the person it created, in the same transaction;
+-- the claims left unset by earlier versions belong to creations that never settled
+DELETE FROM idempotency_key WHERE person_id IS NULL;
+ALTER TABLE idempotency_key ALTER COLUMN person_id SET NOT NULL;
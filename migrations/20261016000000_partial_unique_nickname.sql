@@ -0,0 +1,3 @@
+-- only the active people hold their nickname, a soft deleted one frees it right away
+ALTER TABLE person DROP CONSTRAINT IF EXISTS person_nickname_key;
+CREATE UNIQUE INDEX IF NOT EXISTS person_active_nickname_idx ON person (nickname) WHERE deleted_at IS NULL;
@@ -0,0 +1,1924 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::body::{Body, BoxBody, Bytes, HttpBody};
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, FromRef, MatchedPath, State};
+use axum::http::header::{
+    HeaderName, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER, SERVER, VARY,
+};
+use axum::http::{Extensions, HeaderMap, Request, Version};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put, IntoMakeService};
+use axum::Router;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use hyper::body::SizeHint;
+use hyper::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::postgres::{PgListener, PgPoolOptions};
+use sqlx::{Pool, Postgres};
+use tokio::sync::{broadcast, watch};
+use tower::ServiceBuilder;
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+use tower_http::compression::CompressionLayer;
+use tower_http::decompression::{DecompressionBody, RequestDecompressionLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
+use tower_http::timeout::{RequestBodyTimeoutLayer, TimeoutBody};
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tower_http::LatencyUnit;
+use tracing::{error, info, warn, Level};
+use utoipa::{OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::config::{config_var, env_or, Config, ConfigError, RepositoryKind};
+use crate::domain::{Person, DATE_LOCALE, FIELD_NAMES, LENIENT_STACKS, OMIT_EMPTY_STACKS};
+use crate::error::{AppError, ERROR_DETAIL, ERROR_TYPE_BASE_URL};
+use crate::handlers::{
+    accepts_csv, advanced_search, count_by_birth_year, count_person, count_person_stream,
+    create_people_batch, create_person, dashboard_stats, db_info, delete_person,
+    duplicate_candidates, export_people, get_by_nicknames, get_person, get_stack_meta,
+    handle_layer_error, health, import_people, incomplete_people, list_people, patch_person,
+    people_in_id_range, person_events, purge_deleted, put_stack_meta, random_person, ready,
+    reindex_status, related_stacks, remove_stacks, replace_person, revalidate_people, root,
+    search_person, self_test, set_photo, similar_person, start_reindex, stream_export,
+    suspicious_people, timeline, trending_stacks, ApiDoc, API_VERSION,
+};
+use crate::repository::{
+    CachedPersonRepository, InMemoryPersonRepository, InstrumentedRepository, PersonRepository,
+    PostgresPersonRepository, RedisCache, WriteBehind,
+};
+
+/// Repository the routes read and write people through.
+pub type DynPersonRepo = Arc<dyn PersonRepository + Send + Sync>;
+
+/// Request body as seen by the routes, after the timeout and decompression layers.
+pub(crate) type RequestBody = DecompressionBody<TimeoutBody<Body>>;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub(crate) repo: DynPersonRepo,
+    pub(crate) config: Arc<Config>,
+    pub(crate) in_flight: InFlightRequests,
+    pub(crate) shutdown: ShutdownNotice,
+    pub(crate) reindex: ReindexJob,
+    pub(crate) metrics: Metrics,
+    pub(crate) events: PersonEvents,
+}
+
+impl AppState {
+    pub(crate) fn new(repo: DynPersonRepo, config: Config, pool: Option<Pool<Postgres>>) -> Self {
+        AppState {
+            repo,
+            in_flight: InFlightRequests::default(),
+            shutdown: ShutdownNotice::default(),
+            reindex: ReindexJob::default(),
+            metrics: Metrics::new(pool.clone()),
+            events: PersonEvents::new(pool.filter(|_| config.person_events_notify)),
+            config: Arc::new(config),
+        }
+    }
+
+    /// State keeping people in the process, as with `PERSON_REPOSITORY=memory`,
+    /// for serving [`app`] without a database.
+    pub fn in_memory(config: Config) -> Self {
+        let repo = cached(
+            InstrumentedRepository::new(InMemoryPersonRepository::new(&config)),
+            &config,
+            None,
+        );
+        AppState::new(repo, config, None)
+    }
+}
+
+impl FromRef<AppState> for Metrics {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
+
+impl FromRef<AppState> for PersonEvents {
+    fn from_ref(state: &AppState) -> Self {
+        state.events.clone()
+    }
+}
+
+/// Events a subscriber can fall behind by before it misses some.
+pub(crate) const PERSON_EVENTS_BUFFER: usize = 1024;
+
+/// Postgres channel the created people are sent through with [`Config::person_events_notify`].
+pub(crate) const PERSON_EVENTS_CHANNEL: &str = "person_created";
+
+/// Created people, as the JSON of their responses, fanned out to the `GET /pessoas/events`
+/// subscribers. With a `notify` pool they go through Postgres `NOTIFY` instead, so every
+/// instance listening with [`PersonEvents::listen`] sees the people created by the others.
+#[derive(Clone)]
+pub(crate) struct PersonEvents {
+    pub(crate) sender: broadcast::Sender<Arc<str>>,
+    pub(crate) notify: Option<Pool<Postgres>>,
+}
+
+impl PersonEvents {
+    pub(crate) fn new(notify: Option<Pool<Postgres>>) -> Self {
+        let (sender, _) = broadcast::channel(PERSON_EVENTS_BUFFER);
+        PersonEvents { sender, notify }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Arc<str>> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) async fn publish(&self, person: &Person) {
+        let json = match serde_json::to_string(person) {
+            Ok(json) => json,
+            Err(err) => {
+                error!(error = %err, "failed to serialize person event");
+                return;
+            }
+        };
+        let Some(pool) = &self.notify else {
+            // no subscribers is not an error
+            let _ = self.sender.send(json.into());
+            return;
+        };
+        let result = sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(PERSON_EVENTS_CHANNEL)
+            .bind(json)
+            .execute(pool)
+            .await;
+        if let Err(err) = result {
+            warn!(error = %err, person_id = person.id, "failed to notify person event");
+        }
+    }
+
+    /// Forwards the people every instance notifies to the local subscribers,
+    /// reconnecting when the listening connection drops.
+    pub(crate) async fn listen(self, pool: Pool<Postgres>) {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!(error = %err, "failed to listen for person events");
+                return;
+            }
+        };
+        if let Err(err) = listener.listen(PERSON_EVENTS_CHANNEL).await {
+            error!(error = %err, "failed to listen for person events");
+            return;
+        }
+        loop {
+            match listener.recv().await {
+                Ok(notification) => {
+                    let _ = self.sender.send(notification.payload().into());
+                }
+                // shutting down
+                Err(_) if pool.is_closed() => return,
+                Err(err) => {
+                    warn!(error = %err, "person events listener failed, reconnecting");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+}
+
+/// Upper bounds in seconds of the request latency histogram buckets.
+pub(crate) const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Request counts and latencies by route, method and status, served by `GET /metrics`
+/// in the Prometheus text format along with the connection pool gauges.
+#[derive(Clone)]
+pub(crate) struct Metrics {
+    /// Ordered so the exposition is stable between scrapes.
+    pub(crate) requests: Arc<Mutex<BTreeMap<RequestLabels, LatencyHistogram>>>,
+    /// `None` with `PERSON_REPOSITORY=memory`.
+    pub(crate) pool: Option<Pool<Postgres>>,
+}
+
+/// The route is the matched pattern (`/pessoas/:id`), so ids don't make a series each.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct RequestLabels {
+    pub(crate) route: String,
+    pub(crate) method: String,
+    pub(crate) status: u16,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct LatencyHistogram {
+    /// Not cumulative, one count per [`LATENCY_BUCKETS`] bound plus the overflow.
+    pub(crate) buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    pub(crate) sum: f64,
+    pub(crate) count: u64,
+}
+
+impl Metrics {
+    pub(crate) fn new(pool: Option<Pool<Postgres>>) -> Self {
+        Metrics {
+            requests: Arc::default(),
+            pool,
+        }
+    }
+
+    pub(crate) fn record(&self, labels: RequestLabels, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        let mut requests = self.requests.lock().unwrap_or_else(|err| err.into_inner());
+        let histogram = requests.entry(labels).or_default();
+        histogram.buckets[bucket] += 1;
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    pub(crate) fn render(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let requests = self.requests.lock().unwrap_or_else(|err| err.into_inner());
+        out += "# HELP http_requests_total Requests handled, by route, method and status.\n";
+        out += "# TYPE http_requests_total counter\n";
+        for (labels, histogram) in requests.iter() {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{{}}} {}",
+                labels.render(),
+                histogram.count
+            );
+        }
+        out += "# HELP http_request_duration_seconds Time taken to answer the requests.\n";
+        out += "# TYPE http_request_duration_seconds histogram\n";
+        for (labels, histogram) in requests.iter() {
+            let labels = labels.render();
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{labels}}} {}",
+                histogram.count
+            );
+        }
+        if let Some(pool) = &self.pool {
+            let idle = pool.num_idle() as u32;
+            out += "# HELP db_pool_connections Open database connections, by state.\n";
+            out += "# TYPE db_pool_connections gauge\n";
+            let _ = writeln!(out, "db_pool_connections{{state=\"idle\"}} {idle}");
+            let _ = writeln!(
+                out,
+                "db_pool_connections{{state=\"active\"}} {}",
+                pool.size().saturating_sub(idle)
+            );
+        }
+        out
+    }
+}
+
+impl RequestLabels {
+    pub(crate) fn render(&self) -> String {
+        format!(
+            r#"route="{}",method="{}",status="{}""#,
+            self.route, self.method, self.status
+        )
+    }
+}
+
+pub(crate) async fn record_metrics<B>(
+    State(metrics): State<Metrics>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    // requests no route matched are lumped together, their paths are unbounded
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_owned();
+    let method = req.method().to_string();
+    let started = Instant::now();
+    let res = next.run(req).await;
+    let labels = RequestLabels {
+        route,
+        method,
+        status: res.status().as_u16(),
+    };
+    metrics.record(labels, started.elapsed());
+    res
+}
+
+/// Request counts and latencies in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "The metrics", content_type = "text/plain", body = String),
+    ),
+)]
+pub(crate) async fn metrics(State(metrics): State<Metrics>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+impl FromRef<AppState> for ReindexJob {
+    fn from_ref(state: &AppState) -> Self {
+        state.reindex.clone()
+    }
+}
+
+/// The last reindex started by `POST /admin/reindex`, at most one runs at a time.
+#[derive(Clone, Default)]
+pub(crate) struct ReindexJob(pub(crate) Arc<Mutex<ReindexStatus>>);
+
+impl ReindexJob {
+    pub(crate) fn status(&self) -> ReindexStatus {
+        self.0.lock().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    pub(crate) fn update(&self, f: impl FnOnce(&mut ReindexStatus)) {
+        f(&mut self.0.lock().unwrap_or_else(|err| err.into_inner()));
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub(crate) struct ReindexStatus {
+    pub(crate) state: ReindexState,
+    pub(crate) started_at: Option<DateTime<Utc>>,
+    pub(crate) finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ReindexState {
+    /// Never started since the process did.
+    #[default]
+    Idle,
+    Running,
+    Succeeded,
+    /// The error is in the logs.
+    Failed,
+}
+
+impl FromRef<AppState> for InFlightRequests {
+    fn from_ref(state: &AppState) -> Self {
+        state.in_flight.clone()
+    }
+}
+
+impl FromRef<AppState> for ShutdownNotice {
+    fn from_ref(state: &AppState) -> Self {
+        state.shutdown.clone()
+    }
+}
+
+impl FromRef<AppState> for DynPersonRepo {
+    fn from_ref(state: &AppState) -> Self {
+        state.repo.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+/// Number of requests currently being handled, including the responses still streaming,
+/// reported while draining on shutdown.
+#[derive(Clone, Default)]
+pub(crate) struct InFlightRequests(pub(crate) Arc<AtomicUsize>);
+
+impl InFlightRequests {
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn track(&self) -> InFlightGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.0.clone())
+    }
+}
+
+/// Decrements the counter when dropped, so cancelled requests are accounted for too.
+pub(crate) struct InFlightGuard(pub(crate) Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A response body holding its request's [`InFlightGuard`], so a request is in flight
+/// until its body is sent or dropped rather than until its head is.
+pub(crate) struct InFlightBody {
+    pub(crate) inner: BoxBody,
+    pub(crate) _guard: InFlightGuard,
+}
+
+impl HttpBody for InFlightBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+pub(crate) async fn track_in_flight<B>(
+    State(in_flight): State<InFlightRequests>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let guard = in_flight.track();
+    next.run(req).await.map(|inner| {
+        axum::body::boxed(InFlightBody {
+            inner,
+            _guard: guard,
+        })
+    })
+}
+
+/// Fired once the server starts draining, so the responses that would otherwise stream
+/// forever, like `GET /pessoas/events`, end and let it finish.
+#[derive(Clone)]
+pub(crate) struct ShutdownNotice(pub(crate) Arc<watch::Sender<bool>>);
+
+impl Default for ShutdownNotice {
+    fn default() -> Self {
+        ShutdownNotice(Arc::new(watch::channel(false).0))
+    }
+}
+
+impl ShutdownNotice {
+    pub(crate) fn fire(&self) {
+        self.0.send_replace(true);
+    }
+
+    /// Resolves once [`ShutdownNotice::fire`] was called, right away if it already was.
+    pub(crate) fn fired(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut fired = self.0.subscribe();
+        async move {
+            let _ = fired.wait_for(|fired| *fired).await;
+        }
+    }
+}
+
+/// Hides the `Server` header, or replaces it with [`Config::server_header`] when set.
+pub(crate) async fn set_server_header<B>(
+    State(config): State<Arc<Config>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut res = next.run(req).await;
+    match &config.server_header {
+        Some(value) => {
+            res.headers_mut().insert(SERVER, value.clone());
+        }
+        None => {
+            res.headers_mut().remove(SERVER);
+        }
+    }
+    res
+}
+
+/// Routes that do not answer in JSON, exempt from [`Config::require_json_accept`].
+pub(crate) const NON_JSON_PATHS: &[&str] = &[
+    "/contagem-pessoas",
+    "/contagem-pessoas/stream",
+    "/pessoas/events",
+    "/health",
+    "/ready",
+    "/metrics",
+    "/docs",
+    "/admin/export.json.gz",
+    "/pessoas/export",
+];
+
+/// Whether the `Accept` header, if any, allows a JSON response.
+pub(crate) fn accepts_json(headers: &HeaderMap) -> bool {
+    !headers.contains_key(ACCEPT)
+        || headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|accept| accept.split(','))
+            .map(|range| range.split(';').next().unwrap_or_default().trim())
+            .any(|range| {
+                matches!(range, "application/json" | "application/*" | "*/*")
+                    || range.ends_with("+json")
+            })
+}
+
+pub(crate) async fn require_json_accept<B>(
+    State(config): State<Arc<Config>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    let exempt = NON_JSON_PATHS
+        .iter()
+        .any(|path| req.uri().path().starts_with(path))
+        // CSV search results
+        || accepts_csv(req.headers());
+    if config.require_json_accept && !exempt && !accepts_json(req.headers()) {
+        return Err(AppError::NotAcceptable {
+            supported: &["application/json"],
+        });
+    }
+    Ok(next.run(req).await)
+}
+
+pub(crate) async fn require_admin<B>(
+    State(config): State<Arc<Config>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    match is_admin(&config, req.headers()) {
+        true => Ok(next.run(req).await),
+        false => Err(AppError::Unauthorized),
+    }
+}
+
+/// Whether the request carries the [`Config::admin_token`] as a bearer token.
+pub(crate) fn is_admin(config: &Config, headers: &HeaderMap) -> bool {
+    matches!(
+        (&config.admin_token, bearer_token(headers)),
+        (Some(expected), Some(token)) if expected == token
+    )
+}
+
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+pub(crate) static API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+pub(crate) static RATE_LIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+pub(crate) static RATE_LIMIT_REMAINING: HeaderName =
+    HeaderName::from_static("x-ratelimit-remaining");
+pub(crate) static RATE_LIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+/// Clients tracked by a [`RateLimiter`], past which those whose bucket refilled are forgotten.
+pub(crate) const RATE_LIMIT_MAX_CLIENTS: usize = 100_000;
+
+/// A token bucket per client, holding up to a second worth of requests and refilled
+/// continuously at the configured rate.
+///
+/// Clients are told apart by their `X-Api-Key` when it is one of the [`WriteAuth::ApiKeys`],
+/// so made up keys can't buy fresh buckets, else by their IP: the first hop of
+/// `X-Forwarded-For` with [`Config::trust_forwarded_for`], the peer address otherwise.
+/// Behind a unix socket without a trusted proxy header every client shares one bucket.
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    pub(crate) per_sec: NonZeroU32,
+    pub(crate) trust_forwarded_for: bool,
+    pub(crate) api_keys: Arc<[String]>,
+    pub(crate) buckets: Arc<Mutex<HashMap<String, (f64, Instant)>>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(per_sec: NonZeroU32, config: &Config) -> Self {
+        let api_keys = match &config.write_auth {
+            WriteAuth::ApiKeys(keys) => keys.as_slice().into(),
+            WriteAuth::None | WriteAuth::Jwt(_) => Arc::default(),
+        };
+        RateLimiter {
+            per_sec,
+            trust_forwarded_for: config.trust_forwarded_for,
+            api_keys,
+            buckets: Arc::default(),
+        }
+    }
+
+    pub(crate) fn client<B>(&self, req: &Request<B>) -> String {
+        let headers = req.headers();
+        let api_key = headers
+            .get(&API_KEY)
+            .and_then(|v| v.to_str().ok())
+            .filter(|key| self.api_keys.iter().any(|k| k == key));
+        if let Some(key) = api_key {
+            return format!("key:{key}");
+        }
+        let forwarded = headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(str::trim)
+            .filter(|_| self.trust_forwarded_for);
+        match (forwarded, req.extensions().get::<ConnectInfo<SocketAddr>>()) {
+            (Some(ip), _) => format!("ip:{ip}"),
+            (None, Some(ConnectInfo(addr))) => format!("ip:{}", addr.ip()),
+            (None, None) => "unknown".to_owned(),
+        }
+    }
+
+    /// Takes a token from `client`, returning the tokens left or how long until the next one.
+    pub(crate) fn acquire(&self, client: String) -> Result<u32, Duration> {
+        let rate = f64::from(self.per_sec.get());
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        if buckets.len() >= RATE_LIMIT_MAX_CLIENTS {
+            buckets.retain(|_, (tokens, at)| *tokens + at.elapsed().as_secs_f64() * rate < rate);
+        }
+        let (tokens, at) = buckets.entry(client).or_insert((rate, now));
+        *tokens = (*tokens + now.duration_since(*at).as_secs_f64() * rate).min(rate);
+        *at = now;
+        if *tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - *tokens) / rate));
+        }
+        *tokens -= 1.0;
+        Ok(*tokens as u32)
+    }
+}
+
+/// Answers 429 once the client's bucket is empty, every response tells it the limit and
+/// what is left with `X-RateLimit-*`.
+pub(crate) async fn enforce_rate_limit<B>(
+    State(limiter): State<RateLimiter>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let client = limiter.client(&req);
+    let (mut res, remaining, reset, limited) = match limiter.acquire(client) {
+        Ok(remaining) => (next.run(req).await, remaining, Duration::ZERO, false),
+        Err(wait) => (AppError::RateLimited.into_response(), 0, wait, true),
+    };
+    // whole seconds, rounded up so retrying then always finds a token
+    let reset_secs = reset.as_secs() + u64::from(reset.subsec_nanos() > 0);
+    let headers = res.headers_mut();
+    headers.insert(RATE_LIMIT_LIMIT.clone(), limiter.per_sec.get().into());
+    headers.insert(RATE_LIMIT_REMAINING.clone(), remaining.into());
+    headers.insert(RATE_LIMIT_RESET.clone(), reset_secs.into());
+    if limited {
+        headers.insert(RETRY_AFTER, reset_secs.into());
+    }
+    res
+}
+
+/// How the routes that change people authenticate their callers.
+#[derive(Debug, Clone, Default)]
+pub(crate) enum WriteAuth {
+    /// Anyone can write, as the Rinha contract expects.
+    #[default]
+    None,
+    /// An `X-Api-Key` header holding one of the keys.
+    ApiKeys(Vec<String>),
+    /// An HS256 JWT signed with this secret as `Authorization: Bearer`,
+    /// within its `nbf` and `exp` when it has them.
+    Jwt(Vec<u8>),
+}
+
+/// `WRITE_AUTH` is `none`, `api_key` with the comma separated `API_KEYS` or `jwt` with `JWT_SECRET`.
+pub(crate) fn parse_write_auth() -> Result<WriteAuth, ConfigError> {
+    let mode = env_or("WRITE_AUTH", "none".to_owned())?;
+    let required = |key: &'static str| match config_var(key) {
+        Ok(value) if !value.trim().is_empty() => Ok(value),
+        value => Err(ConfigError {
+            key,
+            value: value.unwrap_or_default(),
+        }),
+    };
+    match mode.as_str() {
+        "none" => Ok(WriteAuth::None),
+        "api_key" => Ok(WriteAuth::ApiKeys(
+            required("API_KEYS")?
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        )),
+        "jwt" => Ok(WriteAuth::Jwt(required("JWT_SECRET")?.into_bytes())),
+        _ => Err(ConfigError {
+            key: "WRITE_AUTH",
+            value: mode,
+        }),
+    }
+}
+
+pub(crate) async fn require_write_auth<B>(
+    State(config): State<Arc<Config>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    let headers = req.headers();
+    let authorized = match &config.write_auth {
+        WriteAuth::None => true,
+        WriteAuth::ApiKeys(keys) => headers
+            .get(&API_KEY)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|key| keys.iter().any(|k| k == key)),
+        WriteAuth::Jwt(secret) => bearer_token(headers)
+            .is_some_and(|token| verify_jwt(token, secret, Utc::now().timestamp())),
+    };
+    match authorized {
+        true => Ok(next.run(req).await),
+        false => Err(AppError::Unauthorized),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct JwtHeader {
+    pub(crate) alg: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct JwtClaims {
+    pub(crate) exp: Option<i64>,
+    pub(crate) nbf: Option<i64>,
+}
+
+/// Checks an HS256 `token` and its time claims against `now`, in seconds since the epoch.
+pub(crate) fn verify_jwt(token: &str, secret: &[u8], now: i64) -> bool {
+    let mut parts = token.split('.');
+    let (Some(header), Some(claims), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(header.as_bytes());
+    mac.update(b".");
+    mac.update(claims.as_bytes());
+    if mac.verify_slice(&signature).is_err() {
+        return false;
+    }
+    // the signature only vouches for the algorithm the header names
+    if decode_jwt_part::<JwtHeader>(header).is_none_or(|header| header.alg != "HS256") {
+        return false;
+    }
+    decode_jwt_part::<JwtClaims>(claims).is_some_and(|claims| {
+        claims.exp.is_none_or(|exp| now < exp) && claims.nbf.is_none_or(|nbf| nbf <= now)
+    })
+}
+
+pub(crate) fn decode_jwt_part<T: DeserializeOwned>(part: &str) -> Option<T> {
+    let json = URL_SAFE_NO_PAD.decode(part).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/// Puts `routes` behind a [`RateLimiter`] of `per_sec` requests when set.
+pub(crate) fn rate_limited(
+    routes: Router<AppState, RequestBody>,
+    per_sec: Option<NonZeroU32>,
+    config: &Config,
+) -> Router<AppState, RequestBody> {
+    match per_sec {
+        Some(per_sec) => routes.route_layer(middleware::from_fn_with_state(
+            RateLimiter::new(per_sec, config),
+            enforce_rate_limit,
+        )),
+        None => routes,
+    }
+}
+
+/// Responses gzipped for clients whose `Accept-Encoding` allows it: those of more than a few bytes,
+/// but neither event streams, which would be held back, nor the already gzipped export.
+pub(crate) fn compressible(enabled: bool) -> impl Predicate {
+    DefaultPredicate::new()
+        .and(NotForContentType::const_new("text/event-stream"))
+        .and(NotForContentType::const_new("application/gzip"))
+        .and(move |_: StatusCode, _: Version, _: &HeaderMap, _: &Extensions| enabled)
+}
+
+/// Builds the router over `repo`, e.g. an [`InMemoryPersonRepository`] to drive every
+/// handler with `tower::ServiceExt::oneshot` from an integration test.
+pub fn build_router(repo: DynPersonRepo, config: Config) -> Router {
+    app(AppState::new(repo, config, None))
+}
+
+/// A process wide setting already installed with another value.
+#[derive(Debug)]
+pub struct SettingsConflict {
+    pub(crate) key: &'static str,
+}
+
+impl Display for SettingsConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is already set to another value in this process",
+            self.key
+        )
+    }
+}
+
+impl Error for SettingsConflict {}
+
+/// Installs the formatting settings of `config` that serde reaches through statics rather than
+/// the state. [`run`] and [`app`] call it, and installing the same values again is a no-op, but
+/// they can't change once the process has them.
+pub fn install_settings(config: &Config) -> Result<(), SettingsConflict> {
+    fn install<T: PartialEq>(
+        cell: &OnceLock<T>,
+        value: T,
+        key: &'static str,
+    ) -> Result<(), SettingsConflict> {
+        match cell.set(value) {
+            Err(value) if cell.get() != Some(&value) => Err(SettingsConflict { key }),
+            _ => Ok(()),
+        }
+    }
+
+    install(&DATE_LOCALE, config.date_locale, "DATE_LOCALE")?;
+    install(&LENIENT_STACKS, config.lenient_stacks, "LENIENT_STACKS")?;
+    install(&FIELD_NAMES, config.field_names, "FIELD_NAMES")?;
+    install(
+        &OMIT_EMPTY_STACKS,
+        config.omit_empty_stacks,
+        "OMIT_EMPTY_STACKS",
+    )?;
+    install(
+        &ERROR_TYPE_BASE_URL,
+        config.error_type_base_url.clone(),
+        "ERROR_TYPE_BASE_URL",
+    )?;
+    install(&ERROR_DETAIL, config.error_detail, "ERROR_DETAIL")
+}
+
+/// Builds the API routes and middleware around `state`, installing its settings first.
+pub fn app(state: AppState) -> Router {
+    let config = state.config.clone();
+    if let Err(err) = install_settings(&config) {
+        warn!("{err}, keeping the value installed first");
+    }
+    let max_body_bytes = config.max_body_bytes;
+    let compress_responses = config.compress_responses;
+    let body_read_timeout = config.body_read_timeout;
+
+    let mut admin = Router::new()
+        .route("/pessoas", get(list_people))
+        .route("/pessoas/range", get(people_in_id_range))
+        .route("/pessoas/suspeitas", get(suspicious_people))
+        .route("/pessoas/duplicados", get(duplicate_candidates))
+        .route("/pessoas/incompletas", get(incomplete_people))
+        .route("/pessoas/revalidate", post(revalidate_people))
+        .route("/pessoas/purge", post(purge_deleted))
+        .route("/export.json.gz", get(export_people))
+        .route("/reindex", get(reindex_status).post(start_reindex));
+    // writes to the real database, so production doesn't even route it
+    if !state.config.production {
+        admin = admin.route("/selftest", post(self_test));
+    }
+
+    // routes see the decompressed body, so the body limit also guards against zip bombs
+    Router::<_, RequestBody>::new()
+        .route("/", get(root))
+        .route("/pessoas/aleatorio", get(random_person))
+        .route("/pessoas/timeline", get(timeline))
+        .route("/pessoas/:id", get(get_person))
+        .route("/pessoas/events", get(person_events))
+        .route("/pessoas/:id/similares", get(similar_person))
+        .route("/pessoas/stats/por-ano", get(count_by_birth_year))
+        .route("/pessoas/by-nicknames", post(get_by_nicknames))
+        .merge(rate_limited(
+            Router::new()
+                .route("/pessoas", get(search_person))
+                .route("/pessoas/search-advanced", get(advanced_search)),
+            config.rate_limit_search,
+            &config,
+        ))
+        // everything changing people, guarded by WRITE_AUTH
+        .merge(rate_limited(
+            Router::new()
+                .route("/pessoas", post(create_person))
+                .route("/pessoas/batch", post(create_people_batch))
+                .route("/pessoas/import", post(import_people))
+                .route(
+                    "/pessoas/:id",
+                    put(replace_person)
+                        .patch(patch_person)
+                        .delete(delete_person),
+                )
+                .route("/pessoas/:id/foto", put(set_photo))
+                .route("/pessoas/:id/stacks/remove", post(remove_stacks))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    require_write_auth,
+                )),
+            config.rate_limit_write,
+            &config,
+        ))
+        .route("/stacks/trending", get(trending_stacks))
+        .route("/stacks/:name/relacionadas", get(related_stacks))
+        .route("/meta/stacks/:name", get(get_stack_meta))
+        .merge(
+            Router::new()
+                .route("/pessoas/export", get(stream_export))
+                .route("/meta/stacks/:name", put(put_stack_meta))
+                .route_layer(middleware::from_fn_with_state(state.clone(), require_admin)),
+        )
+        .route("/stats", get(dashboard_stats))
+        .route("/contagem-pessoas", get(count_person))
+        .route("/contagem-pessoas/stream", get(count_person_stream))
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        // the names Kubernetes probes usually use
+        .route("/healthz", get(health))
+        .route("/readyz", get(ready))
+        .route("/metrics", get(metrics))
+        .merge(SwaggerUi::new("/docs").url("/openapi.json", ApiDoc::openapi()))
+        .nest(
+            "/admin",
+            admin.route_layer(middleware::from_fn_with_state(state.clone(), require_admin)),
+        )
+        .nest(
+            "/info",
+            Router::new()
+                .route("/db", get(db_info))
+                .route_layer(middleware::from_fn_with_state(state.clone(), require_admin)),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_json_accept,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            track_in_flight,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            record_metrics,
+        ))
+        // caches must not hand a gzipped response to a client that can't read it
+        .layer(SetResponseHeaderLayer::appending(
+            VARY,
+            move |_: &Response| {
+                compress_responses.then(|| HeaderValue::from_static("accept-encoding"))
+            },
+        ))
+        .layer(CompressionLayer::new().compress_when(compressible(compress_responses)))
+        .layer(DefaultBodyLimit::max(max_body_bytes))
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_layer_error))
+                .layer(RequestDecompressionLayer::new()),
+        )
+        .layer(RequestBodyTimeoutLayer::new(body_read_timeout))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+                .on_response(
+                    DefaultOnResponse::new()
+                        .level(Level::INFO)
+                        .latency_unit(LatencyUnit::Millis),
+                ),
+        )
+        .layer(SetResponseHeaderLayer::overriding(
+            API_VERSION.clone(),
+            HeaderValue::from_static(env!("CARGO_PKG_VERSION")),
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            set_server_header,
+        ))
+        .with_state(state)
+}
+
+/// Connects the configured repository and serves the API until a shutdown signal,
+/// then drains the in-flight requests and the write-behind queue.
+pub async fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    install_settings(&config)?;
+
+    let redis = match (&config.redis_url, config.person_cache_ttl) {
+        (Some(url), Some(ttl)) => {
+            info!("connecting to Redis");
+            Some(RedisCache::connect(url, ttl).await?)
+        }
+        _ => None,
+    };
+    let mut writer = None;
+    let (repo, pool): (DynPersonRepo, _) = match config.repository {
+        RepositoryKind::Postgres => {
+            info!("connecting to database");
+            let pool = pool_options(&config).connect(&config.database_url).await?;
+            if config.run_migrations {
+                info!("running migrations");
+                sqlx::migrate!().run(&pool).await?;
+            }
+            let mut repo = PostgresPersonRepository::new(pool.clone(), &config);
+            if let Some(flush_interval) = config.write_behind {
+                info!("writing created people in the background");
+                let (write_behind, task) =
+                    WriteBehind::spawn(pool.clone(), config.stacks_storage, flush_interval);
+                repo = repo.with_write_behind(write_behind);
+                writer = Some(task);
+            }
+            (
+                cached(InstrumentedRepository::new(repo), &config, redis),
+                Some(pool),
+            )
+        }
+        RepositoryKind::Memory => {
+            warn!("keeping people in memory, they are lost on restart");
+            let repo = InMemoryPersonRepository::new(&config);
+            (
+                cached(InstrumentedRepository::new(repo), &config, redis),
+                None,
+            )
+        }
+    };
+    let uds_path = config.uds_path.clone();
+    let bind_addr = config.bind_addr;
+    let shutdown_grace = config.shutdown_grace;
+    let http2 = config.http2;
+    let state = AppState::new(repo, config, pool.clone());
+    let in_flight = state.in_flight.clone();
+    let notice = state.shutdown.clone();
+    if let Some(pool) = state.events.notify.clone() {
+        info!("sharing person events through Postgres");
+        tokio::spawn(state.events.clone().listen(pool));
+    }
+
+    info!("starting server");
+
+    let app = app(state);
+
+    if http2 {
+        info!("accepting HTTP/2 with prior knowledge");
+    }
+    let shutdown = shutdown_signal(in_flight.clone(), notice.clone());
+    let served = async {
+        match uds_path {
+            Some(path) => serve_uds(path, app.into_make_service(), shutdown, http2).await,
+            // the peer address identifies the rate limited clients
+            None => Ok(axum::Server::bind(&bind_addr)
+                .http1_only(!http2)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown)
+                .await?),
+        }
+    };
+    let drained = match drain_within(served, &notice, shutdown_grace).await {
+        Some(served) => {
+            served?;
+            true
+        }
+        None => {
+            warn!(
+                remaining = in_flight.count(),
+                "drain timeout elapsed, abandoning the requests left"
+            );
+            false
+        }
+    };
+
+    // the router held the last handles of the writer, which now writes what is still queued
+    if let Some(writer) = writer {
+        info!("writing the queued people");
+        if tokio::time::timeout(shutdown_grace, writer).await.is_err() {
+            warn!("shutdown grace period elapsed, some queued people were not written");
+        }
+    }
+    if let Some(pool) = pool {
+        info!("closing database connections");
+        if drained {
+            pool.close().await;
+        } else {
+            // waits for connections in use to be released, which the abandoned requests may never do
+            let _ = tokio::time::timeout(Duration::from_secs(1), pool.close()).await;
+        }
+    }
+    if !drained {
+        return Err("requests were still running after the shutdown grace period".into());
+    }
+    info!("shutdown complete");
+
+    Ok(())
+}
+
+/// Runs `served` to completion, or only until `grace` has elapsed since `notice` fired,
+/// however many requests are left then.
+pub(crate) async fn drain_within<F: Future>(
+    served: F,
+    notice: &ShutdownNotice,
+    grace: Duration,
+) -> Option<F::Output> {
+    let deadline = async {
+        notice.fired().await;
+        tokio::time::sleep(grace).await;
+    };
+    tokio::select! {
+        served = served => Some(served),
+        _ = deadline => None,
+    }
+}
+
+pub(crate) fn pool_options(config: &Config) -> PgPoolOptions {
+    PgPoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .min_connections(config.db_min_connections)
+        .acquire_timeout(config.db_acquire_timeout)
+        .idle_timeout(config.db_idle_timeout)
+        .max_lifetime(config.db_max_lifetime)
+        .test_before_acquire(config.db_test_before_acquire)
+}
+
+/// Puts `repo` behind a [`CachedPersonRepository`] when [`Config::person_cache_ttl`] is set,
+/// so only the calls reaching the storage are instrumented.
+pub(crate) fn cached<R>(repo: R, config: &Config, redis: Option<RedisCache>) -> DynPersonRepo
+where
+    R: PersonRepository + Send + Sync + 'static,
+{
+    match (config.person_cache_ttl, redis) {
+        (Some(_), Some(redis)) => {
+            info!(
+                ttl_secs = redis.ttl.as_secs(),
+                "caching people lookups in Redis"
+            );
+            Arc::new(CachedPersonRepository::with_cache(repo, redis))
+        }
+        (Some(ttl), None) => {
+            info!(
+                ttl_secs = ttl.as_secs(),
+                "caching people lookups in the process"
+            );
+            Arc::new(CachedPersonRepository::new(repo, ttl))
+        }
+        (None, _) => Arc::new(repo),
+    }
+}
+
+/// Resolves on Ctrl-C or, on unix, `SIGTERM` (what `docker stop` and Kubernetes send),
+/// after which the server stops accepting connections and drains. Fires `notice` and
+/// logs the requests left every second until they are done.
+pub(crate) async fn shutdown_signal(in_flight: InFlightRequests, notice: ShutdownNotice) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for shutdown signal");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for shutdown signal")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("received Ctrl-C, shutting down"),
+        _ = terminate => info!("received SIGTERM, shutting down"),
+    }
+
+    notice.fire();
+    tokio::spawn(async move {
+        loop {
+            let remaining = in_flight.count();
+            if remaining == 0 {
+                return;
+            }
+            info!(remaining, "draining in-flight requests");
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+}
+
+#[cfg(unix)]
+pub(crate) async fn serve_uds(
+    path: PathBuf,
+    app: IntoMakeService<Router>,
+    shutdown: impl Future<Output = ()>,
+    http2: bool,
+) -> Result<(), Box<dyn Error>> {
+    use tokio::net::UnixListener;
+    use tokio_stream::wrappers::UnixListenerStream;
+
+    // a previous run that was killed may have left the socket file behind
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+    let incoming = UnixListenerStream::new(UnixListener::bind(&path)?);
+
+    let served = axum::Server::builder(hyper::server::accept::from_stream(incoming))
+        .http1_only(!http2)
+        .serve(app)
+        .with_graceful_shutdown(shutdown)
+        .await;
+
+    std::fs::remove_file(&path)?;
+    Ok(served?)
+}
+
+#[cfg(not(unix))]
+pub(crate) async fn serve_uds(
+    _path: PathBuf,
+    _app: IntoMakeService<Router>,
+    _shutdown: impl Future<Output = ()>,
+    _http2: bool,
+) -> Result<(), Box<dyn Error>> {
+    Err("APP_UDS_PATH is only supported on unix".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::{CONTENT_ENCODING, IF_MATCH, LOCATION};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::domain::StackMeta;
+    use crate::error::RepositoryError;
+    use crate::handlers::PUBLIC_ENDPOINTS;
+    use crate::test_support::{
+        body_bytes, body_json, config, get, post_json, send, with_env, FailingRepository,
+    };
+
+    #[test]
+    fn pool_options_test_connections_before_acquire_only_when_enabled() {
+        let config = with_env(&[], Config::from_env).unwrap();
+        assert!(!pool_options(&config).get_test_before_acquire());
+
+        let config = with_env(&[("DB_TEST_BEFORE_ACQUIRE", "1")], Config::from_env).unwrap();
+        assert!(pool_options(&config).get_test_before_acquire());
+    }
+
+    #[test]
+    fn pool_options_reclaim_connections_idle_for_the_configured_time() {
+        let config = with_env(&[], Config::from_env).unwrap();
+        assert_eq!(config.db_idle_timeout, Duration::from_secs(600));
+        assert_eq!(
+            pool_options(&config).get_idle_timeout(),
+            Some(Duration::from_secs(600))
+        );
+
+        let config = with_env(&[("DB_IDLE_TIMEOUT_SECS", "30")], Config::from_env).unwrap();
+        assert_eq!(
+            pool_options(&config).get_idle_timeout(),
+            Some(Duration::from_secs(30))
+        );
+
+        assert!(with_env(&[("DB_IDLE_TIMEOUT_SECS", "soon")], Config::from_env).is_err());
+    }
+
+    #[test]
+    fn pool_options_recycle_connections_after_the_configured_lifetime() {
+        let config = with_env(&[], Config::from_env).unwrap();
+        assert_eq!(
+            pool_options(&config).get_max_lifetime(),
+            Some(Duration::from_secs(30 * 60))
+        );
+
+        let config = with_env(&[("DB_MAX_LIFETIME_SECS", "90")], Config::from_env).unwrap();
+        assert_eq!(config.db_max_lifetime, Duration::from_secs(90));
+        assert_eq!(
+            pool_options(&config).get_max_lifetime(),
+            Some(Duration::from_secs(90))
+        );
+
+        assert!(with_env(&[("DB_MAX_LIFETIME_SECS", "-1")], Config::from_env).is_err());
+    }
+
+    #[tokio::test]
+    async fn require_json_accept_rejects_only_an_accept_without_json() {
+        let mut config = config();
+        config.require_json_accept = true;
+        let state = AppState::in_memory(config);
+        let search = |accept: Option<&str>| {
+            let mut req = Request::get("/pessoas?t=rust");
+            if let Some(accept) = accept {
+                req = req.header(ACCEPT, accept);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        assert_eq!(send(&state, search(None)).await.status(), StatusCode::OK);
+        assert_eq!(
+            send(&state, search(Some("*/*"))).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&state, search(Some("application/json")))
+                .await
+                .status(),
+            StatusCode::OK
+        );
+
+        let res = send(&state, search(Some("text/html"))).await;
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(body_json(res).await["status"], 406);
+
+        let health = Request::get("/health")
+            .header(ACCEPT, "text/html")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&state, health).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn requests_are_in_flight_until_their_body_is_done() {
+        let state = AppState::in_memory(config());
+
+        let res = send(&state, get("/pessoas?t=rust")).await;
+        assert_eq!(state.in_flight.count(), 1);
+        body_bytes(res).await;
+        assert_eq!(state.in_flight.count(), 0);
+
+        let events = send(&state, get("/pessoas/events")).await;
+        assert_eq!(events.status(), StatusCode::OK);
+        assert_eq!(state.in_flight.count(), 1);
+        drop(events);
+        assert_eq!(state.in_flight.count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_gives_up_a_grace_period_after_the_shutdown_notice() {
+        let notice = ShutdownNotice::default();
+        let grace = Duration::from_secs(30);
+        assert_eq!(
+            drain_within(async { "served" }, &notice, grace).await,
+            Some("served")
+        );
+
+        let stuck = drain_within(std::future::pending::<()>(), &notice, grace);
+        tokio::pin!(stuck);
+        // the grace period only starts with the notice
+        let waiting = tokio::time::timeout(Duration::from_secs(60), &mut stuck).await;
+        assert!(waiting.is_err());
+
+        notice.fire();
+        let fired_at = tokio::time::Instant::now();
+        assert_eq!(stuck.await, None);
+        assert_eq!(fired_at.elapsed(), grace);
+    }
+
+    #[tokio::test]
+    async fn server_exits_within_the_grace_period_under_a_slow_request() {
+        let config = with_env(&[("SHUTDOWN_GRACE_SECS", "1")], Config::from_env).unwrap();
+        assert_eq!(config.shutdown_grace, Duration::from_secs(1));
+        let grace = Duration::from_millis(200);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let slow = Router::new().route(
+            "/slow",
+            axum::routing::get(|| async {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+            }),
+        );
+        let notice = ShutdownNotice::default();
+        let served = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(slow.into_make_service())
+            .with_graceful_shutdown(notice.fired());
+
+        let started = Instant::now();
+        let client = async {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            tokio::io::AsyncWriteExt::write_all(
+                &mut stream,
+                b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            )
+            .await
+            .unwrap();
+            // lets the server start on the request before shutting down
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            notice.fire();
+            std::future::pending::<()>().await;
+        };
+
+        let drained = tokio::select! {
+            drained = drain_within(served, &notice, grace) => drained,
+            _ = client => unreachable!("the client waits forever"),
+        };
+        assert!(drained.is_none(), "the slow request was abandoned");
+        let elapsed = started.elapsed();
+        assert!(elapsed >= grace, "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_requests_over_a_unix_socket() {
+        let path =
+            std::env::temp_dir().join(format!("person-api-test-{}.sock", std::process::id()));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = serve_uds(
+            path.clone(),
+            app(AppState::in_memory(config())).into_make_service(),
+            async {
+                stopped.await.ok();
+            },
+            false,
+        );
+        let client = async {
+            let mut stream = None;
+            for _ in 0..50 {
+                if let Ok(connected) = tokio::net::UnixStream::connect(&path).await {
+                    stream = Some(connected);
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            let (mut sender, conn) =
+                hyper::client::conn::handshake(stream.expect("socket is bound"))
+                    .await
+                    .unwrap();
+            tokio::spawn(conn);
+            let status = sender.send_request(get("/health")).await.unwrap().status();
+            drop(sender);
+            stop.send(()).unwrap();
+            status
+        };
+
+        let (served, status) = tokio::join!(server, client);
+        served.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(!path.exists());
+    }
+
+    /// Sends a prior knowledge HTTP/2 request to a unix socket served with `http2`.
+    async fn h2_request(http2: bool) -> Result<StatusCode, hyper::Error> {
+        let path =
+            std::env::temp_dir().join(format!("person-api-h2-{http2}-{}.sock", std::process::id()));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = serve_uds(
+            path.clone(),
+            app(AppState::in_memory(config())).into_make_service(),
+            async {
+                stopped.await.ok();
+            },
+            http2,
+        );
+        let client = async {
+            let mut stream = None;
+            for _ in 0..50 {
+                if let Ok(connected) = tokio::net::UnixStream::connect(&path).await {
+                    stream = Some(connected);
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            let sent = async {
+                let (mut sender, conn) = hyper::client::conn::Builder::new()
+                    .http2_only(true)
+                    .handshake(stream.expect("socket is bound"))
+                    .await?;
+                tokio::spawn(conn);
+                let res = sender.send_request(get("/health")).await?;
+                assert_eq!(res.version(), axum::http::Version::HTTP_2);
+                Ok(res.status())
+            }
+            .await;
+            stop.send(()).unwrap();
+            sent
+        };
+
+        let (served, status) = tokio::join!(server, client);
+        served.unwrap();
+        status
+    }
+
+    #[tokio::test]
+    async fn http2_is_served_with_prior_knowledge_only_when_enabled() {
+        assert!(with_env(&[("HTTP2", "1")], Config::from_env).unwrap().http2);
+        assert!(!config().http2);
+
+        assert_eq!(h2_request(true).await.unwrap(), StatusCode::OK);
+        assert!(h2_request(false).await.is_err());
+    }
+
+    fn gzipped_post(uri: &str, body: &[u8]) -> Request<Body> {
+        use std::io::Write;
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(body).unwrap();
+        Request::post(uri)
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_ENCODING, "gzip")
+            .body(Body::from(encoder.finish().unwrap()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn gzipped_bodies_are_read_decompressed_within_the_body_limit() {
+        let mut config = config();
+        config.max_body_bytes = 4096;
+        let state = AppState::in_memory(config);
+        let batch = serde_json::json!([
+            { "apelido": "ana", "nome": "Ana Barros", "nascimento": "1985-09-23" },
+            { "apelido": "bia", "nome": "Bia Souza", "nascimento": "1990-01-02", "stack": ["go"] },
+        ]);
+
+        let res = send(
+            &state,
+            gzipped_post("/pessoas/batch", batch.to_string().as_bytes()),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let results = body_json(res).await;
+        assert_eq!(results[0]["status"], 201);
+        assert_eq!(results[1]["status"], 201);
+        let count = send(&state, get("/contagem-pessoas")).await;
+        assert_eq!(body_bytes(count).await, b"2");
+
+        // compresses to a few hundred bytes, but is well over the limit once inflated
+        let bomb = format!("[{}]", " ".repeat(1024 * 1024));
+        let res = send(&state, gzipped_post("/pessoas/batch", bomb.as_bytes())).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn bodies_shorter_than_their_content_length_are_bad_requests() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener).unwrap().serve(
+            app(AppState::in_memory(config())).into_make_service_with_connect_info::<SocketAddr>(),
+        );
+        tokio::spawn(server);
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"POST /pessoas HTTP/1.1\r\nHost: localhost\r\n\
+                Content-Type: application/json\r\nContent-Length: 100\r\n\r\n\
+                {\"apelido\": \"ana\"",
+            )
+            .await
+            .unwrap();
+        // the client gives up on the rest of the body
+        stream.shutdown().await.unwrap();
+        let mut res = String::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut res))
+            .await
+            .expect("the server answers rather than waiting for the rest")
+            .unwrap();
+
+        assert!(res.starts_with("HTTP/1.1 400 "), "{res}");
+        assert!(res.contains(r#""title":"Malformed request body""#), "{res}");
+    }
+
+    #[tokio::test]
+    async fn repository_errors_reach_clients_as_problem_details() {
+        let failing = |error: fn() -> AppError| {
+            AppState::new(Arc::new(FailingRepository(error)), config(), None)
+        };
+
+        let state = failing(|| RepositoryError::Unexpected.into());
+        let res = send(&state, get("/pessoas/1")).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let error = body_json(res).await;
+        assert_eq!(error["status"], 500);
+        assert_eq!(error["code"], "Unexpected");
+        assert_eq!(error["detail"], "Unexpected error");
+
+        let state = failing(|| AppError::ServiceUnavailable("Database is unreachable"));
+        let res = send(&state, get("/pessoas/1")).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let error = body_json(res).await;
+        assert_eq!(error["status"], 503);
+        assert_eq!(error["code"], "ServiceUnavailable");
+        assert_eq!(error["detail"], "Database is unreachable");
+
+        let state = failing(|| {
+            RepositoryError::StaleVersion {
+                resoure_name: "person",
+                resource_id: 1,
+            }
+            .into()
+        });
+        let delete = Request::delete("/pessoas/1")
+            .header(IF_MATCH, "\"1\"")
+            .body(Body::empty())
+            .unwrap();
+        let res = send(&state, delete).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let error = body_json(res).await;
+        assert_eq!(error["status"], 409);
+        assert_eq!(error["code"], "StaleVersion");
+    }
+
+    #[tokio::test]
+    async fn root_describes_the_service_without_the_database() {
+        let unreachable = || AppError::ServiceUnavailable("The database is unreachable");
+        let state = AppState::new(Arc::new(FailingRepository(unreachable)), config(), None);
+        let res = send(&state, get("/")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let descriptor = body_json(res).await;
+        assert_eq!(descriptor["service"], "person-api");
+        assert_eq!(descriptor["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(descriptor["endpoints"], serde_json::json!(PUBLIC_ENDPOINTS));
+
+        let config = with_env(&[("ROOT_REDIRECT", "/docs")], Config::from_env).unwrap();
+        let state = AppState::new(Arc::new(FailingRepository(unreachable)), config, None);
+        let res = send(&state, get("/")).await;
+        assert_eq!(res.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(res.headers()[LOCATION], "/docs");
+    }
+
+    #[test]
+    fn settings_install_again_but_dont_change() {
+        // every test installs the defaults, through app or here
+        install_settings(&config()).unwrap();
+        install_settings(&config()).unwrap();
+
+        let english = with_env(&[("FIELD_NAMES", "en")], Config::from_env).unwrap();
+        let err = install_settings(&english).unwrap_err();
+        assert_eq!(err.key, "FIELD_NAMES");
+        assert_eq!(FIELD_NAMES.get(), Some(&crate::domain::FieldNames::Pt));
+    }
+
+    #[tokio::test]
+    async fn build_router_serves_the_given_repository() {
+        let config = config();
+        let repo = Arc::new(InMemoryPersonRepository::new(&config));
+        let router = build_router(repo.clone(), config);
+
+        let payload =
+            serde_json::json!({"apelido": "ana", "nome": "Ana", "nascimento": "2000-01-01"});
+        let res = router
+            .clone()
+            .oneshot(post_json("/pessoas", payload))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let location = res.headers()[LOCATION].to_str().unwrap().to_owned();
+        assert_eq!(repo.people().len(), 1);
+
+        let res = router.oneshot(get(&location)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_json(res).await["apelido"], "ana");
+    }
+
+    #[tokio::test]
+    async fn every_public_endpoint_is_routed() {
+        for endpoint in PUBLIC_ENDPOINTS {
+            let (method, path) = endpoint.split_once(' ').unwrap();
+            // each on its own people, so a delete can't hide the next endpoint
+            let state = AppState::in_memory(config());
+            let payload = serde_json::json!({
+                "apelido": "ana",
+                "nome": "Ana Barros",
+                "nascimento": "1985-09-23",
+                "stack": ["rust"],
+            });
+            let person = state
+                .repo
+                .create_person(serde_json::from_value(payload).unwrap())
+                .await
+                .unwrap();
+            let meta = StackMeta {
+                name: "rust".to_owned(),
+                display_name: None,
+                icon: None,
+            };
+            state.repo.put_stack_meta(meta).await.unwrap();
+
+            let uri = path
+                .replace(":id", &person.id.to_string())
+                .replace(":name", "rust")
+                .replace("?t=", "?t=rust");
+            let req = Request::builder()
+                .method(method)
+                .uri(&uri)
+                .body(Body::empty())
+                .unwrap();
+            let res = send(&state, req).await;
+            assert!(
+                !matches!(
+                    res.status(),
+                    StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+                ),
+                "{endpoint} answered {}",
+                res.status()
+            );
+        }
+    }
+
+    /// Every route of [`app`] but the docs themselves.
+    const ROUTES: &[(&str, &str)] = &[
+        ("GET", "/"),
+        ("GET", "/pessoas"),
+        ("POST", "/pessoas"),
+        ("POST", "/pessoas/batch"),
+        ("POST", "/pessoas/import"),
+        ("GET", "/pessoas/search-advanced"),
+        ("POST", "/pessoas/by-nicknames"),
+        ("GET", "/pessoas/:id"),
+        ("PUT", "/pessoas/:id"),
+        ("PATCH", "/pessoas/:id"),
+        ("DELETE", "/pessoas/:id"),
+        ("PUT", "/pessoas/:id/foto"),
+        ("POST", "/pessoas/:id/stacks/remove"),
+        ("GET", "/pessoas/:id/similares"),
+        ("GET", "/pessoas/events"),
+        ("GET", "/pessoas/timeline"),
+        ("GET", "/pessoas/aleatorio"),
+        ("GET", "/pessoas/stats/por-ano"),
+        ("GET", "/pessoas/export"),
+        ("GET", "/stacks/trending"),
+        ("GET", "/stacks/:name/relacionadas"),
+        ("GET", "/meta/stacks/:name"),
+        ("PUT", "/meta/stacks/:name"),
+        ("GET", "/stats"),
+        ("GET", "/contagem-pessoas"),
+        ("GET", "/contagem-pessoas/stream"),
+        ("GET", "/health"),
+        ("GET", "/healthz"),
+        ("GET", "/ready"),
+        ("GET", "/readyz"),
+        ("GET", "/metrics"),
+        ("GET", "/admin/pessoas"),
+        ("GET", "/admin/pessoas/range"),
+        ("GET", "/admin/pessoas/suspeitas"),
+        ("GET", "/admin/pessoas/duplicados"),
+        ("GET", "/admin/pessoas/incompletas"),
+        ("POST", "/admin/pessoas/revalidate"),
+        ("POST", "/admin/pessoas/purge"),
+        ("GET", "/admin/export.json.gz"),
+        ("GET", "/admin/reindex"),
+        ("POST", "/admin/reindex"),
+        ("POST", "/admin/selftest"),
+        ("GET", "/info/db"),
+    ];
+
+    /// The probe names `/healthz` and `/readyz` only alias documented routes.
+    const ALIASES: &[&str] = &["/healthz", "/readyz"];
+
+    #[tokio::test]
+    async fn every_route_is_in_the_openapi_spec() {
+        let state = AppState::in_memory(config());
+        let res = send(&state, get("/openapi.json")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let spec = body_json(res).await;
+
+        let mut documented = Vec::new();
+        for (path, operations) in spec["paths"].as_object().unwrap() {
+            for method in operations.as_object().unwrap().keys() {
+                documented.push((method.to_uppercase(), path.clone()));
+            }
+        }
+        let mut routed: Vec<_> = ROUTES
+            .iter()
+            .filter(|(_, path)| !ALIASES.contains(path))
+            .map(|(method, path)| {
+                let path = path.replace(":id", "{id}").replace(":name", "{name}");
+                (method.to_string(), path)
+            })
+            .collect();
+        documented.sort();
+        routed.sort();
+        assert_eq!(documented, routed);
+
+        // the list itself must not drift from the router either
+        for (method, path) in ROUTES {
+            let uri = path.replace(":id", "1").replace(":name", "rust");
+            let req = Request::builder()
+                .method(*method)
+                .uri(&uri)
+                .body(Body::empty())
+                .unwrap();
+            let res = send(&state, req).await;
+            // the router's own 404 and 405 come without our JSON error body
+            let unrouted = matches!(
+                res.status(),
+                StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+            ) && !res.headers().contains_key(CONTENT_TYPE);
+            assert!(!unrouted, "{method} {path} is not routed");
+        }
+    }
+
+    #[tokio::test]
+    async fn docs_are_served_without_a_cdn() {
+        let state = AppState::in_memory(config());
+        let res = send(&state, get("/docs")).await;
+        assert!(res.status().is_redirection());
+        assert_eq!(res.headers()[LOCATION], "/docs/");
+
+        let res = send(&state, get("/docs/")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let page = String::from_utf8(body_bytes(res).await.to_vec()).unwrap();
+        assert!(page.contains("swagger-ui"));
+        assert!(!page.contains("unpkg.com"));
+
+        let res = send(&state, get("/docs/swagger-ui-bundle.js")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn every_response_carries_the_api_version() {
+        let state = AppState::in_memory(config());
+        let version = env!("CARGO_PKG_VERSION");
+
+        let res = send(&state, get("/health")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[&API_VERSION], version);
+
+        let res = send(&state, get("/pessoas/999")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()[&API_VERSION], version);
+
+        let res = send(&state, get("/no/such/route")).await;
+        assert_eq!(res.headers()[&API_VERSION], version);
+    }
+
+    #[tokio::test]
+    async fn server_header_is_removed_or_overridden() {
+        let leaky = |config: Config| {
+            Router::new()
+                .route(
+                    "/",
+                    axum::routing::get(|| async { ([(SERVER, "hyper/0.14")], "ok") }),
+                )
+                .layer(middleware::from_fn_with_state(
+                    Arc::new(config),
+                    set_server_header,
+                ))
+        };
+
+        let res = leaky(config()).oneshot(get("/")).await.unwrap();
+        assert!(res.headers().get(SERVER).is_none());
+
+        let config = with_env(&[("SERVER_HEADER", "people")], Config::from_env).unwrap();
+        let res = leaky(config.clone()).oneshot(get("/")).await.unwrap();
+        assert_eq!(res.headers()[SERVER], "people");
+
+        let state = AppState::in_memory(config);
+        let res = send(&state, get("/pessoas/999")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()[SERVER], "people");
+    }
+
+    #[test]
+    fn rate_limiter_keys_clients_by_configured_api_keys_only() {
+        let mut config = config();
+        config.write_auth = WriteAuth::ApiKeys(vec!["known".to_owned()]);
+        let limiter = RateLimiter::new(NonZeroU32::new(1).unwrap(), &config);
+        let from = |key: &str| {
+            let mut req = Request::get("/pessoas")
+                .header(&API_KEY, key)
+                .body(())
+                .unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 1234))));
+            req
+        };
+
+        assert_eq!(limiter.client(&from("known")), "key:known");
+        assert_eq!(limiter.client(&from("made-up")), "ip:10.0.0.1");
+        assert_eq!(limiter.acquire(limiter.client(&from("made-up"))), Ok(0));
+        assert!(limiter.acquire(limiter.client(&from("another"))).is_err());
+
+        config.write_auth = WriteAuth::None;
+        let limiter = RateLimiter::new(NonZeroU32::new(1).unwrap(), &config);
+        assert_eq!(limiter.client(&from("known")), "ip:10.0.0.1");
+    }
+}
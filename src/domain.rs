@@ -0,0 +1,1671 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use serde::ser::SerializeStruct;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use utoipa::openapi::schema::{
+    ArrayBuilder, KnownFormat, ObjectBuilder, OneOfBuilder, Ref, Schema, SchemaFormat, SchemaType,
+};
+use utoipa::openapi::RefOr;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::Config;
+use crate::handlers::Validate;
+use crate::repository::SearchTerms;
+
+#[derive(Debug, Clone, Deserialize, Default, sqlx::FromRow)]
+pub struct Person {
+    pub id: i64,
+    pub nickname: String,
+    pub name: String,
+    pub dob: NaiveDate,
+    pub stacks: Option<Vec<String>>,
+    #[sqlx(default)]
+    pub stacks_detail: Option<sqlx::types::Json<Vec<Stack>>>,
+    #[sqlx(default)]
+    pub photo: Option<String>,
+    /// Also the keyset of the timeline.
+    #[sqlx(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    /// Bumped along with `version`.
+    #[sqlx(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Set by soft deletes, only admins see such people.
+    #[sqlx(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Bumped by tracked reads, not part of the responses yet either.
+    #[sqlx(default)]
+    pub view_count: i64,
+    /// Bumped by every update, sent as the `ETag` so deletes can be made conditional.
+    #[sqlx(default)]
+    pub version: i64,
+}
+
+/// Language of the field names in responses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum FieldNames {
+    /// `apelido`, `nome`, `nascimento`... as the original API.
+    #[default]
+    Pt,
+    /// `nickname`, `name`, `birth_date`...
+    En,
+}
+
+pub(crate) struct PersonFieldNames {
+    pub(crate) nickname: &'static str,
+    pub(crate) name: &'static str,
+    pub(crate) dob: &'static str,
+    pub(crate) stacks: &'static str,
+    pub(crate) stacks_detail: &'static str,
+    pub(crate) photo: &'static str,
+    pub(crate) created_at: &'static str,
+    pub(crate) updated_at: &'static str,
+    pub(crate) deleted_at: &'static str,
+}
+
+impl FieldNames {
+    pub(crate) fn person(self) -> &'static PersonFieldNames {
+        match self {
+            FieldNames::Pt => &PersonFieldNames {
+                nickname: "apelido",
+                name: "nome",
+                dob: "nascimento",
+                stacks: "stack",
+                stacks_detail: "stack_detalhes",
+                photo: "foto",
+                created_at: "criado_em",
+                updated_at: "atualizado_em",
+                deleted_at: "removido_em",
+            },
+            FieldNames::En => &PersonFieldNames {
+                nickname: "nickname",
+                name: "name",
+                dob: "birth_date",
+                stacks: "stacks",
+                stacks_detail: "stack_details",
+                photo: "photo",
+                created_at: "created_at",
+                updated_at: "updated_at",
+                deleted_at: "deleted_at",
+            },
+        }
+    }
+}
+
+impl FromStr for FieldNames {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pt" => Ok(FieldNames::Pt),
+            "en" => Ok(FieldNames::En),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Set by [`crate::install_settings`] from [`Config::field_names`].
+pub(crate) static FIELD_NAMES: OnceLock<FieldNames> = OnceLock::new();
+
+/// Set by [`crate::install_settings`] from [`Config::omit_empty_stacks`].
+pub(crate) static OMIT_EMPTY_STACKS: OnceLock<bool> = OnceLock::new();
+
+impl Serialize for Person {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_person(self, false, serializer)
+    }
+}
+
+/// The shape [`serialize_person`] writes with the default [`FieldNames`].
+impl<'s> ToSchema<'s> for Person {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        let names = FieldNames::default().person();
+        let string = || ObjectBuilder::new().schema_type(SchemaType::String);
+        let formatted = |format| string().format(Some(SchemaFormat::KnownFormat(format)));
+        let stacks = ArrayBuilder::new().items(string()).nullable(true);
+        let person = ObjectBuilder::new()
+            .property(
+                "id",
+                ObjectBuilder::new()
+                    .schema_type(SchemaType::Integer)
+                    .format(Some(SchemaFormat::KnownFormat(KnownFormat::Int64))),
+            )
+            .required("id")
+            .property(names.nickname, string())
+            .required(names.nickname)
+            .property(names.name, string())
+            .required(names.name)
+            .property(
+                names.dob,
+                formatted(KnownFormat::Date).description(Some("Left out of masked people")),
+            )
+            .property(
+                names.stacks,
+                stacks.description(Some("Left out when empty with `OMIT_EMPTY_STACKS`")),
+            )
+            .property(
+                names.stacks_detail,
+                ArrayBuilder::new().items(Ref::from_schema_name("Stack")),
+            )
+            .property(names.photo, string())
+            .property(names.created_at, formatted(KnownFormat::DateTime))
+            .property(names.updated_at, formatted(KnownFormat::DateTime))
+            .property(
+                names.deleted_at,
+                formatted(KnownFormat::DateTime)
+                    .description(Some("Only set on soft deleted people")),
+            )
+            .description(Some(
+                "With `FIELD_NAMES=en` the fields are named in English",
+            ));
+        ("Person", person.into())
+    }
+}
+
+/// A person for public views: the name is partially masked and the birth date left out.
+pub(crate) struct MaskedPerson(pub(crate) Person);
+
+impl Serialize for MaskedPerson {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_person(&self.0, true, serializer)
+    }
+}
+
+/// Serializes with the [`FIELD_NAMES`] and [`OMIT_EMPTY_STACKS`] set at startup.
+pub(crate) fn serialize_person<S>(
+    p: &Person,
+    masked: bool,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serialize_person_as(
+        p,
+        masked,
+        FIELD_NAMES.get().copied().unwrap_or_default(),
+        OMIT_EMPTY_STACKS.get().copied().unwrap_or_default(),
+        serializer,
+    )
+}
+
+pub(crate) fn serialize_person_as<S>(
+    p: &Person,
+    masked: bool,
+    field_names: FieldNames,
+    omit_empty_stacks: bool,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let names = field_names.person();
+    let mut person = serializer.serialize_struct("Person", 10)?;
+    person.serialize_field("id", &p.id)?;
+    person.serialize_field(names.nickname, &p.nickname)?;
+    if masked {
+        person.serialize_field(names.name, &mask_name(&p.name))?;
+        person.skip_field(names.dob)?;
+    } else {
+        person.serialize_field(names.name, &p.name)?;
+        person.serialize_field(names.dob, &p.dob)?;
+    }
+    let omit_stacks = omit_empty_stacks && p.stacks.as_ref().is_none_or(Vec::is_empty);
+    if omit_stacks {
+        person.skip_field(names.stacks)?;
+    } else {
+        person.serialize_field(names.stacks, &p.stacks)?;
+    }
+    match &p.stacks_detail {
+        Some(detail) => person.serialize_field(names.stacks_detail, detail)?,
+        None => person.skip_field(names.stacks_detail)?,
+    }
+    match &p.photo {
+        Some(photo) => person.serialize_field(names.photo, photo)?,
+        None => person.skip_field(names.photo)?,
+    }
+    for (name, timestamp) in [
+        (names.created_at, &p.created_at),
+        (names.updated_at, &p.updated_at),
+        (names.deleted_at, &p.deleted_at),
+    ] {
+        match timestamp {
+            Some(timestamp) => person.serialize_field(name, timestamp)?,
+            None => person.skip_field(name)?,
+        }
+    }
+    person.end()
+}
+
+/// `"John Smith"` becomes `"Jo** S****"`: two characters of the first word and the
+/// initial of the others are kept, but never a whole word longer than one character.
+pub(crate) fn mask_name(name: &str) -> String {
+    name.split(' ')
+        .enumerate()
+        .map(|(i, word)| {
+            let len = word.chars().count();
+            let prefix = if i == 0 { 2 } else { 1 };
+            let kept = prefix.min(len.saturating_sub(1)).max(1);
+            word.chars()
+                .enumerate()
+                .map(|(j, c)| if j < kept { c } else { '*' })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A single entry of a person's stack.
+///
+/// Legacy clients send bare strings (`"rust"`) while richer clients may send
+/// `{ "name": "rust", "level": 3 }`, both shapes are accepted.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(try_from = "StackRepr")]
+pub struct Stack {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum StackRepr {
+    Name(String),
+    Detailed { name: String, level: Option<u8> },
+    // anything else, so the error can say what was expected instead of
+    // serde's generic "did not match any variant"
+    Invalid(de::IgnoredAny),
+}
+
+pub(crate) const INVALID_STACK: &str =
+    "stack must be an array of strings or { name, level } objects";
+
+impl TryFrom<StackRepr> for Stack {
+    type Error = &'static str;
+
+    fn try_from(value: StackRepr) -> Result<Self, Self::Error> {
+        match value {
+            StackRepr::Name(name) => Ok(Stack { name, level: None }),
+            StackRepr::Detailed { name, level } => Ok(Stack { name, level }),
+            StackRepr::Invalid(_) => Err(INVALID_STACK),
+        }
+    }
+}
+
+/// Either shape of [`StackRepr`], always written back as the object.
+impl<'s> ToSchema<'s> for Stack {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        let name = || {
+            ObjectBuilder::new()
+                .schema_type(SchemaType::String)
+                .max_length(Some(MAX_STACK_CHARS))
+        };
+        let detailed = ObjectBuilder::new()
+            .property("name", name())
+            .required("name")
+            .property(
+                "level",
+                ObjectBuilder::new()
+                    .schema_type(SchemaType::Integer)
+                    .nullable(true),
+            );
+        let stack = OneOfBuilder::new().item(name()).item(detailed);
+        ("Stack", stack.into())
+    }
+}
+
+/// Creation made with an `Idempotency-Key`, only ever stored along with the person it created.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct IdempotencyRecord {
+    /// SHA-256 of the payload created with the key.
+    pub(crate) fingerprint: String,
+    pub(crate) person_id: i64,
+}
+
+/// What [`PersonRepository::create_person_idempotently`](crate::repository::PersonRepository::create_person_idempotently)
+/// did with its key.
+#[derive(Debug)]
+pub enum IdempotentCreation {
+    Created(Person),
+    /// The key was already used in its replay window, for this creation.
+    Existing(IdempotencyRecord),
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, ToSchema)]
+pub struct CreatePersonPayload {
+    #[serde(rename(deserialize = "apelido"))]
+    #[schema(rename = "apelido", max_length = 32)]
+    pub nickname: String,
+    #[serde(rename(deserialize = "nome"))]
+    #[schema(rename = "nome", max_length = 100)]
+    pub name: String,
+    #[serde(
+        rename(deserialize = "nascimento"),
+        deserialize_with = "deserialize_unchecked_dob"
+    )]
+    #[schema(rename = "nascimento", value_type = NaiveDate)]
+    pub dob: Dob,
+    #[serde(
+        rename(deserialize = "stack"),
+        default,
+        deserialize_with = "deserialize_stacks"
+    )]
+    #[schema(rename = "stack")]
+    pub stacks: Option<Vec<Stack>>,
+}
+
+impl Validate for CreatePersonPayload {
+    fn validate(&self, config: &Config) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        validate_nickname(&self.nickname, config, &mut errors);
+        validate_text("nome", &self.name, MAX_NAME_CHARS, &mut errors);
+        if let Err(err) = validate_dob(self.dob.0, Utc::now().date_naive()) {
+            errors.push(format!("nascimento {err}"));
+        }
+        if let Some(names) = self.stack_names() {
+            validate_stack_names(&names, config, &mut errors);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+pub(crate) const MAX_NICKNAME_CHARS: usize = 32;
+pub(crate) const MAX_NAME_CHARS: usize = 100;
+pub(crate) const MAX_STACK_CHARS: usize = 32;
+
+/// Requires a non blank `value` of at most `max_chars` characters.
+pub(crate) fn validate_text(field: &str, value: &str, max_chars: usize, errors: &mut Vec<String>) {
+    if value.trim().is_empty() {
+        errors.push(format!("{field} must not be empty"));
+    } else if value.chars().count() > max_chars {
+        errors.push(format!("{field} must have at most {max_chars} characters"));
+    }
+}
+
+/// Nicknames end up in URLs, so on top of the text rules each character must match [`Config::nickname_charset`].
+pub(crate) fn validate_nickname(nickname: &str, config: &Config, errors: &mut Vec<String>) {
+    validate_text("apelido", nickname, MAX_NICKNAME_CHARS, errors);
+    let mut buf = [0; 4];
+    let mut invalid = Vec::new();
+    for c in nickname.chars() {
+        let quoted = format!("{c:?}");
+        if !config.nickname_charset.is_match(c.encode_utf8(&mut buf)) && !invalid.contains(&quoted)
+        {
+            invalid.push(quoted);
+        }
+    }
+    if !invalid.is_empty() {
+        errors.push(format!(
+            "apelido contains invalid characters: {}",
+            invalid.join(", ")
+        ));
+    }
+}
+
+/// An empty stack is fine, each of its entries must be non blank, fit [`MAX_STACK_CHARS`] and all of them the byte budget.
+pub(crate) fn validate_stack_names(names: &[String], config: &Config, errors: &mut Vec<String>) {
+    for (i, name) in names.iter().enumerate() {
+        if name.trim().is_empty() {
+            errors.push(format!("stack[{i}] must not be empty"));
+        } else if name.chars().count() > MAX_STACK_CHARS {
+            errors.push(format!(
+                "stack[{i}] must have at most {MAX_STACK_CHARS} characters"
+            ));
+        }
+    }
+    if let Err(err) = validate_stacks_size(names, config.max_stacks_bytes) {
+        errors.push(err);
+    }
+}
+
+impl CreatePersonPayload {
+    pub(crate) fn stack_names(&self) -> Option<Vec<String>> {
+        self.stacks.as_deref().map(stack_names)
+    }
+}
+
+pub(crate) fn stack_names(stacks: &[Stack]) -> Vec<String> {
+    stacks.iter().map(|s| s.name.clone()).collect()
+}
+
+/// Checks the size in bytes of the stack names serialized as a JSON array, the shape search scans.
+pub(crate) fn validate_stacks_size(names: &[String], budget: usize) -> Result<(), String> {
+    let size = serde_json::to_vec(names).map_or(0, |encoded| encoded.len());
+    if size > budget {
+        return Err(format!(
+            "stack must be at most {budget} bytes when serialized, got {size}"
+        ));
+    }
+    Ok(())
+}
+
+/// Body of `PATCH /pessoas/:id`, following JSON Merge Patch (RFC 7386):
+/// an absent field is left unchanged while `null` clears it.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct PersonMergePatch {
+    #[serde(
+        rename = "apelido",
+        default,
+        deserialize_with = "deserialize_patch_field"
+    )]
+    pub nickname: Option<Option<String>>,
+    #[serde(rename = "nome", default, deserialize_with = "deserialize_patch_field")]
+    pub name: Option<Option<String>>,
+    #[serde(
+        rename = "nascimento",
+        default,
+        deserialize_with = "deserialize_unchecked_dob_patch"
+    )]
+    #[schema(value_type = Option<NaiveDate>)]
+    pub dob: Option<Option<Dob>>,
+    #[serde(
+        rename = "stack",
+        default,
+        deserialize_with = "deserialize_patch_stacks"
+    )]
+    pub stacks: Option<Option<Vec<Stack>>>,
+}
+
+impl PersonMergePatch {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.nickname.is_none()
+            && self.name.is_none()
+            && self.dob.is_none()
+            && self.stacks.is_none()
+    }
+}
+
+/// A full replacement sets every field, so a missing `stack` clears it.
+impl From<CreatePersonPayload> for PersonMergePatch {
+    fn from(payload: CreatePersonPayload) -> Self {
+        Self {
+            nickname: Some(Some(payload.nickname)),
+            name: Some(Some(payload.name)),
+            dob: Some(Some(payload.dob)),
+            stacks: Some(payload.stacks),
+        }
+    }
+}
+
+impl Validate for PersonMergePatch {
+    /// Only `stack` is nullable, the other fields can be replaced but not cleared.
+    fn validate(&self, config: &Config) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        match &self.nickname {
+            Some(Some(nickname)) => validate_nickname(nickname, config, &mut errors),
+            Some(None) => errors.push("apelido must not be null".to_owned()),
+            None => {}
+        }
+        match &self.name {
+            Some(Some(name)) => validate_text("nome", name, MAX_NAME_CHARS, &mut errors),
+            Some(None) => errors.push("nome must not be null".to_owned()),
+            None => {}
+        }
+        match self.dob {
+            Some(Some(dob)) => {
+                if let Err(err) = validate_dob(dob.0, Utc::now().date_naive()) {
+                    errors.push(format!("nascimento {err}"));
+                }
+            }
+            Some(None) => errors.push("nascimento must not be null".to_owned()),
+            None => {}
+        }
+        if let Some(Some(stacks)) = &self.stacks {
+            validate_stack_names(&stack_names(stacks), config, &mut errors);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Tells a present `null` (`Some(None)`) apart from an absent field (`None`, via `#[serde(default)]`).
+pub(crate) fn deserialize_patch_field<'de, T, D>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Birth dates before January 1st of this year are considered implausible.
+pub(crate) const MIN_DOB_YEAR: i32 = 1900;
+
+/// Checks that `dob` is a plausible birth date as of `today`.
+pub(crate) fn validate_dob(dob: NaiveDate, today: NaiveDate) -> Result<(), String> {
+    if dob.year() < MIN_DOB_YEAR {
+        return Err(format!("must not be before {MIN_DOB_YEAR}-01-01"));
+    }
+    if dob > today {
+        return Err("must not be in the future".to_owned());
+    }
+    Ok(())
+}
+
+/// A plausible birth date in any of the accepted formats, an implausible one fails to
+/// deserialize with the [`validate_dob`] error. The payloads parse theirs with
+/// [`deserialize_unchecked_dob`] instead, so it is reported along with their other problems.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, sqlx::Type)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct Dob(pub(crate) NaiveDate);
+
+impl<'de> Deserialize<'de> for Dob {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let dob = deserialize_date(deserializer)?;
+        validate_dob(dob, Utc::now().date_naive()).map_err(de::Error::custom)?;
+        Ok(Dob(dob))
+    }
+}
+
+/// A [`Dob`] that is only parsed, left for [`validate_dob`] to check during validation.
+pub(crate) fn deserialize_unchecked_dob<'de, D>(deserializer: D) -> Result<Dob, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_date(deserializer).map(Dob)
+}
+
+/// [`deserialize_unchecked_dob`] of a merge patch field, see [`deserialize_patch_field`].
+pub(crate) fn deserialize_unchecked_dob_patch<'de, D>(
+    deserializer: D,
+) -> Result<Option<Option<Dob>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Unchecked(#[serde(deserialize_with = "deserialize_unchecked_dob")] Dob);
+
+    Option::<Unchecked>::deserialize(deserializer).map(|dob| Some(dob.map(|Unchecked(dob)| dob)))
+}
+
+/// Locale used to interpret dates that are not in ISO format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum DateLocale {
+    /// Only `YYYY-MM-DD` is accepted.
+    #[default]
+    Iso,
+    /// Also accepts `DD/MM/YYYY`.
+    PtBr,
+    /// Also accepts `MM/DD/YYYY`.
+    EnUs,
+}
+
+impl DateLocale {
+    pub(crate) fn fallback_format(&self) -> Option<&'static str> {
+        match self {
+            DateLocale::Iso => None,
+            DateLocale::PtBr => Some("%d/%m/%Y"),
+            DateLocale::EnUs => Some("%m/%d/%Y"),
+        }
+    }
+
+    /// ISO dates, or else dates in the format of the locale.
+    pub(crate) fn parse(self, raw: &str) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(raw, "%Y-%m-%d").ok().or_else(|| {
+            self.fallback_format()
+                .and_then(|format| NaiveDate::parse_from_str(raw, format).ok())
+        })
+    }
+}
+
+impl FromStr for DateLocale {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "iso" => Ok(DateLocale::Iso),
+            "pt-br" => Ok(DateLocale::PtBr),
+            "en-us" => Ok(DateLocale::EnUs),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Set by [`crate::install_settings`] from [`Config::date_locale`],
+/// serde gives deserializers no other way in.
+pub(crate) static DATE_LOCALE: OnceLock<DateLocale> = OnceLock::new();
+
+pub(crate) fn deserialize_date<'de, D>(deserializer: D) -> Result<NaiveDate, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = String::deserialize(deserializer)?;
+    DATE_LOCALE
+        .get()
+        .copied()
+        .unwrap_or_default()
+        .parse(&raw)
+        .ok_or_else(|| de::Error::custom(format!("invalid date '{raw}'")))
+}
+
+/// Set by [`crate::install_settings`] from [`Config::lenient_stacks`].
+pub(crate) static LENIENT_STACKS: OnceLock<bool> = OnceLock::new();
+
+/// Entries allowed in a person's stack. Enforced while deserializing, so a huge
+/// array is rejected before all of it has been allocated.
+pub(crate) const MAX_STACK_ENTRIES: usize = 64;
+
+pub(crate) fn too_many_stacks() -> String {
+    format!("stack must have at most {MAX_STACK_ENTRIES} entries")
+}
+
+/// A stack array that stops at [`MAX_STACK_ENTRIES`].
+pub(crate) struct StackList(pub(crate) Vec<Stack>);
+
+impl<'de> Deserialize<'de> for StackList {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct StackListVisitor;
+
+        impl<'de> de::Visitor<'de> for StackListVisitor {
+            type Value = StackList;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an array of stacks")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::SeqAccess<'de>,
+            {
+                let capacity = seq.size_hint().unwrap_or_default().min(MAX_STACK_ENTRIES);
+                let mut stacks = Vec::with_capacity(capacity);
+                while let Some(stack) = seq.next_element()? {
+                    if stacks.len() == MAX_STACK_ENTRIES {
+                        return Err(de::Error::custom(too_many_stacks()));
+                    }
+                    stacks.push(stack);
+                }
+                Ok(StackList(stacks))
+            }
+        }
+
+        deserializer.deserialize_seq(StackListVisitor)
+    }
+}
+
+/// Accepts `"stack": ["a", "b"]` and, with [`LENIENT_STACKS`] only, `"stack": "a,b"`.
+pub(crate) fn deserialize_stacks<'de, D>(deserializer: D) -> Result<Option<Vec<Stack>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let lenient = LENIENT_STACKS.get().copied().unwrap_or_default();
+    deserialize_stacks_leniently(deserializer, lenient)
+}
+
+/// [`deserialize_stacks`], also accepting `"stack": "a,b"` when `lenient`.
+pub(crate) fn deserialize_stacks_leniently<'de, D>(
+    deserializer: D,
+    lenient: bool,
+) -> Result<Option<Vec<Stack>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum LenientStacks {
+        // the untagged enum buffers the whole value anyway, the size is checked once it matched
+        List(Vec<Stack>),
+        Joined(String),
+        Invalid(de::IgnoredAny),
+    }
+
+    if !lenient {
+        return Ok(Option::<StackList>::deserialize(deserializer)?.map(|list| list.0));
+    }
+
+    Option::<LenientStacks>::deserialize(deserializer)?
+        .map(|stacks| {
+            let stacks = match stacks {
+                LenientStacks::List(stacks) => stacks,
+                LenientStacks::Joined(joined) => joined
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| Stack {
+                        name: name.to_owned(),
+                        level: None,
+                    })
+                    .collect(),
+                LenientStacks::Invalid(_) => return Err(de::Error::custom(INVALID_STACK)),
+            };
+            if stacks.len() > MAX_STACK_ENTRIES {
+                return Err(de::Error::custom(too_many_stacks()));
+            }
+            Ok(stacks)
+        })
+        .transpose()
+}
+
+/// [`deserialize_stacks`] for a merge patch, where `null` clears the stacks.
+pub(crate) fn deserialize_patch_stacks<'de, D>(
+    deserializer: D,
+) -> Result<Option<Option<Vec<Stack>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_stacks(deserializer).map(Some)
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct SearchPersonQuery {
+    /// Required unless filtering with the fields below.
+    #[serde(rename(deserialize = "t"))]
+    #[param(rename = "t")]
+    pub(crate) search_term: Option<String>,
+    /// Also computes the total number of matches, returned in `X-Total-Count`.
+    /// Not available for fuzzy searches.
+    #[serde(default)]
+    pub(crate) with_total: bool,
+    /// Matches by trigram similarity instead of substring, tolerating typos.
+    #[serde(default)]
+    pub(crate) fuzzy: bool,
+    pub(crate) limit: Option<i64>,
+    pub(crate) offset: Option<i64>,
+    /// Wraps the JSON results as `{ "items": [...], "took_ms": 3 }` with the query time.
+    #[serde(default)]
+    pub(crate) debug_timing: bool,
+    /// Wraps the JSON results as `{ "items": [...], "total": 120, "next_offset": 50 }`,
+    /// `total` being `null` for fuzzy searches and `next_offset` once there are no more pages.
+    #[serde(default)]
+    pub(crate) paged: bool,
+    /// Matches a stack name exactly, ignoring case.
+    pub(crate) stack: Option<String>,
+    /// Earliest birth date, inclusive.
+    pub(crate) nascimento_gte: Option<NaiveDate>,
+    /// Latest birth date, inclusive.
+    pub(crate) nascimento_lte: Option<NaiveDate>,
+    /// Matches the start of the nickname, ignoring case.
+    pub(crate) apelido: Option<String>,
+    /// Like [`AdvancedSearchQuery::sort`].
+    pub(crate) sort: Option<String>,
+}
+
+impl SearchPersonQuery {
+    /// The same search on `GET /pessoas/search-advanced` when any structured filter is set.
+    pub(crate) fn as_advanced(&self) -> Option<AdvancedSearchQuery> {
+        let filtered = self.stack.is_some()
+            || self.nascimento_gte.is_some()
+            || self.nascimento_lte.is_some()
+            || self.apelido.is_some()
+            || self.sort.is_some();
+        filtered.then(|| AdvancedSearchQuery {
+            t: self.search_term.clone(),
+            from: self.nascimento_gte,
+            to: self.nascimento_lte,
+            apelido: self.apelido.clone(),
+            stack: self.stack.clone(),
+            sort: self.sort.clone(),
+            limit: self.limit,
+            offset: self.offset,
+            ..AdvancedSearchQuery::default()
+        })
+    }
+}
+
+/// The Rinha contract returns at most 50 search results.
+pub(crate) const DEFAULT_SEARCH_LIMIT: i64 = 50;
+
+/// Keyset over `(created_at, id)`, newest first. Without `before_id` every
+/// person created at exactly `before` is excluded too.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct TimelineQuery {
+    pub(crate) before: Option<DateTime<Utc>>,
+    pub(crate) before_id: Option<i64>,
+    pub(crate) limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct TimelinePage {
+    pub(crate) items: Vec<Person>,
+    pub(crate) next_before: Option<DateTime<Utc>>,
+    pub(crate) next_before_id: Option<i64>,
+}
+
+/// Query of `GET /pessoas/search-advanced`, every filter is optional and they are all combined.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct AdvancedSearchQuery {
+    pub(crate) t: Option<String>,
+    /// Earliest birth date, inclusive.
+    pub(crate) from: Option<NaiveDate>,
+    /// Latest birth date, inclusive.
+    pub(crate) to: Option<NaiveDate>,
+    /// Matched against the nickname as [`AdvancedSearchQuery::apelido_match`] says.
+    pub(crate) apelido: Option<String>,
+    /// Defaults to [`MatchMode::Prefix`].
+    pub(crate) apelido_match: Option<MatchMode>,
+    pub(crate) nome: Option<String>,
+    /// Defaults to [`MatchMode::Substring`].
+    pub(crate) nome_match: Option<MatchMode>,
+    /// Matched against each stack name as [`AdvancedSearchQuery::stack_match`] says.
+    pub(crate) stack: Option<String>,
+    /// Defaults to [`MatchMode::Exact`].
+    pub(crate) stack_match: Option<MatchMode>,
+    /// One of `apelido`, `nome` or `nascimento`, prefixed with `-` for descending order.
+    pub(crate) sort: Option<String>,
+    /// `X-Next-Cursor` of the previous page with the same `sort`, can't be combined with `offset`.
+    pub(crate) cursor: Option<String>,
+    pub(crate) limit: Option<i64>,
+    pub(crate) offset: Option<i64>,
+}
+
+/// Position in the sorted advanced search: the sort column's value and the id of the last person of a page.
+#[derive(Debug)]
+pub(crate) struct SortCursor {
+    pub(crate) value: CursorValue,
+    pub(crate) id: i64,
+}
+
+#[derive(Debug)]
+pub(crate) enum CursorValue {
+    Text(String),
+    Date(NaiveDate),
+    /// Sorted by id alone, which already is the tiebreak.
+    Id,
+}
+
+impl SortCursor {
+    /// The base64 (URL safe) of the JSON `[value, id]` tuple, e.g. `["ana", 42]`.
+    pub(crate) fn encode(person: &Person, sort: SortOrder) -> String {
+        let value = match sort.column {
+            "nickname" => person.nickname.clone(),
+            "name" => person.name.clone(),
+            "dob" => person.dob.to_string(),
+            _ => person.id.to_string(),
+        };
+        let tuple = serde_json::to_vec(&(value, person.id)).expect("a tuple always serializes");
+        URL_SAFE_NO_PAD.encode(tuple)
+    }
+
+    pub(crate) fn decode(raw: &str, sort: SortOrder) -> Result<Self, String> {
+        let invalid = || "cursor is invalid".to_owned();
+        let tuple = URL_SAFE_NO_PAD.decode(raw).map_err(|_| invalid())?;
+        let (value, id): (String, i64) = serde_json::from_slice(&tuple).map_err(|_| invalid())?;
+        let value = match sort.column {
+            "nickname" | "name" => CursorValue::Text(value),
+            "dob" => CursorValue::Date(value.parse().map_err(|_| invalid())?),
+            _ => CursorValue::Id,
+        };
+        Ok(SortCursor { value, id })
+    }
+
+    /// Whether `person` comes after the cursor in `sort` order, which ties on id ascending.
+    pub(crate) fn precedes(&self, person: &Person, sort: SortOrder) -> bool {
+        let order = match &self.value {
+            CursorValue::Text(value) if sort.column == "nickname" => {
+                person.nickname.as_str().cmp(value.as_str())
+            }
+            CursorValue::Text(value) => person.name.as_str().cmp(value.as_str()),
+            CursorValue::Date(value) => person.dob.cmp(value),
+            CursorValue::Id => person.id.cmp(&self.id),
+        };
+        let order = if sort.descending {
+            order.reverse()
+        } else {
+            order
+        };
+        order.then(person.id.cmp(&self.id)).is_gt()
+    }
+}
+
+/// How a single field of the advanced search is compared with its value, always case-insensitive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum MatchMode {
+    Exact,
+    Prefix,
+    Substring,
+}
+
+impl MatchMode {
+    pub(crate) fn matches(&self, field: &str, value: &str) -> bool {
+        let (field, value) = (field.to_lowercase(), value.to_lowercase());
+        match self {
+            MatchMode::Exact => field == value,
+            MatchMode::Prefix => field.starts_with(&value),
+            MatchMode::Substring => field.contains(&value),
+        }
+    }
+}
+
+/// A field filter of the advanced search.
+#[derive(Debug)]
+pub(crate) struct FieldMatch {
+    pub(crate) mode: MatchMode,
+    pub(crate) value: String,
+}
+
+impl FieldMatch {
+    pub(crate) fn new(
+        value: Option<String>,
+        mode: Option<MatchMode>,
+        default: MatchMode,
+    ) -> Option<Self> {
+        value.map(|value| FieldMatch {
+            mode: mode.unwrap_or(default),
+            value,
+        })
+    }
+}
+
+/// Allowlisted `ORDER BY` for the advanced search, the column never comes from the client.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SortOrder {
+    pub(crate) column: &'static str,
+    pub(crate) descending: bool,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder {
+            column: "id",
+            descending: false,
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, descending) = match s.strip_prefix('-') {
+            Some(field) => (field, true),
+            None => (s, false),
+        };
+        let column = match field {
+            "apelido" => "nickname",
+            "nome" => "name",
+            "nascimento" => "dob",
+            _ => {
+                return Err(format!(
+                    "sort must be one of apelido, nome or nascimento, optionally prefixed with '-', got '{s}'"
+                ))
+            }
+        };
+        Ok(SortOrder { column, descending })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AdvancedSearch {
+    pub(crate) terms: SearchTerms,
+    pub(crate) from: Option<NaiveDate>,
+    pub(crate) to: Option<NaiveDate>,
+    pub(crate) nickname: Option<FieldMatch>,
+    pub(crate) name: Option<FieldMatch>,
+    pub(crate) stack: Option<FieldMatch>,
+    pub(crate) sort: SortOrder,
+    pub(crate) after: Option<SortCursor>,
+    pub(crate) limit: i64,
+    pub(crate) offset: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct PhotoPayload {
+    #[serde(rename(deserialize = "foto"))]
+    #[schema(rename = "foto")]
+    pub(crate) photo: String,
+}
+
+impl Validate for PhotoPayload {
+    fn validate(&self, _config: &Config) -> Result<(), Vec<String>> {
+        validate_http_url("foto", &self.photo).map_err(|e| vec![e])
+    }
+}
+
+/// Only absolute http(s) URLs are accepted as photos and icons.
+pub(crate) fn validate_http_url(field: &str, raw: &str) -> Result<(), String> {
+    match url::Url::parse(raw) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => Ok(()),
+        _ => Err(format!("{field} must be an http(s) URL, got '{raw}'")),
+    }
+}
+
+/// Body of `POST /pessoas/:id/stacks/remove`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct StackRemovalPayload {
+    #[serde(rename(deserialize = "stack"))]
+    #[schema(rename = "stack")]
+    pub(crate) stacks: Vec<String>,
+}
+
+impl Validate for StackRemovalPayload {
+    /// Names the person doesn't have are fine, they are simply not removed.
+    fn validate(&self, _config: &Config) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if self.stacks.is_empty() {
+            errors.push("stack must not be empty".to_owned());
+        } else if self.stacks.len() > MAX_STACK_ENTRIES {
+            errors.push(too_many_stacks());
+        }
+        for (i, name) in self.stacks.iter().enumerate() {
+            validate_text(&format!("stack[{i}]"), name, MAX_STACK_CHARS, &mut errors);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+pub(crate) const MAX_NICKNAMES_LOOKUP: usize = 100;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct NicknamesPayload {
+    #[serde(rename(deserialize = "apelidos"))]
+    #[schema(rename = "apelidos")]
+    pub(crate) nicknames: Vec<String>,
+}
+
+impl Validate for NicknamesPayload {
+    fn validate(&self, _config: &Config) -> Result<(), Vec<String>> {
+        if self.nicknames.len() > MAX_NICKNAMES_LOOKUP {
+            return Err(vec![format!(
+                "apelidos must have at most {MAX_NICKNAMES_LOOKUP} entries"
+            )]);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) const MAX_BATCH_SIZE: usize = 1000;
+
+/// Body of `POST /pessoas/batch`, each entry is parsed and validated on its own
+/// so a bad one only fails itself.
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct BatchPayload(pub(crate) Vec<serde_json::Value>);
+
+impl Validate for BatchPayload {
+    fn validate(&self, _config: &Config) -> Result<(), Vec<String>> {
+        if self.0.is_empty() || self.0.len() > MAX_BATCH_SIZE {
+            return Err(vec![format!(
+                "the batch must have between 1 and {MAX_BATCH_SIZE} people"
+            )]);
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of one entry of a batch, in the order they were sent.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct BatchItemResult {
+    pub(crate) index: usize,
+    /// What creating the entry alone would have answered.
+    pub(crate) status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<i64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) errors: Vec<String>,
+}
+
+pub(crate) const DEFAULT_PAGE_LIMIT: i64 = 50;
+pub(crate) const MAX_PAGE_LIMIT: i64 = 100;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ListPersonQuery {
+    /// Id of the last person of the previous page.
+    pub(crate) cursor: Option<i64>,
+    pub(crate) limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[aliases(PersonPage = Page<Person>, RevalidationPage = Page<RevalidationFailure>)]
+pub(crate) struct Page<T> {
+    pub(crate) items: Vec<T>,
+    pub(crate) next_cursor: Option<i64>,
+}
+
+/// A stored person that no longer passes the current validation rules.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct RevalidationFailure {
+    pub(crate) id: i64,
+    pub(crate) errors: Vec<String>,
+}
+
+/// Runs the rules applied on create against an already stored person.
+pub(crate) fn revalidate_person(person: &Person, config: &Config, today: NaiveDate) -> Vec<String> {
+    let mut errors = Vec::new();
+    validate_nickname(&person.nickname, config, &mut errors);
+    validate_text("nome", &person.name, MAX_NAME_CHARS, &mut errors);
+    if let Err(err) = validate_dob(person.dob, today) {
+        errors.push(format!("nascimento {err}"));
+    }
+    if let Some(stacks) = &person.stacks {
+        validate_stack_names(stacks, config, &mut errors);
+    }
+    errors
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow, ToSchema)]
+pub struct BirthYearCount {
+    #[serde(rename(serialize = "ano"))]
+    #[schema(rename = "ano")]
+    pub(crate) year: i32,
+    pub(crate) count: i64,
+}
+
+/// Everything `GET /stats` shows at once.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DashboardStats {
+    #[serde(flatten)]
+    pub(crate) counts: CountBreakdown,
+    pub(crate) top_stacks: Vec<StackCount>,
+    pub(crate) age: AgeStats,
+    pub(crate) newest: Option<Person>,
+}
+
+/// Ages in whole years, all `None` while there is nobody.
+#[derive(Debug, Serialize, sqlx::FromRow, ToSchema)]
+pub(crate) struct AgeStats {
+    pub(crate) min: Option<i32>,
+    pub(crate) max: Option<i32>,
+    pub(crate) avg: Option<f64>,
+    /// People left out for a missing or, with [`InvalidAges::Skip`], implausible birth date.
+    pub(crate) excluded: i64,
+}
+
+/// What the age stats do with birth dates [`validate_dob`] rejects, left by legacy data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum InvalidAges {
+    /// Leaves them out, counted in [`AgeStats::excluded`].
+    #[default]
+    Skip,
+    /// Moves them to the nearest plausible date, so a future one counts as age 0.
+    Clamp,
+}
+
+impl FromStr for InvalidAges {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(InvalidAges::Skip),
+            "clamp" => Ok(InvalidAges::Clamp),
+            _ => Err(()),
+        }
+    }
+}
+
+pub(crate) const DASHBOARD_TOP_STACKS: i64 = 5;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DbInfo {
+    /// Latest applied `sqlx` migration, `None` while the schema was created without `RUN_MIGRATIONS`.
+    pub(crate) migration_version: Option<i64>,
+    pub(crate) server_version: String,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct CountQuery {
+    #[serde(default)]
+    pub(crate) breakdown: bool,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow, ToSchema)]
+pub struct CountBreakdown {
+    pub(crate) total: i64,
+    pub(crate) with_stacks: i64,
+    pub(crate) without_stacks: i64,
+}
+
+/// Two people whose names are similar enough that they may be the same person.
+#[derive(Debug, Serialize, sqlx::FromRow, ToSchema)]
+pub struct DuplicateCandidate {
+    pub(crate) first_id: i64,
+    pub(crate) first_name: String,
+    pub(crate) second_id: i64,
+    pub(crate) second_name: String,
+    pub(crate) similarity: f32,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct DuplicatesQuery {
+    pub(crate) limit: Option<i64>,
+    pub(crate) offset: Option<i64>,
+}
+
+pub(crate) const DEFAULT_SIMILAR_LIMIT: i64 = 10;
+pub(crate) const MAX_SIMILAR_LIMIT: i64 = 50;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct LimitQuery {
+    pub(crate) limit: Option<i64>,
+}
+
+pub(crate) const DEFAULT_TRENDING_DAYS: i32 = 30;
+pub(crate) const MAX_TRENDING_DAYS: i32 = 365;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct TrendingQuery {
+    pub(crate) days: Option<i32>,
+    pub(crate) limit: Option<i64>,
+}
+
+pub(crate) const DEFAULT_PURGE_OLDER_THAN_DAYS: i32 = 30;
+/// Rows deleted per statement by the purge, so no single statement holds long locks.
+pub(crate) const PURGE_BATCH_SIZE: i64 = 1000;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct PurgeQuery {
+    pub(crate) older_than_days: Option<i32>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct PurgeResult {
+    pub(crate) purged: u64,
+}
+
+/// What `POST /admin/selftest` did, step by step.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct SelfTestReport {
+    pub(crate) ok: bool,
+    pub(crate) steps: Vec<SelfTestStep>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct SelfTestStep {
+    pub(crate) step: &'static str,
+    pub(crate) ok: bool,
+    pub(crate) took_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow, ToSchema)]
+pub struct StackCount {
+    pub(crate) stack: String,
+    pub(crate) count: i64,
+    /// From the stack's [`StackMeta`], where the endpoint joins it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub(crate) display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub(crate) icon: Option<String>,
+}
+
+/// Curated details of a stack, keyed by the name people put in their stacks.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct StackMeta {
+    pub(crate) name: String,
+    pub(crate) display_name: Option<String>,
+    pub(crate) icon: Option<String>,
+}
+
+/// Body of `PUT /meta/stacks/:name`, which replaces the whole metadata.
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct StackMetaPayload {
+    pub(crate) display_name: Option<String>,
+    pub(crate) icon: Option<String>,
+}
+
+pub(crate) const MAX_STACK_DISPLAY_NAME_CHARS: usize = 64;
+
+impl Validate for StackMetaPayload {
+    fn validate(&self, _config: &Config) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if let Some(display_name) = &self.display_name {
+            validate_text(
+                "display_name",
+                display_name,
+                MAX_STACK_DISPLAY_NAME_CHARS,
+                &mut errors,
+            );
+        }
+        if let Some(Err(err)) = self
+            .icon
+            .as_deref()
+            .map(|icon| validate_http_url("icon", icon))
+        {
+            errors.push(err);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dob(value: &str) -> Result<Dob, serde_json::Error> {
+        serde_json::from_value(serde_json::Value::from(value))
+    }
+
+    #[test]
+    fn names_are_masked_but_for_their_first_letters() {
+        assert_eq!(mask_name("João Silva"), "Jo** S****");
+        assert_eq!(mask_name("Ana Maria de Souza"), "An* M**** d* S****");
+        // a letter is always kept, and one letter words are kept whole
+        assert_eq!(mask_name("Al B"), "A* B");
+        assert_eq!(mask_name("X"), "X");
+    }
+
+    #[test]
+    fn nicknames_are_limited_to_the_configured_characters() {
+        let errors = |nickname: &str, config: &Config| {
+            let mut errors = Vec::new();
+            validate_nickname(nickname, config, &mut errors);
+            errors
+        };
+        let config = crate::test_support::config();
+        for allowed in ["ana", "Ana_B", "ana.barros-85", "A1"] {
+            assert!(errors(allowed, &config).is_empty(), "{allowed}");
+        }
+        assert_eq!(
+            errors("ana barros/85 ç/", &config),
+            [r#"apelido contains invalid characters: ' ', '/', 'ç'"#]
+        );
+        assert_eq!(
+            errors("ana?", &config),
+            [r#"apelido contains invalid characters: '?'"#]
+        );
+
+        let config =
+            crate::test_support::with_env(&[("NICKNAME_CHARSET", "[a-zç]")], Config::from_env)
+                .unwrap();
+        assert!(errors("anaç", &config).is_empty());
+        assert_eq!(
+            errors("Ana_1", &config),
+            [r#"apelido contains invalid characters: 'A', '_', '1'"#]
+        );
+    }
+
+    #[test]
+    fn validate_dob_bounds_birth_dates_from_1900_to_today() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let today = date(2024, 6, 15);
+        assert!(validate_dob(date(1899, 12, 31), today).is_err());
+        assert!(validate_dob(date(1900, 1, 1), today).is_ok());
+        assert!(validate_dob(today, today).is_ok());
+        assert!(validate_dob(date(2024, 6, 16), today).is_err());
+
+        assert!(dob("2020-02-29").is_ok());
+        let err = dob("2021-02-29").unwrap_err().to_string();
+        assert!(err.contains("invalid date '2021-02-29'"), "{err}");
+    }
+
+    #[test]
+    fn dob_accepts_plausible_birth_dates() {
+        assert_eq!(
+            dob("1985-09-23").unwrap(),
+            Dob(NaiveDate::from_ymd_opt(1985, 9, 23).unwrap())
+        );
+        assert!(dob("1900-01-01").is_ok());
+        assert!(dob(&Utc::now().date_naive().to_string()).is_ok());
+    }
+
+    #[test]
+    fn dob_rejects_implausible_birth_dates() {
+        let err = dob("1899-12-31").unwrap_err().to_string();
+        assert!(err.contains("must not be before 1900-01-01"), "{err}");
+
+        let tomorrow = Utc::now().date_naive().succ_opt().unwrap();
+        let err = dob(&tomorrow.to_string()).unwrap_err().to_string();
+        assert!(err.contains("must not be in the future"), "{err}");
+
+        assert!(dob("23/09/1985").is_err());
+    }
+
+    #[test]
+    fn payloads_report_implausible_birth_dates_when_validated() {
+        let create = serde_json::from_value::<CreatePersonPayload>(serde_json::json!({
+            "apelido": "ana",
+            "nome": "Ana Barros",
+            "nascimento": "1800-01-01",
+        }))
+        .unwrap();
+        assert_eq!(
+            create.validate(&crate::test_support::config()).unwrap_err(),
+            ["nascimento must not be before 1900-01-01"]
+        );
+
+        let patch = serde_json::from_value::<PersonMergePatch>(serde_json::json!({
+            "nascimento": "1800-01-01",
+        }))
+        .unwrap();
+        assert_eq!(
+            patch.validate(&crate::test_support::config()).unwrap_err(),
+            ["nascimento must not be before 1900-01-01"]
+        );
+        let patch = serde_json::from_value::<PersonMergePatch>(serde_json::json!({
+            "nascimento": null,
+        }))
+        .unwrap();
+        assert_eq!(patch.dob, Some(None));
+    }
+
+    #[test]
+    fn dates_fall_back_to_the_format_of_the_locale() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d);
+        assert_eq!(DateLocale::Iso.parse("03/04/2020"), None);
+        assert_eq!(DateLocale::PtBr.parse("03/04/2020"), date(2020, 4, 3));
+        assert_eq!(DateLocale::EnUs.parse("03/04/2020"), date(2020, 3, 4));
+        for locale in [DateLocale::Iso, DateLocale::PtBr, DateLocale::EnUs] {
+            assert_eq!(locale.parse("2020-04-03"), date(2020, 4, 3));
+        }
+        assert_eq!(DateLocale::PtBr.parse("31/02/2020"), None);
+
+        // tests only install the default DATE_LOCALE
+        let err = dob("03/04/2020").unwrap_err().to_string();
+        assert!(err.contains("invalid date '03/04/2020'"), "{err}");
+    }
+
+    #[test]
+    fn joined_stacks_are_only_accepted_leniently() {
+        let stacks = |json: &str, lenient| {
+            let mut deserializer = serde_json::Deserializer::from_str(json);
+            deserialize_stacks_leniently(&mut deserializer, lenient)
+                .map(|stacks| stacks.map(|stacks| stack_names(&stacks)))
+        };
+
+        for lenient in [false, true] {
+            assert_eq!(
+                stacks(r#"["rust", "go"]"#, lenient).unwrap(),
+                Some(vec!["rust".to_owned(), "go".to_owned()])
+            );
+            assert_eq!(stacks("null", lenient).unwrap(), None);
+        }
+        assert!(stacks(r#""rust, go""#, false).is_err());
+        assert_eq!(
+            stacks(r#""rust, go,, ""#, true).unwrap(),
+            Some(vec!["rust".to_owned(), "go".to_owned()])
+        );
+        assert!(stacks("1", true).is_err());
+    }
+
+    fn ana() -> Person {
+        Person {
+            id: 1,
+            nickname: "ana".to_owned(),
+            name: "Ana Barros".to_owned(),
+            dob: NaiveDate::from_ymd_opt(1985, 9, 23).unwrap(),
+            stacks: Some(vec!["rust".to_owned()]),
+            photo: Some("https://cdn.example/ana.png".to_owned()),
+            ..Person::default()
+        }
+    }
+
+    #[test]
+    fn empty_stacks_are_omitted_only_when_enabled() {
+        let stack_of = |stacks: Option<Vec<String>>, omit_empty_stacks| {
+            let person = Person { stacks, ..ana() };
+            let json = serialize_person_as(
+                &person,
+                false,
+                FieldNames::Pt,
+                omit_empty_stacks,
+                serde_json::value::Serializer,
+            )
+            .unwrap();
+            json.get("stack").cloned()
+        };
+
+        // disabled, `null` and `[]` stay apart as the Rinha contract has them
+        assert_eq!(stack_of(None, false), Some(serde_json::Value::Null));
+        assert_eq!(stack_of(Some(vec![]), false), Some(serde_json::json!([])));
+        assert_eq!(stack_of(None, true), None);
+        assert_eq!(stack_of(Some(vec![]), true), None);
+        for omit_empty_stacks in [false, true] {
+            assert_eq!(
+                stack_of(Some(vec!["rust".to_owned()]), omit_empty_stacks),
+                Some(serde_json::json!(["rust"]))
+            );
+        }
+    }
+
+    #[test]
+    fn people_serialize_with_the_field_names_of_the_mode() {
+        let pt = serialize_person_as(
+            &ana(),
+            false,
+            FieldNames::Pt,
+            false,
+            serde_json::value::Serializer,
+        );
+        assert_eq!(
+            pt.unwrap(),
+            serde_json::json!({
+                "id": 1,
+                "apelido": "ana",
+                "nome": "Ana Barros",
+                "nascimento": "1985-09-23",
+                "stack": ["rust"],
+                "foto": "https://cdn.example/ana.png",
+            })
+        );
+
+        let en = serialize_person_as(
+            &ana(),
+            false,
+            FieldNames::En,
+            false,
+            serde_json::value::Serializer,
+        );
+        assert_eq!(
+            en.unwrap(),
+            serde_json::json!({
+                "id": 1,
+                "nickname": "ana",
+                "name": "Ana Barros",
+                "birth_date": "1985-09-23",
+                "stacks": ["rust"],
+                "photo": "https://cdn.example/ana.png",
+            })
+        );
+
+        assert_eq!("EN".parse(), Ok(FieldNames::En));
+        assert_eq!("pt".parse(), Ok(FieldNames::Pt));
+        assert!("fr".parse::<FieldNames>().is_err());
+        // tests only install the default FIELD_NAMES
+        assert_eq!(serde_json::to_value(ana()).unwrap()["apelido"], "ana");
+    }
+
+    #[test]
+    fn stacks_accept_names_and_detailed_entries() {
+        let payload = serde_json::from_value::<CreatePersonPayload>(serde_json::json!({
+            "apelido": "ana",
+            "nome": "Ana Barros",
+            "nascimento": "1985-09-23",
+            "stack": ["go", { "name": "rust", "level": 3 }, { "name": "sql" }],
+        }))
+        .unwrap();
+        assert_eq!(
+            payload.stacks.as_deref().unwrap(),
+            [
+                Stack {
+                    name: "go".to_owned(),
+                    level: None
+                },
+                Stack {
+                    name: "rust".to_owned(),
+                    level: Some(3)
+                },
+                Stack {
+                    name: "sql".to_owned(),
+                    level: None
+                },
+            ]
+        );
+        assert_eq!(payload.stack_names().unwrap(), ["go", "rust", "sql"]);
+
+        let err = serde_json::from_value::<CreatePersonPayload>(serde_json::json!({
+            "apelido": "ana",
+            "nome": "Ana Barros",
+            "nascimento": "1985-09-23",
+            "stack": [{ "level": 3 }],
+        }))
+        .unwrap_err();
+        assert!(err.to_string().contains(INVALID_STACK), "{err}");
+    }
+
+    #[test]
+    fn detailed_stacks_serialize_beside_the_flat_names() {
+        let mut person = Person {
+            id: 1,
+            nickname: "ana".to_owned(),
+            name: "Ana Barros".to_owned(),
+            dob: NaiveDate::from_ymd_opt(1985, 9, 23).unwrap(),
+            stacks: Some(vec!["go".to_owned(), "rust".to_owned()]),
+            ..Person::default()
+        };
+        let json = serde_json::to_value(&person).unwrap();
+        assert_eq!(json["stack"], serde_json::json!(["go", "rust"]));
+        assert!(json.get("stack_detalhes").is_none());
+
+        person.stacks_detail = Some(sqlx::types::Json(vec![
+            Stack {
+                name: "go".to_owned(),
+                level: None,
+            },
+            Stack {
+                name: "rust".to_owned(),
+                level: Some(3),
+            },
+        ]));
+        let json = serde_json::to_value(&person).unwrap();
+        assert_eq!(json["stack"], serde_json::json!(["go", "rust"]));
+        assert_eq!(
+            json["stack_detalhes"],
+            serde_json::json!([{ "name": "go" }, { "name": "rust", "level": 3 }])
+        );
+    }
+}
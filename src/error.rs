@@ -0,0 +1,562 @@
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use hyper::StatusCode;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use utoipa::openapi::schema::{ObjectBuilder, Schema, SchemaFormat, SchemaType};
+use utoipa::openapi::RefOr;
+use utoipa::ToSchema;
+
+use crate::domain::INVALID_STACK;
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    #[allow(dead_code)]
+    NotFound {
+        resoure_name: &'static str,
+        resource_id: i64,
+    },
+    Empty {
+        resoure_name: &'static str,
+    },
+    UniqueViolation {
+        field: &'static str,
+        value: String,
+    },
+    #[allow(dead_code)]
+    Conflict {
+        reason: String,
+    },
+    /// Like [`RepositoryError::NotFound`] for resources keyed by name.
+    NotFoundByName {
+        resoure_name: &'static str,
+        name: String,
+    },
+    /// The client's `If-Match` version is no longer the stored one.
+    StaleVersion {
+        resoure_name: &'static str,
+        resource_id: i64,
+    },
+    #[allow(dead_code)]
+    Unexpected,
+}
+
+impl IntoResponse for RepositoryError {
+    fn into_response(self) -> Response {
+        let (status, response) = match self {
+            RepositoryError::NotFound {
+                resoure_name,
+                resource_id,
+            } => (
+                StatusCode::NOT_FOUND,
+                ErrorResponse {
+                    detail: format!(
+                        "Resource '{}' with id {} not found",
+                        resoure_name, resource_id
+                    ),
+                    code: "NotFound",
+                    title: "Resource not found",
+                    status: StatusCode::NOT_FOUND.as_u16(),
+                },
+            ),
+            RepositoryError::NotFoundByName { resoure_name, name } => (
+                StatusCode::NOT_FOUND,
+                ErrorResponse {
+                    detail: format!("Resource '{}' named '{}' not found", resoure_name, name),
+                    code: "NotFound",
+                    title: "Resource not found",
+                    status: StatusCode::NOT_FOUND.as_u16(),
+                },
+            ),
+            RepositoryError::Empty { resoure_name } => (
+                StatusCode::NOT_FOUND,
+                ErrorResponse {
+                    detail: format!("There are no '{}' resources", resoure_name),
+                    code: "NotFound",
+                    title: "Resource not found",
+                    status: StatusCode::NOT_FOUND.as_u16(),
+                },
+            ),
+            RepositoryError::UniqueViolation { field, value } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorResponse {
+                    status: StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                    code: "UniqueViolation",
+                    title: "Unprocessable entity",
+                    detail: format!("'{}' value '{}' is already taken", field, value),
+                },
+            ),
+            RepositoryError::Conflict { reason } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorResponse {
+                    status: StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                    code: "Conflict",
+                    title: "Unprocessable entity",
+                    detail: format!("Conflict due to {}", reason),
+                },
+            ),
+            RepositoryError::StaleVersion {
+                resoure_name,
+                resource_id,
+            } => (
+                StatusCode::CONFLICT,
+                ErrorResponse {
+                    status: StatusCode::CONFLICT.as_u16(),
+                    code: "StaleVersion",
+                    title: "Resource was modified",
+                    detail: format!(
+                        "Resource '{}' with id {} changed since the version in If-Match",
+                        resoure_name, resource_id
+                    ),
+                },
+            ),
+            RepositoryError::Unexpected => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorResponse {
+                    status: StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                    code: "Unexpected",
+                    title: "Internal Server Error",
+                    detail: "Unexpected error".to_owned(),
+                },
+            ),
+        };
+
+        (status, Json(response)).into_response()
+    }
+}
+
+impl From<RepositoryError> for AppError {
+    fn from(value: RepositoryError) -> Self {
+        AppError::Repo(value)
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(value: QueryRejection) -> Self {
+        AppError::InvalidQuery(value.body_text())
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(value: JsonRejection) -> Self {
+        AppError::InvalidJsonRequest(value)
+    }
+}
+
+#[derive(Debug)]
+pub enum AppError {
+    Repo(RepositoryError),
+    InvalidJsonRequest(JsonRejection),
+    InvalidQuery(String),
+    Validation(Vec<String>),
+    /// The body could not be read, e.g. it was shorter than its `Content-Length`.
+    MalformedBody(String),
+    Unauthorized,
+    /// `Accept` allows none of the media types the route can answer with.
+    NotAcceptable {
+        supported: &'static [&'static str],
+    },
+    UnsupportedMediaType {
+        expected: &'static str,
+    },
+    /// A dependency such as the database can't be reached.
+    ServiceUnavailable(&'static str),
+    /// A search matched nobody, with `SEARCH_EMPTY_STATUS=404`.
+    NoResults,
+    /// The client used up its [`RateLimiter`] budget.
+    RateLimited,
+    /// An `Idempotency-Key` was already used with another payload.
+    IdempotencyKeyReused,
+    InvalidHeader(String),
+}
+
+/// Problem details (RFC 7807) body of every error response.
+pub(crate) struct ErrorResponse {
+    pub status: u16,
+    /// Machine name of the error, also the last segment of its `type` URI.
+    pub code: &'static str,
+    pub title: &'static str,
+    pub detail: String,
+}
+
+/// How much of a rejected JSON body's parse error is echoed back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ErrorDetail {
+    /// The full serde error, which includes field names and positions.
+    Verbose,
+    /// A generic message that does not reveal the payload structure.
+    Minimal,
+}
+
+impl Default for ErrorDetail {
+    /// Verbose in debug builds, minimal in release ones.
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            ErrorDetail::Verbose
+        } else {
+            ErrorDetail::Minimal
+        }
+    }
+}
+
+impl FromStr for ErrorDetail {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "verbose" => Ok(ErrorDetail::Verbose),
+            "minimal" => Ok(ErrorDetail::Minimal),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Set by [`crate::install_settings`] from [`Config::error_detail`].
+pub(crate) static ERROR_DETAIL: OnceLock<ErrorDetail> = OnceLock::new();
+
+pub(crate) fn rejection_detail(rejection: &JsonRejection, minimal: &str) -> String {
+    rejection_detail_as(
+        ERROR_DETAIL.get().copied().unwrap_or_default(),
+        rejection,
+        minimal,
+    )
+}
+
+pub(crate) fn rejection_detail_as(
+    mode: ErrorDetail,
+    rejection: &JsonRejection,
+    minimal: &str,
+) -> String {
+    match mode {
+        ErrorDetail::Verbose => rejection.body_text(),
+        ErrorDetail::Minimal => minimal.to_owned(),
+    }
+}
+
+pub(crate) fn is_type_error(rejection: &JsonRejection) -> bool {
+    let JsonRejection::JsonDataError(err) = rejection else {
+        return false;
+    };
+    is_type_mismatch(&err.body_text())
+}
+
+/// Whether a serde error is about a field of the wrong type rather than a missing or null one.
+pub(crate) fn is_type_mismatch(text: &str) -> bool {
+    (text.contains("invalid type: ") && !text.contains("invalid type: null"))
+        || text.contains(INVALID_STACK)
+}
+
+/// Set by [`crate::install_settings`] from [`Config::error_type_base_url`].
+pub(crate) static ERROR_TYPE_BASE_URL: OnceLock<String> = OnceLock::new();
+
+pub(crate) const DEFAULT_ERROR_TYPE_BASE_URL: &str = "https://errors.example";
+
+/// [`error_type_uri_under`] the [`ERROR_TYPE_BASE_URL`].
+pub(crate) fn error_type_uri(code: &str) -> String {
+    let base = ERROR_TYPE_BASE_URL
+        .get()
+        .map_or(DEFAULT_ERROR_TYPE_BASE_URL, String::as_str);
+    error_type_uri_under(base, code)
+}
+
+/// `NotFound` becomes `<base>/not-found`.
+pub(crate) fn error_type_uri_under(base: &str, code: &str) -> String {
+    let base = base.trim_end_matches('/');
+    let mut uri = format!("{base}/");
+    for (i, c) in code.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                uri.push('-');
+            }
+            uri.push(c.to_ascii_lowercase());
+        } else {
+            uri.push(c);
+        }
+    }
+    uri
+}
+
+impl Serialize for ErrorResponse {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut res = serializer.serialize_struct("ErrorResponse", 5)?;
+        res.serialize_field("status", &self.status)?;
+        res.serialize_field("type", &error_type_uri(self.code))?;
+        res.serialize_field("code", self.code)?;
+        res.serialize_field("title", self.title)?;
+        res.serialize_field("detail", &self.detail)?;
+        res.end()
+    }
+}
+
+impl<'s> ToSchema<'s> for ErrorResponse {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        let string = || ObjectBuilder::new().schema_type(SchemaType::String);
+        let res = ObjectBuilder::new()
+            .property(
+                "status",
+                ObjectBuilder::new().schema_type(SchemaType::Integer),
+            )
+            .property(
+                "type",
+                string().format(Some(SchemaFormat::Custom("uri".to_owned()))),
+            )
+            .property("code", string())
+            .property("title", string())
+            .property("detail", string())
+            .required("status")
+            .required("type")
+            .required("code")
+            .required("title")
+            .required("detail")
+            .description(Some("Problem details (RFC 7807)"));
+        ("ErrorResponse", res.into())
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        match self {
+            AppError::Repo(inner) => inner.into_response(),
+            // the body itself could not be read, e.g. it was shorter than its Content-Length
+            AppError::InvalidJsonRequest(rejection @ JsonRejection::BytesRejection(_))
+                if rejection.status() == StatusCode::BAD_REQUEST =>
+            {
+                let res = ErrorResponse {
+                    status: StatusCode::BAD_REQUEST.as_u16(),
+                    code: "BadRequest",
+                    title: "Malformed request body",
+                    detail: rejection_detail(&rejection, "Malformed request body"),
+                };
+                (StatusCode::BAD_REQUEST, Json(res)).into_response()
+            }
+            // as the Rinha spec wants, a body that isn't JSON or has a field of the wrong
+            // type (`"nome": 1`) is a 400, while a missing or null field stays a 422
+            AppError::InvalidJsonRequest(rejection)
+                if matches!(rejection, JsonRejection::JsonSyntaxError(_))
+                    || is_type_error(&rejection) =>
+            {
+                let res = ErrorResponse {
+                    status: StatusCode::BAD_REQUEST.as_u16(),
+                    code: "BadRequest",
+                    title: "Malformed request payload",
+                    detail: rejection_detail(&rejection, "Malformed request payload"),
+                };
+                (StatusCode::BAD_REQUEST, Json(res)).into_response()
+            }
+            // the body went past `MAX_BODY_BYTES`
+            AppError::InvalidJsonRequest(rejection)
+                if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE =>
+            {
+                let res = ErrorResponse {
+                    status: StatusCode::PAYLOAD_TOO_LARGE.as_u16(),
+                    code: "PayloadTooLarge",
+                    title: "Request payload too large",
+                    detail: "Request body is larger than the server accepts".to_owned(),
+                };
+                (StatusCode::PAYLOAD_TOO_LARGE, Json(res)).into_response()
+            }
+            AppError::InvalidJsonRequest(rejection) => {
+                let res = ErrorResponse {
+                    status: StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                    code: "UnprocessableEntity",
+                    title: "Invalid request payload",
+                    detail: rejection_detail(&rejection, "Invalid request payload"),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, Json(res)).into_response()
+            }
+            AppError::InvalidQuery(detail) => {
+                let res = ErrorResponse {
+                    status: StatusCode::BAD_REQUEST.as_u16(),
+                    code: "BadRequest",
+                    title: "Invalid query string",
+                    detail,
+                };
+                (StatusCode::BAD_REQUEST, Json(res)).into_response()
+            }
+            AppError::MalformedBody(detail) => {
+                let res = ErrorResponse {
+                    status: StatusCode::BAD_REQUEST.as_u16(),
+                    code: "BadRequest",
+                    title: "Malformed request body",
+                    detail,
+                };
+                (StatusCode::BAD_REQUEST, Json(res)).into_response()
+            }
+            AppError::InvalidHeader(detail) => {
+                let res = ErrorResponse {
+                    status: StatusCode::BAD_REQUEST.as_u16(),
+                    code: "BadRequest",
+                    title: "Invalid request header",
+                    detail,
+                };
+                (StatusCode::BAD_REQUEST, Json(res)).into_response()
+            }
+            AppError::Validation(errors) => {
+                let res = ErrorResponse {
+                    status: StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                    code: "ValidationError",
+                    title: "Invalid request payload",
+                    detail: errors.join("; "),
+                };
+                (StatusCode::UNPROCESSABLE_ENTITY, Json(res)).into_response()
+            }
+            AppError::NotAcceptable { supported } => {
+                let supported: Vec<String> = supported.iter().map(|t| format!("'{t}'")).collect();
+                let res = ErrorResponse {
+                    status: StatusCode::NOT_ACCEPTABLE.as_u16(),
+                    code: "NotAcceptable",
+                    title: "Not acceptable",
+                    detail: format!("Responses are only available as {}", supported.join(", ")),
+                };
+                (StatusCode::NOT_ACCEPTABLE, Json(res)).into_response()
+            }
+            AppError::UnsupportedMediaType { expected } => {
+                let res = ErrorResponse {
+                    status: StatusCode::UNSUPPORTED_MEDIA_TYPE.as_u16(),
+                    code: "UnsupportedMediaType",
+                    title: "Unsupported media type",
+                    detail: format!("Expected Content-Type '{}'", expected),
+                };
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(res)).into_response()
+            }
+            AppError::Unauthorized => {
+                let res = ErrorResponse {
+                    status: StatusCode::UNAUTHORIZED.as_u16(),
+                    code: "Unauthorized",
+                    title: "Unauthorized",
+                    detail: "Missing or invalid credentials".to_owned(),
+                };
+                (StatusCode::UNAUTHORIZED, Json(res)).into_response()
+            }
+            AppError::NoResults => {
+                let res = ErrorResponse {
+                    status: StatusCode::NOT_FOUND.as_u16(),
+                    code: "NoResults",
+                    title: "No results",
+                    detail: "No person matches the search".to_owned(),
+                };
+                (StatusCode::NOT_FOUND, Json(res)).into_response()
+            }
+            AppError::RateLimited => {
+                let res = ErrorResponse {
+                    status: StatusCode::TOO_MANY_REQUESTS.as_u16(),
+                    code: "RateLimited",
+                    title: "Too many requests",
+                    detail: "Too many requests, retry after the Retry-After delay".to_owned(),
+                };
+                (StatusCode::TOO_MANY_REQUESTS, Json(res)).into_response()
+            }
+            AppError::IdempotencyKeyReused => {
+                let res = ErrorResponse {
+                    status: StatusCode::CONFLICT.as_u16(),
+                    code: "IdempotencyKeyReused",
+                    title: "Idempotency key reused",
+                    detail: "Idempotency-Key was already used with a different payload".to_owned(),
+                };
+                (StatusCode::CONFLICT, Json(res)).into_response()
+            }
+            AppError::ServiceUnavailable(detail) => {
+                let res = ErrorResponse {
+                    status: StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+                    code: "ServiceUnavailable",
+                    title: "Service unavailable",
+                    detail: detail.to_owned(),
+                };
+                (StatusCode::SERVICE_UNAVAILABLE, Json(res)).into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::body_json;
+
+    #[test]
+    fn error_types_are_uris_named_after_the_code() {
+        assert_eq!(
+            error_type_uri_under("https://errors.example", "NotFound"),
+            "https://errors.example/not-found"
+        );
+        assert_eq!(
+            error_type_uri_under("https://api.example/problems/", "UniqueViolation"),
+            "https://api.example/problems/unique-violation"
+        );
+        assert_eq!(
+            error_type_uri_under("https://errors.example", "Unexpected"),
+            "https://errors.example/unexpected"
+        );
+    }
+
+    async fn rejection(body: &'static str) -> JsonRejection {
+        let req = axum::http::Request::post("/")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        <Json<serde_json::Value> as axum::extract::FromRequest<(), axum::body::Body>>::from_request(
+            req,
+            &(),
+        )
+        .await
+        .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn rejections_are_detailed_only_in_verbose_mode() {
+        let rejection = rejection(r#"{"apelido": "#).await;
+
+        let verbose =
+            rejection_detail_as(ErrorDetail::Verbose, &rejection, "Invalid request payload");
+        assert_eq!(verbose, rejection.body_text());
+        assert!(verbose.contains("line 1"), "{verbose}");
+
+        let minimal =
+            rejection_detail_as(ErrorDetail::Minimal, &rejection, "Invalid request payload");
+        assert_eq!(minimal, "Invalid request payload");
+
+        assert_eq!("VERBOSE".parse(), Ok(ErrorDetail::Verbose));
+        assert_eq!("minimal".parse(), Ok(ErrorDetail::Minimal));
+        assert_eq!("terse".parse::<ErrorDetail>(), Err(()));
+    }
+
+    #[tokio::test]
+    async fn error_bodies_carry_both_the_type_uri_and_the_code() {
+        let res = AppError::NoResults.into_response();
+        let error = body_json(res).await;
+        // tests only install the default ERROR_TYPE_BASE_URL
+        assert_eq!(error["type"], "https://errors.example/no-results");
+        assert_eq!(error["code"], "NoResults");
+        assert_eq!(error["status"], 404);
+        assert_eq!(error["title"], "No results");
+    }
+
+    #[tokio::test]
+    async fn unique_violations_and_conflicts_have_their_own_type() {
+        let res = RepositoryError::UniqueViolation {
+            field: "apelido",
+            value: "ana".to_owned(),
+        }
+        .into_response();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let unique = body_json(res).await;
+        assert_eq!(unique["code"], "UniqueViolation");
+        assert_eq!(unique["detail"], "'apelido' value 'ana' is already taken");
+
+        let res = RepositoryError::Conflict {
+            reason: "a constraint".to_owned(),
+        }
+        .into_response();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let conflict = body_json(res).await;
+        assert_eq!(conflict["code"], "Conflict");
+        assert_ne!(unique["type"], conflict["type"]);
+    }
+}
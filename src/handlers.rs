@@ -0,0 +1,3932 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::body::StreamBody;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{BodyStream, FromRef, FromRequest, FromRequestParts, Path, Query, State};
+use axum::http::header::{
+    HeaderName, HeaderValue, ACCEPT, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE, ETAG,
+    IF_MATCH, IF_NONE_MATCH, LOCATION,
+};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, Request};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::{async_trait, BoxError, Json};
+use chrono::{NaiveDate, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::StreamExt;
+use hyper::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tracing::{error, info, warn};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+
+use crate::app::{
+    is_admin, DynPersonRepo, PersonEvents, ReindexJob, ReindexState, ReindexStatus, ShutdownNotice,
+};
+use crate::config::{Config, EmptySearch};
+use crate::domain::{
+    revalidate_person, AdvancedSearch, AdvancedSearchQuery, AgeStats, BatchItemResult,
+    BatchPayload, BirthYearCount, CountBreakdown, CountQuery, CreatePersonPayload, DashboardStats,
+    DbInfo, Dob, DuplicateCandidate, DuplicatesQuery, FieldMatch, IdempotentCreation, LimitQuery,
+    ListPersonQuery, MaskedPerson, MatchMode, NicknamesPayload, Page, Person, PersonMergePatch,
+    PersonPage, PhotoPayload, PurgeQuery, PurgeResult, RevalidationFailure, RevalidationPage,
+    SearchPersonQuery, SelfTestReport, SelfTestStep, SortCursor, SortOrder, Stack, StackCount,
+    StackMeta, StackMetaPayload, StackRemovalPayload, TimelinePage, TimelineQuery, TrendingQuery,
+    DEFAULT_PAGE_LIMIT, DEFAULT_PURGE_OLDER_THAN_DAYS, DEFAULT_SEARCH_LIMIT, DEFAULT_SIMILAR_LIMIT,
+    DEFAULT_TRENDING_DAYS, MAX_PAGE_LIMIT, MAX_SIMILAR_LIMIT, MAX_TRENDING_DAYS,
+};
+use crate::error::{is_type_mismatch, AppError, ErrorResponse, RepositoryError};
+use crate::repository::{normalize_search_term, SearchTerms};
+
+pub(crate) static RESULTS_TRUNCATED: HeaderName = HeaderName::from_static("x-results-truncated");
+pub(crate) static TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+pub(crate) static NEXT_CURSOR: HeaderName = HeaderName::from_static("x-next-cursor");
+pub(crate) static API_VERSION: HeaderName = HeaderName::from_static("x-api-version");
+pub(crate) static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+pub(crate) static IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Longest `Idempotency-Key` accepted.
+pub(crate) const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// Serializes `items` as a JSON array, dropping trailing items once the encoded
+/// body would exceed `budget` bytes. Returns the body and how many items it holds.
+pub(crate) fn json_array_within_budget<T: Serialize>(
+    items: &[T],
+    budget: usize,
+) -> (Vec<u8>, usize) {
+    let mut body = vec![b'['];
+
+    for (kept, item) in items.iter().enumerate() {
+        let encoded = serde_json::to_vec(item).expect("response types always serialize");
+        // one byte for the separator (or closing bracket)
+        if body.len() + encoded.len() + 1 > budget {
+            body.push(b']');
+            return (body, kept);
+        }
+        if body.len() > 1 {
+            body.push(b',');
+        }
+        body.extend(encoded);
+    }
+
+    body.push(b']');
+    (body, items.len())
+}
+
+/// Renders search results as CSV, with the stacks joined by `;` in a single cell.
+pub(crate) fn people_to_csv(people: &[Person]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    for person in people {
+        csv.push_str(&csv_row(person));
+    }
+    csv
+}
+
+pub(crate) const CSV_HEADER: &str = "apelido,nome,nascimento,stack\r\n";
+
+pub(crate) fn csv_row(person: &Person) -> String {
+    let stacks = person.stacks.as_deref().unwrap_or_default().join(";");
+    let row = [
+        csv_field(&person.nickname),
+        csv_field(&person.name),
+        person.dob.to_string(),
+        csv_field(&stacks),
+    ];
+    row.join(",") + "\r\n"
+}
+
+/// Quotes a field as in RFC 4180 when it contains a separator, quote or line break.
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Picks the first of `supported` the client prefers per `Accept`, ranges ordered by
+/// their `q` and `q=0` ones ignored, or the first of `supported` without `Accept`.
+pub(crate) fn negotiate(
+    headers: &HeaderMap,
+    supported: &'static [&'static str],
+) -> Result<&'static str, AppError> {
+    let mut ranges: Vec<(&str, f32)> = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|accept| accept.split(','))
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media_range = parts.next().filter(|r| !r.is_empty())?;
+            let q = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(1.0, |q| q.parse().unwrap_or(0.0));
+            (q > 0.0).then_some((media_range, q))
+        })
+        .collect();
+    if ranges.is_empty() && headers.get(ACCEPT).is_none() {
+        return Ok(supported[0]);
+    }
+    // stable, so equally preferred ranges keep the client's order
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges
+        .iter()
+        .find_map(|(range, _)| {
+            supported
+                .iter()
+                .find(|media_type| media_range_matches(range, media_type))
+        })
+        .copied()
+        .ok_or(AppError::NotAcceptable { supported })
+}
+
+pub(crate) fn media_range_matches(range: &str, media_type: &str) -> bool {
+    match range.strip_suffix("/*") {
+        Some("*") => true,
+        Some(main_type) => media_type.split('/').next() == Some(main_type),
+        None => range.eq_ignore_ascii_case(media_type),
+    }
+}
+
+pub(crate) const SEARCH_MEDIA_TYPES: &[&str] = &["application/json", "text/csv"];
+
+pub(crate) fn accepts_csv(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/csv"))
+}
+
+/// Request payloads whose deserialization alone does not cover every rule.
+pub(crate) trait Validate {
+    fn validate(&self, config: &Config) -> Result<(), Vec<String>>;
+}
+
+/// A JSON body that was deserialized and then [validated](Validate),
+/// either failure is rejected with our error envelope before the handler runs.
+pub(crate) struct ValidatedJson<T>(pub(crate) T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidatedJson<T>
+where
+    T: Validate,
+    Json<T>: FromRequest<S, B, Rejection = JsonRejection>,
+    Arc<Config>: FromRef<S>,
+    S: Send + Sync,
+    B: Send + 'static,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        value
+            .validate(&Arc::<Config>::from_ref(state))
+            .map_err(AppError::Validation)?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+/// A query string where every parameter appears at most once, so `?t=a&t=b` is
+/// rejected up front instead of picking either value. Failures use our error envelope.
+pub(crate) struct StrictQuery<T>(pub(crate) T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for StrictQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let raw = parts.uri.query().unwrap_or_default();
+        let mut seen = HashSet::new();
+        for (key, _) in url::form_urlencoded::parse(raw.as_bytes()) {
+            if !seen.insert(key.clone()) {
+                return Err(AppError::InvalidQuery(format!(
+                    "query parameter '{key}' must not be repeated"
+                )));
+            }
+        }
+        let Query(value) = Query::<T>::from_request_parts(parts, state).await?;
+        Ok(StrictQuery(value))
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct GetPersonQuery {
+    /// Counts the read in the person's `view_count`.
+    #[serde(default)]
+    pub(crate) track: bool,
+    /// Answers with a [`MaskedPerson`], for public profile views.
+    #[serde(default)]
+    pub(crate) mask: bool,
+    /// Also finds soft deleted people, for admins only.
+    #[serde(default)]
+    pub(crate) include_deleted: bool,
+}
+
+/// Reads a person.
+///
+/// Answers 304 without a body when `If-None-Match` holds the person's current [`etag`].
+#[utoipa::path(
+    get,
+    path = "/pessoas/{id}",
+    params(
+        GetPersonQuery,
+        ("If-None-Match" = Option<String>, Header, description = "Answers 304 when it holds the current `ETag`"),
+    ),
+    responses(
+        (status = 200, description = "The person", body = Person),
+        (status = 304, description = "Not modified since the `If-None-Match` version"),
+        (status = 404, description = "No such person", body = ErrorResponse),
+        (status = 401, description = "`include_deleted` without the admin token", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn get_person(
+    Path(id): Path<i64>,
+    StrictQuery(query): StrictQuery<GetPersonQuery>,
+    State(repo): State<DynPersonRepo>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if query.include_deleted && !is_admin(&config, &headers) {
+        return Err(AppError::Unauthorized);
+    }
+    let person = match (query.include_deleted, query.track) {
+        (true, _) => repo.get_person_including_deleted(id).await?,
+        (false, true) => repo.get_and_track(id).await?,
+        (false, false) => repo.get_person(id).await?,
+    };
+    let etag = etag(&person);
+    let cache_control = match config.person_max_age {
+        Duration::ZERO => HeaderValue::from_static("no-cache"),
+        max_age => HeaderValue::try_from(format!("max-age={}", max_age.as_secs()))
+            .expect("a max-age is a valid header value"),
+    };
+    if if_none_match(&headers, &etag) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(ETAG, etag), (CACHE_CONTROL, cache_control)],
+        )
+            .into_response());
+    }
+    let headers = [(ETAG, etag), (CACHE_CONTROL, cache_control)];
+    if query.mask {
+        return Ok((headers, Json(MaskedPerson(person))).into_response());
+    }
+    Ok((headers, Json(person)).into_response())
+}
+
+/// A strong entity tag of the person's version, e.g. `"3"`.
+pub(crate) fn etag(person: &Person) -> HeaderValue {
+    HeaderValue::try_from(format!("\"{}\"", person.version))
+        .expect("a quoted number is a valid header value")
+}
+
+/// Whether `If-None-Match` lists `etag` or is `*`, compared weakly as RFC 9110 asks.
+pub(crate) fn if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Some(value) = headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let etag = etag.to_str().unwrap_or_default();
+    value.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+/// The version in `If-Match`, `None` when absent or `*` (any version).
+pub(crate) fn if_match_version(headers: &HeaderMap) -> Result<Option<i64>, AppError> {
+    let Some(value) = headers.get(IF_MATCH) else {
+        return Ok(None);
+    };
+    let invalid = || AppError::InvalidHeader("If-Match must be a version such as \"3\"".to_owned());
+    let value = value.to_str().map_err(|_| invalid())?.trim();
+    if value == "*" {
+        return Ok(None);
+    }
+    let version = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    version.parse().map(Some).map_err(|_| invalid())
+}
+
+/// Soft deletes a person.
+///
+/// Honors `If-Match`, answering 409 when the person changed since the client read it.
+#[utoipa::path(
+    delete,
+    path = "/pessoas/{id}",
+    params(
+        ("If-Match" = Option<String>, Header, description = "Only deletes the person still at this version"),
+    ),
+    responses(
+        (status = 204, description = "Soft deleted, its nickname free again"),
+        (status = 404, description = "No such person", body = ErrorResponse),
+        (status = 409, description = "The person changed since the `If-Match` version", body = ErrorResponse),
+        (status = 401, description = "`WRITE_AUTH` rejected the credentials", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+    security((), ("api_key" = []), ("jwt" = [])),
+)]
+pub(crate) async fn delete_person(
+    Path(id): Path<i64>,
+    State(repo): State<DynPersonRepo>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    let version = if_match_version(&headers)?;
+    repo.delete_person(id, version).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Creates a person, `Location` pointing to it.
+///
+/// With an `Idempotency-Key` a retry of the same payload answers the person created
+/// the first time, flagged with `Idempotent-Replayed: true`, and another payload a 409.
+#[utoipa::path(
+    post,
+    path = "/pessoas",
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key and payload answer the first creation, within `IDEMPOTENCY_KEY_TTL_SECS`"),
+    ),
+    request_body = CreatePersonPayload,
+    responses(
+        (status = 201, description = "Created", body = Person),
+        (status = 400, description = "Malformed JSON or a field of the wrong type", body = ErrorResponse),
+        (status = 422, description = "Missing, null or invalid field, or nickname already taken", body = ErrorResponse),
+        (status = 409, description = "`Idempotency-Key` already used with another payload", body = ErrorResponse),
+        (status = 413, description = "Body larger than `MAX_BODY_BYTES`", body = ErrorResponse),
+        (status = 401, description = "`WRITE_AUTH` rejected the credentials", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+    security((), ("api_key" = []), ("jwt" = [])),
+)]
+pub(crate) async fn create_person(
+    State(repo): State<DynPersonRepo>,
+    State(events): State<PersonEvents>,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<CreatePersonPayload>,
+) -> Result<Response, AppError> {
+    let Some(key) = idempotency_key(&headers)? else {
+        let person = repo.create_person(payload).await?;
+        events.publish(&person).await;
+        return Ok(created(person));
+    };
+
+    let fingerprint = payload_fingerprint(&payload);
+    match repo
+        .create_person_idempotently(key, fingerprint.clone(), payload)
+        .await?
+    {
+        IdempotentCreation::Created(person) => {
+            events.publish(&person).await;
+            Ok(created(person))
+        }
+        IdempotentCreation::Existing(record) if record.fingerprint != fingerprint => {
+            Err(AppError::IdempotencyKeyReused)
+        }
+        IdempotentCreation::Existing(record) => {
+            let mut res = created(repo.get_person(record.person_id).await?);
+            res.headers_mut().insert(
+                IDEMPOTENT_REPLAYED.clone(),
+                HeaderValue::from_static("true"),
+            );
+            Ok(res)
+        }
+    }
+}
+
+fn created(person: Person) -> Response {
+    let location = format!("/pessoas/{}", person.id);
+    (StatusCode::CREATED, [(LOCATION, location)], Json(person)).into_response()
+}
+
+/// The `Idempotency-Key` header, which must be 1 to [`MAX_IDEMPOTENCY_KEY_LEN`] visible characters.
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, AppError> {
+    let Some(value) = headers.get(&IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN => {
+            Ok(Some(key.to_owned()))
+        }
+        _ => Err(AppError::InvalidHeader(format!(
+            "Idempotency-Key must be 1 to {} visible ASCII characters",
+            MAX_IDEMPOTENCY_KEY_LEN
+        ))),
+    }
+}
+
+/// Hex SHA-256 of the parsed payload, so retries differing only in formatting match.
+fn payload_fingerprint(payload: &CreatePersonPayload) -> String {
+    let json = serde_json::to_vec(payload).expect("payloads always serialize");
+    Sha256::digest(json)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Creates up to 1000 people at once.
+///
+/// Answers 207 with the [`BatchItemResult`] of every entry, the valid ones are created
+/// with a single `INSERT`.
+#[utoipa::path(
+    post,
+    path = "/pessoas/batch",
+    request_body = [CreatePersonPayload],
+    responses(
+        (status = 207, description = "The outcome of every entry, in order", body = [BatchItemResult]),
+        (status = 422, description = "Empty batch or more than 1000 people", body = ErrorResponse),
+        (status = 413, description = "Body larger than `MAX_BODY_BYTES`", body = ErrorResponse),
+        (status = 401, description = "`WRITE_AUTH` rejected the credentials", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+    security((), ("api_key" = []), ("jwt" = [])),
+)]
+pub(crate) async fn create_people_batch(
+    State(repo): State<DynPersonRepo>,
+    State(events): State<PersonEvents>,
+    State(config): State<Arc<Config>>,
+    ValidatedJson(BatchPayload(entries)): ValidatedJson<BatchPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut results = Vec::with_capacity(entries.len());
+    let mut valid = Vec::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let parsed = serde_json::from_value::<CreatePersonPayload>(entry)
+            .map_err(|err| {
+                let err = err.to_string();
+                let status = match is_type_mismatch(&err) {
+                    true => StatusCode::BAD_REQUEST,
+                    false => StatusCode::UNPROCESSABLE_ENTITY,
+                };
+                (status, vec![err])
+            })
+            .and_then(|payload| match payload.validate(&config) {
+                Ok(()) => Ok(payload),
+                Err(errors) => Err((StatusCode::UNPROCESSABLE_ENTITY, errors)),
+            });
+        match parsed {
+            Ok(payload) => valid.push((index, payload)),
+            Err((status, errors)) => results.push(BatchItemResult {
+                index,
+                status: status.as_u16(),
+                id: None,
+                errors,
+            }),
+        }
+    }
+
+    let (indexes, payloads): (Vec<_>, Vec<_>) = valid.into_iter().unzip();
+    let nicknames: Vec<String> = payloads.iter().map(|p| p.nickname.clone()).collect();
+    let created = match payloads.is_empty() {
+        true => Vec::new(),
+        false => repo.create_people_bulk(payloads).await?,
+    };
+    for ((index, nickname), person) in indexes.into_iter().zip(nicknames).zip(created) {
+        if let Some(person) = &person {
+            events.publish(person).await;
+        }
+        results.push(match person {
+            Some(person) => BatchItemResult {
+                index,
+                status: StatusCode::CREATED.as_u16(),
+                id: Some(person.id),
+                errors: Vec::new(),
+            },
+            None => BatchItemResult {
+                index,
+                status: StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                id: None,
+                errors: vec![format!("'apelido' value '{nickname}' is already taken")],
+            },
+        });
+    }
+    results.sort_by_key(|result| result.index);
+    Ok((StatusCode::MULTI_STATUS, Json(results)))
+}
+
+/// People sent to the import writer at a time, also the size of each `INSERT`.
+pub(crate) const IMPORT_BATCH_SIZE: usize = 500;
+/// Only the first invalid lines are detailed so the report stays small for huge imports.
+pub(crate) const IMPORT_MAX_REPORTED_ERRORS: usize = 100;
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ImportQuery {
+    /// Reports what the import would do without keeping any of it.
+    #[serde(default)]
+    pub(crate) dry_run: bool,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub(crate) struct ImportReport {
+    pub(crate) dry_run: bool,
+    /// With a dry run, how many would have been imported.
+    pub(crate) imported: u64,
+    /// Valid lines whose nickname was already taken.
+    pub(crate) duplicates: u64,
+    pub(crate) invalid: u64,
+    pub(crate) errors: Vec<ImportLineError>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct ImportLineError {
+    pub(crate) line: usize,
+    pub(crate) error: String,
+}
+
+pub(crate) fn parse_import_line(
+    line: &[u8],
+    config: &Config,
+) -> Result<CreatePersonPayload, String> {
+    let payload: CreatePersonPayload =
+        serde_json::from_slice(line).map_err(|err| err.to_string())?;
+    payload
+        .validate(config)
+        .map_err(|errors| errors.join("; "))?;
+    Ok(payload)
+}
+
+/// Aborts the task when dropped, e.g. along with the request that spawned it.
+pub(crate) struct AbortOnDrop<T>(pub(crate) JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Imports newline delimited JSON people (`application/x-ndjson`) as the body streams in.
+///
+/// Parsed lines are handed in batches to a writer task through a bounded channel,
+/// so while the database is busy the body stops being read and memory stays flat.
+/// The import is all or nothing: the writer only keeps the people once told the body
+/// was read to the end, so a body cut short or a request dropped midway imports nobody.
+#[utoipa::path(
+    post,
+    path = "/pessoas/import",
+    params(ImportQuery),
+    request_body(content = String, content_type = "application/x-ndjson", description = "One person per line, as in `POST /pessoas`"),
+    responses(
+        (status = 200, description = "What was imported, and why the other lines weren't", body = ImportReport),
+        (status = 400, description = "The body could not be read to the end", body = ErrorResponse),
+        (status = 422, description = "A line longer than `MAX_BODY_BYTES`", body = ErrorResponse),
+        (status = 401, description = "`WRITE_AUTH` rejected the credentials", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+    security((), ("api_key" = []), ("jwt" = [])),
+)]
+pub(crate) async fn import_people(
+    StrictQuery(query): StrictQuery<ImportQuery>,
+    State(repo): State<DynPersonRepo>,
+    State(config): State<Arc<Config>>,
+    body: BodyStream,
+) -> Result<Json<ImportReport>, AppError> {
+    let (tx, rx) = mpsc::channel::<Vec<CreatePersonPayload>>(2);
+    let (done, finished) = oneshot::channel();
+    let mut writer = AbortOnDrop(tokio::spawn(async move {
+        repo.import_people(rx, finished, query.dry_run).await
+    }));
+
+    let mut report = ImportReport {
+        dry_run: query.dry_run,
+        ..Default::default()
+    };
+    let read = read_import(body, &tx, &config, &mut report).await;
+    if read.is_ok() {
+        let _ = done.send(());
+    } else {
+        // closed without `finished`, the writer rolls back
+        drop(done);
+    }
+    drop(tx);
+
+    let written = (&mut writer.0).await.map_err(|err| {
+        error!(%err, "import writer failed");
+        AppError::from(RepositoryError::Unexpected)
+    });
+    read?;
+    let (sent, inserted) = written??;
+    report.imported = inserted;
+    report.duplicates = sent - inserted;
+    Ok(report.into())
+}
+
+/// Sends the valid lines of `body` to the import writer through `tx`, and the invalid
+/// ones to `report`.
+pub(crate) async fn read_import(
+    mut body: BodyStream,
+    tx: &mpsc::Sender<Vec<CreatePersonPayload>>,
+    config: &Config,
+    report: &mut ImportReport,
+) -> Result<(), AppError> {
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut pending = Vec::new();
+    let mut line_number = 0;
+    let mut body_done = false;
+
+    'read: while !body_done {
+        match body.next().await {
+            Some(chunk) => {
+                let chunk = chunk.map_err(|err| AppError::MalformedBody(err.to_string()))?;
+                pending.extend_from_slice(&chunk);
+            }
+            None => {
+                // the last line may not end with a newline
+                pending.push(b'\n');
+                body_done = true;
+            }
+        }
+
+        let mut consumed = 0;
+        while let Some(len) = pending[consumed..].iter().position(|b| *b == b'\n') {
+            let line = &pending[consumed..consumed + len];
+            consumed += len + 1;
+            line_number += 1;
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+
+            match parse_import_line(line, config) {
+                Ok(payload) => batch.push(payload),
+                Err(error) => {
+                    report.invalid += 1;
+                    if report.errors.len() < IMPORT_MAX_REPORTED_ERRORS {
+                        report.errors.push(ImportLineError {
+                            line: line_number,
+                            error,
+                        });
+                    }
+                }
+            }
+            // fails only if the writer stopped, its error is returned below
+            if batch.len() == IMPORT_BATCH_SIZE
+                && tx.send(std::mem::take(&mut batch)).await.is_err()
+            {
+                break 'read;
+            }
+        }
+        pending.drain(..consumed);
+
+        if pending.len() > config.max_body_bytes {
+            return Err(AppError::Validation(vec![format!(
+                "line {} is longer than {} bytes",
+                line_number + 1,
+                config.max_body_bytes
+            )]));
+        }
+    }
+
+    if !batch.is_empty() {
+        // same as above, a failed writer reports its own error
+        let _ = tx.send(batch).await;
+    }
+    Ok(())
+}
+
+/// The people with any of the nicknames, compared ignoring case.
+#[utoipa::path(
+    post,
+    path = "/pessoas/by-nicknames",
+    request_body = NicknamesPayload,
+    responses(
+        (status = 200, description = "The people found", body = [Person]),
+        (status = 400, description = "Malformed JSON or a field of the wrong type", body = ErrorResponse),
+        (status = 422, description = "More than 100 nicknames", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn get_by_nicknames(
+    State(repo): State<DynPersonRepo>,
+    ValidatedJson(payload): ValidatedJson<NicknamesPayload>,
+) -> Result<Json<Vec<Person>>, AppError> {
+    let ps = repo.get_by_nicknames(payload.nicknames).await?;
+    Ok(ps.into())
+}
+
+/// Sets the photo of a person.
+#[utoipa::path(
+    put,
+    path = "/pessoas/{id}/foto",
+    request_body = PhotoPayload,
+    responses(
+        (status = 200, description = "The updated person", body = Person),
+        (status = 404, description = "No such person", body = ErrorResponse),
+        (status = 422, description = "Not an http(s) URL", body = ErrorResponse),
+        (status = 401, description = "`WRITE_AUTH` rejected the credentials", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+    security((), ("api_key" = []), ("jwt" = [])),
+)]
+pub(crate) async fn set_photo(
+    Path(id): Path<i64>,
+    State(repo): State<DynPersonRepo>,
+    ValidatedJson(payload): ValidatedJson<PhotoPayload>,
+) -> Result<Json<Person>, AppError> {
+    let person = repo.set_photo(id, payload.photo).await?;
+    Ok(person.into())
+}
+
+/// Removes some stacks of a person, keeping the order of the others.
+#[utoipa::path(
+    post,
+    path = "/pessoas/{id}/stacks/remove",
+    request_body = StackRemovalPayload,
+    responses(
+        (status = 200, description = "The updated person", body = Person),
+        (status = 404, description = "No such person", body = ErrorResponse),
+        (status = 422, description = "Empty or invalid stack", body = ErrorResponse),
+        (status = 401, description = "`WRITE_AUTH` rejected the credentials", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+    security((), ("api_key" = []), ("jwt" = [])),
+)]
+pub(crate) async fn remove_stacks(
+    Path(id): Path<i64>,
+    State(repo): State<DynPersonRepo>,
+    ValidatedJson(payload): ValidatedJson<StackRemovalPayload>,
+) -> Result<Json<Person>, AppError> {
+    let person = repo.remove_stacks(id, payload.stacks).await?;
+    Ok(person.into())
+}
+
+/// Replaces the whole person, validated like a creation.
+#[utoipa::path(
+    put,
+    path = "/pessoas/{id}",
+    request_body = CreatePersonPayload,
+    responses(
+        (status = 200, description = "The updated person, its version in `ETag`", body = Person),
+        (status = 400, description = "Malformed JSON or a field of the wrong type", body = ErrorResponse),
+        (status = 404, description = "No such person", body = ErrorResponse),
+        (status = 422, description = "Invalid field or nickname already taken", body = ErrorResponse),
+        (status = 413, description = "Body larger than `MAX_BODY_BYTES`", body = ErrorResponse),
+        (status = 401, description = "`WRITE_AUTH` rejected the credentials", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+    security((), ("api_key" = []), ("jwt" = [])),
+)]
+pub(crate) async fn replace_person(
+    Path(id): Path<i64>,
+    State(repo): State<DynPersonRepo>,
+    ValidatedJson(payload): ValidatedJson<CreatePersonPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    let person = repo.patch_person(id, payload.into()).await?;
+    Ok(([(ETAG, etag(&person))], Json(person)))
+}
+
+pub(crate) const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+
+/// Updates some fields of a person.
+#[utoipa::path(
+    patch,
+    path = "/pessoas/{id}",
+    request_body(content = PersonMergePatch, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated person, its version in `ETag`", body = Person),
+        (status = 404, description = "No such person", body = ErrorResponse),
+        (status = 415, description = "Not sent as `application/merge-patch+json`", body = ErrorResponse),
+        (status = 422, description = "Invalid field or nickname already taken", body = ErrorResponse),
+        (status = 413, description = "Body larger than `MAX_BODY_BYTES`", body = ErrorResponse),
+        (status = 401, description = "`WRITE_AUTH` rejected the credentials", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+    security((), ("api_key" = []), ("jwt" = [])),
+)]
+pub(crate) async fn patch_person(
+    Path(id): Path<i64>,
+    State(repo): State<DynPersonRepo>,
+    headers: HeaderMap,
+    ValidatedJson(patch): ValidatedJson<PersonMergePatch>,
+) -> Result<impl IntoResponse, AppError> {
+    let content_type = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    if content_type != Some(MERGE_PATCH_CONTENT_TYPE) {
+        return Err(AppError::UnsupportedMediaType {
+            expected: MERGE_PATCH_CONTENT_TYPE,
+        });
+    }
+
+    let person = repo.patch_person(id, patch).await?;
+    Ok(([(ETAG, etag(&person))], Json(person)))
+}
+
+/// Searches people by nickname, name and stacks.
+#[utoipa::path(
+    get,
+    path = "/pessoas",
+    params(SearchPersonQuery),
+    responses(
+        (status = 200, description = "Matching people, as JSON or CSV (`Accept: text/csv`)", content(("application/json" = [Person]), ("text/csv" = String))),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+        (status = 404, description = "Nobody matched, with `SEARCH_EMPTY_STATUS=404`", body = ErrorResponse),
+        (status = 406, description = "`Accept` allows none of the supported media types", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn search_person(
+    StrictQuery(query): StrictQuery<SearchPersonQuery>,
+    State(repo): State<DynPersonRepo>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if let Some(advanced) = query.as_advanced() {
+        if query.fuzzy {
+            return Err(AppError::InvalidQuery(
+                "fuzzy can't be combined with stack, apelido, nascimento or sort".to_owned(),
+            ));
+        }
+        return filtered_search(advanced, &repo).await;
+    }
+    let media_type = negotiate(&headers, SEARCH_MEDIA_TYPES)?;
+    let Some(term) = &query.search_term else {
+        return Err(AppError::InvalidQuery(
+            "t is required unless filtering by stack, apelido or nascimento".to_owned(),
+        ));
+    };
+    let term = normalize_search_term(term);
+    // an empty pattern would match everyone
+    if term.is_empty() {
+        return Err(AppError::InvalidQuery("t must not be blank".to_owned()));
+    }
+    let limit = match query.limit {
+        Some(limit) if limit < 1 => {
+            return Err(AppError::InvalidQuery(
+                "limit must be at least 1".to_owned(),
+            ))
+        }
+        limit => limit.unwrap_or(DEFAULT_SEARCH_LIMIT).min(MAX_PAGE_LIMIT),
+    };
+    let offset = query.offset.unwrap_or_default();
+    if offset < 0 {
+        return Err(AppError::InvalidQuery(
+            "offset must not be negative".to_owned(),
+        ));
+    }
+
+    let started = Instant::now();
+    let (ps, total) = if query.fuzzy {
+        let ps = repo
+            .fuzzy_search(term, config.fuzzy_threshold, limit, offset)
+            .await?;
+        (ps, None)
+    } else if query.with_total || query.paged {
+        let (ps, total) = tokio::try_join!(
+            repo.search_person(term.clone(), limit, offset),
+            repo.count_search(term),
+        )?;
+        (ps, Some(total))
+    } else {
+        (repo.search_person(term, limit, offset).await?, None)
+    };
+    let took_ms = started.elapsed().as_millis();
+    // paging past the last match still finds nothing with a 200
+    if ps.is_empty() && offset == 0 && config.empty_search == EmptySearch::NotFound {
+        return Err(AppError::NoResults);
+    }
+
+    let (mut response, truncated) = if media_type == "text/csv" {
+        let body = people_to_csv(&ps);
+        let response = ([(CONTENT_TYPE, "text/csv; charset=utf-8")], body).into_response();
+        (response, false)
+    } else {
+        let (mut body, kept) = json_array_within_budget(&ps, config.max_search_response_bytes);
+        // only wrapped on request, the plain array is what the Rinha contract expects
+        if query.debug_timing || query.paged {
+            let mut fields = String::new();
+            if query.paged {
+                // a cut page goes on after its last item, so no match is skipped, and a
+                // full last page doesn't point past the matches
+                let next_offset = offset + kept as i64;
+                let next_offset = total
+                    .map_or(kept < ps.len() || ps.len() as i64 == limit, |t| {
+                        next_offset < t
+                    })
+                    .then_some(next_offset);
+                let json = |value: Option<i64>| value.map_or("null".to_owned(), |v| v.to_string());
+                fields += &format!(
+                    r#","total":{},"next_offset":{}"#,
+                    json(total),
+                    json(next_offset)
+                );
+            }
+            if query.debug_timing {
+                fields += &format!(r#","took_ms":{took_ms}"#);
+            }
+            body = [br#"{"items":"#.as_slice(), &body, fields.as_bytes(), b"}"].concat();
+        }
+        let truncated = kept < ps.len();
+        let response = ([(CONTENT_TYPE, "application/json")], body).into_response();
+        (response, truncated)
+    };
+    if truncated {
+        response
+            .headers_mut()
+            .insert(RESULTS_TRUNCATED.clone(), HeaderValue::from_static("true"));
+    }
+    if let Some(total) = total {
+        response
+            .headers_mut()
+            .insert(TOTAL_COUNT.clone(), HeaderValue::from(total));
+    }
+    Ok(response)
+}
+
+/// Searches people combining filters, `X-Next-Cursor` set when there is a next page.
+#[utoipa::path(
+    get,
+    path = "/pessoas/search-advanced",
+    params(AdvancedSearchQuery),
+    responses(
+        (status = 200, description = "Matching people", body = [Person]),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+        (status = 422, description = "Invalid filter, sort or cursor", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn advanced_search(
+    StrictQuery(query): StrictQuery<AdvancedSearchQuery>,
+    State(repo): State<DynPersonRepo>,
+) -> Result<Response, AppError> {
+    filtered_search(query, &repo).await
+}
+
+/// Runs an advanced search, answering the page as a JSON array with the `X-Next-Cursor` of the next.
+pub(crate) async fn filtered_search(
+    query: AdvancedSearchQuery,
+    repo: &DynPersonRepo,
+) -> Result<Response, AppError> {
+    let mut errors = Vec::new();
+    let sort = match query.sort.as_deref().map(SortOrder::from_str).transpose() {
+        Ok(sort) => sort.unwrap_or_default(),
+        Err(err) => {
+            errors.push(err);
+            SortOrder::default()
+        }
+    };
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            errors.push("from must not be after to".to_owned());
+        }
+    }
+    if query.offset.is_some_and(|offset| offset < 0) {
+        errors.push("offset must not be negative".to_owned());
+    }
+    let after = match &query.cursor {
+        Some(_) if query.offset.is_some() => {
+            errors.push("cursor and offset can't be combined".to_owned());
+            None
+        }
+        Some(raw) => SortCursor::decode(raw, sort)
+            .map_err(|err| errors.push(err))
+            .ok(),
+        None => None,
+    };
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+
+    let search = AdvancedSearch {
+        terms: SearchTerms::parse(&query.t.unwrap_or_default()),
+        from: query.from,
+        to: query.to,
+        nickname: FieldMatch::new(query.apelido, query.apelido_match, MatchMode::Prefix),
+        name: FieldMatch::new(query.nome, query.nome_match, MatchMode::Substring),
+        stack: FieldMatch::new(query.stack, query.stack_match, MatchMode::Exact),
+        sort,
+        after,
+        // one extra row tells whether there is a next page
+        limit: limit + 1,
+        offset: query.offset.unwrap_or_default(),
+    };
+    let mut ps = repo.advanced_search(search).await?;
+    let next_cursor = if ps.len() as i64 > limit {
+        ps.truncate(limit as usize);
+        ps.last().map(|p| SortCursor::encode(p, sort))
+    } else {
+        None
+    };
+
+    let mut response = Json(ps).into_response();
+    if let Some(cursor) = next_cursor {
+        let cursor = HeaderValue::try_from(cursor).expect("base64 is a valid header value");
+        response.headers_mut().insert(NEXT_CURSOR.clone(), cursor);
+    }
+    Ok(response)
+}
+
+/// A random person.
+#[utoipa::path(
+    get,
+    path = "/pessoas/aleatorio",
+    responses(
+        (status = 200, description = "A person", body = Person),
+        (status = 404, description = "Nobody is stored", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn random_person(
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<Person>, AppError> {
+    let person = repo.random_person().await?;
+    Ok(person.into())
+}
+
+/// The people sharing the most stacks with a person.
+#[utoipa::path(
+    get,
+    path = "/pessoas/{id}/similares",
+    params(LimitQuery),
+    responses(
+        (status = 200, description = "Most similar first", body = [Person]),
+        (status = 404, description = "No such person", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn similar_person(
+    Path(id): Path<i64>,
+    StrictQuery(query): StrictQuery<LimitQuery>,
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<Vec<Person>>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SIMILAR_LIMIT)
+        .clamp(1, MAX_SIMILAR_LIMIT);
+
+    // 404 rather than an empty list when the person itself does not exist
+    repo.get_person(id).await?;
+    let ps = repo.similar_by_stacks(id, limit).await?;
+    Ok(ps.into())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct IdRangeQuery {
+    pub(crate) from_id: i64,
+    pub(crate) to_id: i64,
+}
+
+/// People whose ids are in `from_id..=to_id`, for exports split across workers.
+/// At most `ID_RANGE_BATCH_SIZE` are returned, a cut range is flagged with `X-Results-Truncated: true`.
+#[utoipa::path(
+    get,
+    path = "/admin/pessoas/range",
+    params(IdRangeQuery),
+    responses(
+        (status = 200, description = "The people of the range, `X-Results-Truncated: true` when cut", body = [Person]),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn people_in_id_range(
+    StrictQuery(query): StrictQuery<IdRangeQuery>,
+    State(repo): State<DynPersonRepo>,
+    State(config): State<Arc<Config>>,
+) -> Result<Response, AppError> {
+    if query.from_id > query.to_id {
+        return Err(AppError::InvalidQuery(
+            "from_id must not be greater than to_id".to_owned(),
+        ));
+    }
+
+    let limit = config.id_range_batch_size;
+    // one extra row tells whether the range was cut
+    let mut ps = repo
+        .people_in_id_range(query.from_id, query.to_id, limit + 1)
+        .await?;
+    let truncated = ps.len() as i64 > limit;
+    ps.truncate(limit as usize);
+
+    let mut response = Json(ps).into_response();
+    if truncated {
+        response
+            .headers_mut()
+            .insert(RESULTS_TRUNCATED.clone(), HeaderValue::from_static("true"));
+    }
+    Ok(response)
+}
+
+/// Everyone, by id.
+#[utoipa::path(
+    get,
+    path = "/admin/pessoas",
+    params(ListPersonQuery),
+    responses(
+        (status = 200, description = "A page of people", body = PersonPage),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn list_people(
+    StrictQuery(query): StrictQuery<ListPersonQuery>,
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<Page<Person>>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+
+    // one extra row tells whether there is a next page
+    let mut items = repo.list_people(query.cursor, limit + 1).await?;
+    let next_cursor = if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        items.last().map(|p| p.id)
+    } else {
+        None
+    };
+
+    Ok(Page { items, next_cursor }.into())
+}
+
+/// Scans one page of people, reporting only the ones failing validation.
+///
+/// `next_cursor` follows the scan so pages with no failures can still be skipped.
+#[utoipa::path(
+    post,
+    path = "/admin/pessoas/revalidate",
+    params(ListPersonQuery),
+    responses(
+        (status = 200, description = "The failures of the page scanned", body = RevalidationPage),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn revalidate_people(
+    StrictQuery(query): StrictQuery<ListPersonQuery>,
+    State(repo): State<DynPersonRepo>,
+    State(config): State<Arc<Config>>,
+) -> Result<Json<Page<RevalidationFailure>>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+
+    let mut people = repo.list_people(query.cursor, limit + 1).await?;
+    let next_cursor = if people.len() as i64 > limit {
+        people.truncate(limit as usize);
+        people.last().map(|p| p.id)
+    } else {
+        None
+    };
+
+    let today = Utc::now().date_naive();
+    let items = people
+        .iter()
+        .filter_map(|person| {
+            let errors = revalidate_person(person, &config, today);
+            (!errors.is_empty()).then_some(RevalidationFailure {
+                id: person.id,
+                errors,
+            })
+        })
+        .collect();
+
+    Ok(Page { items, next_cursor }.into())
+}
+
+/// People newest first.
+#[utoipa::path(
+    get,
+    path = "/pessoas/timeline",
+    params(TimelineQuery),
+    responses(
+        (status = 200, description = "A page of people", body = TimelinePage),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn timeline(
+    StrictQuery(query): StrictQuery<TimelineQuery>,
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<TimelinePage>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+    let before = query.before.map(|before| (before, query.before_id));
+
+    // one extra row tells whether there is a next page
+    let mut items = repo.timeline(before, limit + 1).await?;
+    let next = if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        items.last().and_then(|p| Some((p.created_at?, p.id)))
+    } else {
+        None
+    };
+    let (next_before, next_before_id) = next.unzip();
+
+    Ok(TimelinePage {
+        items,
+        next_before,
+        next_before_id,
+    }
+    .into())
+}
+
+/// How many people were born each year.
+#[utoipa::path(
+    get,
+    path = "/pessoas/stats/por-ano",
+    responses((status = 200, description = "Most common years first", body = [BirthYearCount])),
+)]
+pub(crate) async fn count_by_birth_year(
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<Vec<BirthYearCount>>, AppError> {
+    let counts = repo.count_by_birth_year().await?;
+    Ok(counts.into())
+}
+
+/// The most used stacks of the people created recently.
+#[utoipa::path(
+    get,
+    path = "/stacks/trending",
+    params(TrendingQuery),
+    responses(
+        (status = 200, description = "Stacks with their counts", body = [StackCount]),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+        (status = 422, description = "`days` out of range", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn trending_stacks(
+    StrictQuery(query): StrictQuery<TrendingQuery>,
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<Vec<StackCount>>, AppError> {
+    let days = query.days.unwrap_or(DEFAULT_TRENDING_DAYS);
+    if !(1..=MAX_TRENDING_DAYS).contains(&days) {
+        return Err(AppError::Validation(vec![format!(
+            "days must be between 1 and {MAX_TRENDING_DAYS}"
+        )]));
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SIMILAR_LIMIT)
+        .clamp(1, MAX_SIMILAR_LIMIT);
+
+    let stacks = repo.trending_stacks(days, limit).await?;
+    Ok(stacks.into())
+}
+
+/// The other stacks of the people who have this one.
+#[utoipa::path(
+    get,
+    path = "/stacks/{name}/relacionadas",
+    params(LimitQuery),
+    responses(
+        (status = 200, description = "Stacks with their counts", body = [StackCount]),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn related_stacks(
+    Path(name): Path<String>,
+    StrictQuery(query): StrictQuery<LimitQuery>,
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<Vec<StackCount>>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SIMILAR_LIMIT)
+        .clamp(1, MAX_SIMILAR_LIMIT);
+
+    let stacks = repo.related_stacks(name, limit).await?;
+    Ok(stacks.into())
+}
+
+/// The curated details of a stack.
+#[utoipa::path(
+    get,
+    path = "/meta/stacks/{name}",
+    responses(
+        (status = 200, description = "The details", body = StackMeta),
+        (status = 404, description = "The stack has no details", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn get_stack_meta(
+    Path(name): Path<String>,
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<StackMeta>, AppError> {
+    let meta = repo.get_stack_meta(name).await?;
+    Ok(meta.into())
+}
+
+/// Replaces the curated details of a stack.
+#[utoipa::path(
+    put,
+    path = "/meta/stacks/{name}",
+    request_body = StackMetaPayload,
+    responses(
+        (status = 200, description = "The details", body = StackMeta),
+        (status = 400, description = "Malformed JSON or a field of the wrong type", body = ErrorResponse),
+        (status = 422, description = "Invalid display name or icon", body = ErrorResponse),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn put_stack_meta(
+    Path(name): Path<String>,
+    State(repo): State<DynPersonRepo>,
+    ValidatedJson(payload): ValidatedJson<StackMetaPayload>,
+) -> Result<Json<StackMeta>, AppError> {
+    let meta = StackMeta {
+        name,
+        display_name: payload.display_name,
+        icon: payload.icon,
+    };
+    let meta = repo.put_stack_meta(meta).await?;
+    Ok(meta.into())
+}
+
+/// Deletes for good the people soft deleted long enough ago, freeing their nicknames.
+#[utoipa::path(
+    post,
+    path = "/admin/pessoas/purge",
+    params(PurgeQuery),
+    responses(
+        (status = 200, description = "How many were purged", body = PurgeResult),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+        (status = 422, description = "Negative `older_than_days`", body = ErrorResponse),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn purge_deleted(
+    StrictQuery(query): StrictQuery<PurgeQuery>,
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<PurgeResult>, AppError> {
+    let older_than_days = query
+        .older_than_days
+        .unwrap_or(DEFAULT_PURGE_OLDER_THAN_DAYS);
+    if older_than_days < 0 {
+        return Err(AppError::Validation(vec![
+            "older_than_days must not be negative".to_owned(),
+        ]));
+    }
+
+    let purged = repo.purge_deleted(older_than_days).await?;
+    Ok(PurgeResult { purged }.into())
+}
+
+/// Times `op`, recording it in `steps` as `step`.
+pub(crate) async fn self_test_step<T>(
+    steps: &mut Vec<SelfTestStep>,
+    step: &'static str,
+    op: impl Future<Output = Result<T, String>>,
+) -> Option<T> {
+    let started = Instant::now();
+    let result = op.await;
+    let took_ms = started.elapsed().as_millis();
+    let (value, error) = match result {
+        Ok(value) => (Some(value), None),
+        Err(error) => (None, Some(error)),
+    };
+    steps.push(SelfTestStep {
+        step,
+        ok: error.is_none(),
+        took_ms,
+        error,
+    });
+    value
+}
+
+pub(crate) fn self_test_error(err: AppError) -> String {
+    format!("answered {}", err.into_response().status())
+}
+
+/// Creates, reads, searches and deletes a throwaway person, to check a deploy end to end.
+/// Not routed with `APP_ENV=production`. Answers 500 when a step failed.
+#[utoipa::path(
+    post,
+    path = "/admin/selftest",
+    responses(
+        (status = 200, description = "Every step passed", body = SelfTestReport),
+        (status = 500, description = "A step failed", body = SelfTestReport),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn self_test(
+    State(repo): State<DynPersonRepo>,
+) -> (StatusCode, Json<SelfTestReport>) {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let nickname = format!("selftest_{nanos}");
+    let payload = CreatePersonPayload {
+        nickname: nickname.clone(),
+        name: "Self Test".to_owned(),
+        dob: Dob(NaiveDate::from_ymd_opt(2000, 1, 1).expect("valid date")),
+        stacks: None,
+    };
+
+    let mut steps = Vec::new();
+    let created = self_test_step(&mut steps, "create", async {
+        repo.create_person(payload).await.map_err(self_test_error)
+    })
+    .await;
+    if let Some(person) = created {
+        self_test_step(&mut steps, "read", async {
+            repo.get_person(person.id).await.map_err(self_test_error)
+        })
+        .await;
+        self_test_step(&mut steps, "search", async {
+            let found = repo
+                .search_person(nickname, 1, 0)
+                .await
+                .map_err(self_test_error)?;
+            match found.first() {
+                Some(p) if p.id == person.id => Ok(()),
+                _ => Err("the person was not found".to_owned()),
+            }
+        })
+        .await;
+        // even when reading or searching failed, so no throwaway person is left behind
+        self_test_step(&mut steps, "delete", async {
+            repo.delete_person(person.id, None)
+                .await
+                .map_err(self_test_error)
+        })
+        .await;
+    }
+
+    let ok = steps.iter().all(|s| s.ok);
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    (status, Json(SelfTestReport { ok, steps }))
+}
+
+/// Starts rebuilding the indexes in the background.
+///
+/// Answers 202 right away, the progress is at `GET /admin/reindex`. A reindex already running is not started again.
+#[utoipa::path(
+    post,
+    path = "/admin/reindex",
+    responses(
+        (status = 202, description = "Started, or already running", body = ReindexStatus),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn start_reindex(
+    State(repo): State<DynPersonRepo>,
+    State(job): State<ReindexJob>,
+) -> impl IntoResponse {
+    let mut started = false;
+    job.update(|status| {
+        if status.state != ReindexState::Running {
+            *status = ReindexStatus {
+                state: ReindexState::Running,
+                started_at: Some(Utc::now()),
+                finished_at: None,
+            };
+            started = true;
+        }
+    });
+    if started {
+        let job = job.clone();
+        tokio::spawn(async move {
+            info!("reindexing");
+            let state = match repo.reindex().await {
+                Ok(()) => ReindexState::Succeeded,
+                Err(_) => ReindexState::Failed,
+            };
+            info!(?state, "reindex finished");
+            job.update(|status| {
+                status.state = state;
+                status.finished_at = Some(Utc::now());
+            });
+        });
+    }
+    (
+        StatusCode::ACCEPTED,
+        [(LOCATION, HeaderValue::from_static("/admin/reindex"))],
+        Json(job.status()),
+    )
+}
+
+/// The last reindex started.
+#[utoipa::path(
+    get,
+    path = "/admin/reindex",
+    responses(
+        (status = 200, description = "Its state", body = ReindexStatus),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn reindex_status(State(job): State<ReindexJob>) -> Json<ReindexStatus> {
+    job.status().into()
+}
+
+/// People whose derived columns are missing, for backfills.
+#[utoipa::path(
+    get,
+    path = "/admin/pessoas/incompletas",
+    params(ListPersonQuery),
+    responses(
+        (status = 200, description = "A page of people", body = PersonPage),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn incomplete_people(
+    StrictQuery(query): StrictQuery<ListPersonQuery>,
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<Page<Person>>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+
+    let mut items = repo.incomplete_people(query.cursor, limit + 1).await?;
+    let next_cursor = if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        items.last().map(|p| p.id)
+    } else {
+        None
+    };
+
+    Ok(Page { items, next_cursor }.into())
+}
+
+/// Pairs of people with similar names, for staff to review before merging them.
+#[utoipa::path(
+    get,
+    path = "/admin/pessoas/duplicados",
+    params(DuplicatesQuery),
+    responses(
+        (status = 200, description = "Most similar first", body = [DuplicateCandidate]),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn duplicate_candidates(
+    StrictQuery(query): StrictQuery<DuplicatesQuery>,
+    State(repo): State<DynPersonRepo>,
+    State(config): State<Arc<Config>>,
+) -> Result<Json<Vec<DuplicateCandidate>>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+    let offset = query.offset.unwrap_or_default();
+    if offset < 0 {
+        return Err(AppError::InvalidQuery(
+            "offset must not be negative".to_owned(),
+        ));
+    }
+
+    let pairs = repo
+        .duplicate_candidates(config.duplicate_threshold, limit, offset)
+        .await?;
+    Ok(pairs.into())
+}
+
+/// People that would fail today's validation or look like placeholders.
+#[utoipa::path(
+    get,
+    path = "/admin/pessoas/suspeitas",
+    params(LimitQuery),
+    responses(
+        (status = 200, description = "The people found", body = [Person]),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn suspicious_people(
+    StrictQuery(query): StrictQuery<LimitQuery>,
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<Vec<Person>>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+
+    let ps = repo.suspicious_people(limit).await?;
+    Ok(ps.into())
+}
+
+/// What `GET /` describes, so the API can be found without reading its code.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct ServiceDescriptor {
+    pub(crate) service: &'static str,
+    pub(crate) version: &'static str,
+    #[schema(value_type = Vec<String>)]
+    pub(crate) endpoints: &'static [&'static str],
+}
+
+/// The public routes, the admin ones are left out.
+pub(crate) const PUBLIC_ENDPOINTS: &[&str] = &[
+    "GET /",
+    "POST /pessoas",
+    "POST /pessoas/batch",
+    "POST /pessoas/import",
+    "POST /pessoas/by-nicknames",
+    "GET /pessoas?t=",
+    "GET /pessoas/search-advanced",
+    "GET /pessoas/:id",
+    "GET /pessoas/:id/similares",
+    "GET /pessoas/events",
+    "PUT /pessoas/:id",
+    "PATCH /pessoas/:id",
+    "DELETE /pessoas/:id",
+    "PUT /pessoas/:id/foto",
+    "POST /pessoas/:id/stacks/remove",
+    "GET /pessoas/timeline",
+    "GET /pessoas/aleatorio",
+    "GET /pessoas/stats/por-ano",
+    "GET /contagem-pessoas",
+    "GET /contagem-pessoas/stream",
+    "GET /stats",
+    "GET /stacks/trending",
+    "GET /stacks/:name/relacionadas",
+    "GET /meta/stacks/:name",
+    "GET /health",
+    "GET /healthz",
+    "GET /ready",
+    "GET /readyz",
+    "GET /metrics",
+    "GET /openapi.json",
+    "GET /docs",
+];
+
+/// Every route, documented by the `#[utoipa::path]` of its handler. Served at `GET /openapi.json`
+/// along with the Swagger UI at `GET /docs`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "person-api",
+        description = "REST API inspired by the rinha de backend 2023 challenge."
+    ),
+    paths(
+        root,
+        create_person,
+        create_people_batch,
+        import_people,
+        search_person,
+        advanced_search,
+        get_by_nicknames,
+        get_person,
+        replace_person,
+        patch_person,
+        delete_person,
+        set_photo,
+        remove_stacks,
+        similar_person,
+        person_events,
+        timeline,
+        random_person,
+        count_by_birth_year,
+        stream_export,
+        trending_stacks,
+        related_stacks,
+        get_stack_meta,
+        put_stack_meta,
+        dashboard_stats,
+        count_person,
+        count_person_stream,
+        health,
+        ready,
+        crate::app::metrics,
+        list_people,
+        people_in_id_range,
+        suspicious_people,
+        duplicate_candidates,
+        incomplete_people,
+        revalidate_people,
+        purge_deleted,
+        export_people,
+        reindex_status,
+        start_reindex,
+        self_test,
+        db_info,
+    ),
+    components(schemas(
+        Person,
+        Stack,
+        CreatePersonPayload,
+        PersonMergePatch,
+        NicknamesPayload,
+        PhotoPayload,
+        StackRemovalPayload,
+        BatchItemResult,
+        ImportReport,
+        ImportLineError,
+        TimelinePage,
+        PersonPage,
+        RevalidationPage,
+        RevalidationFailure,
+        MatchMode,
+        BirthYearCount,
+        StackCount,
+        StackMeta,
+        StackMetaPayload,
+        DashboardStats,
+        CountBreakdown,
+        AgeStats,
+        DuplicateCandidate,
+        PurgeResult,
+        ExportFormat,
+        ReindexStatus,
+        ReindexState,
+        SelfTestReport,
+        SelfTestStep,
+        DbInfo,
+        ServiceDescriptor,
+        ErrorResponse,
+    )),
+    modifiers(&ApiDocExtras)
+)]
+pub(crate) struct ApiDoc;
+
+/// What the derive can't tell: the credentials the paths' `security` refer to, writes
+/// only needing one with `WRITE_AUTH`, and that the crate declares no license.
+pub(crate) struct ApiDocExtras;
+
+impl Modify for ApiDocExtras {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.info.license = None;
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("The `ADMIN_TOKEN`"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-Api-Key",
+                "One of the `API_KEYS`, with `WRITE_AUTH=api_key`",
+            ))),
+        );
+        components.add_security_scheme(
+            "jwt",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some("Signed with the `JWT_SECRET`, with `WRITE_AUTH=jwt`"))
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// Redirects to [`Config::root_redirect`] when set, otherwise describes the service. Never touches the database.
+#[utoipa::path(
+    get,
+    path = "/",
+    responses(
+        (status = 200, description = "The service, its version and public endpoints", body = ServiceDescriptor),
+        (status = 307, description = "Redirected to `ROOT_REDIRECT`"),
+    ),
+)]
+pub(crate) async fn root(State(config): State<Arc<Config>>) -> Response {
+    match &config.root_redirect {
+        Some(location) => (
+            StatusCode::TEMPORARY_REDIRECT,
+            [(LOCATION, location.clone())],
+        )
+            .into_response(),
+        None => Json(ServiceDescriptor {
+            service: "person-api",
+            version: env!("CARGO_PKG_VERSION"),
+            endpoints: PUBLIC_ENDPOINTS,
+        })
+        .into_response(),
+    }
+}
+
+/// Liveness, answers as long as the process does.
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses((status = 200, description = "The process is up")),
+)]
+pub(crate) async fn health() -> StatusCode {
+    StatusCode::OK
+}
+
+/// How long readiness waits for the database, the pool would otherwise retry connecting for much longer.
+pub(crate) const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Readiness, fails with a 503 while the database can't be queried or lacks one of [`EXPECTED_INDEXES`].
+#[utoipa::path(
+    get,
+    path = "/ready",
+    responses(
+        (status = 200, description = "Ready to serve"),
+        (status = 503, description = "The database is unreachable or lacks indexes", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn ready(State(repo): State<DynPersonRepo>) -> Result<StatusCode, AppError> {
+    let unavailable = AppError::ServiceUnavailable("The database is unreachable");
+    let check = async {
+        repo.ping().await?;
+        repo.missing_indexes().await
+    };
+    let missing = match tokio::time::timeout(READY_TIMEOUT, check).await {
+        Ok(Ok(missing)) => missing,
+        Ok(Err(_)) | Err(_) => return Err(unavailable),
+    };
+    if !missing.is_empty() {
+        warn!(?missing, "database schema is missing indexes");
+        return Err(AppError::ServiceUnavailable(
+            "The database schema is missing indexes",
+        ));
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Plain text count as the Rinha contract expects, or a JSON breakdown with `?breakdown=true`.
+#[utoipa::path(
+    get,
+    path = "/contagem-pessoas",
+    params(CountQuery),
+    responses(
+        (status = 200, description = "The count", content(("text/plain" = String), ("application/json" = CountBreakdown))),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+    ),
+)]
+pub(crate) async fn count_person(
+    StrictQuery(query): StrictQuery<CountQuery>,
+    State(repo): State<DynPersonRepo>,
+) -> Result<Response, AppError> {
+    if query.breakdown {
+        let breakdown = repo.count_breakdown().await?;
+        return Ok(Json(breakdown).into_response());
+    }
+    let count = repo.count().await?;
+    Ok(count.to_string().into_response())
+}
+
+/// Counts, ages and top stacks at once.
+#[utoipa::path(
+    get,
+    path = "/stats",
+    responses((status = 200, description = "The statistics", body = DashboardStats)),
+)]
+pub(crate) async fn dashboard_stats(
+    State(repo): State<DynPersonRepo>,
+) -> Result<Json<DashboardStats>, AppError> {
+    let stats = repo.dashboard_stats().await?;
+    Ok(stats.into())
+}
+
+/// The database schema version and server.
+#[utoipa::path(
+    get,
+    path = "/info/db",
+    responses(
+        (status = 200, description = "The database", body = DbInfo),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn db_info(State(repo): State<DynPersonRepo>) -> Result<Json<DbInfo>, AppError> {
+    let info = repo.db_info().await?;
+    Ok(info.into())
+}
+
+pub(crate) async fn handle_layer_error(err: BoxError) -> Response {
+    error!(error = %err, "request failed in a middleware");
+    let res = ErrorResponse {
+        status: StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+        code: "Unexpected",
+        title: "Internal Server Error",
+        detail: "Unexpected error".to_owned(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(res)).into_response()
+}
+
+/// Streams `estimate` events every second while the exact count runs, then a final `count` event.
+#[utoipa::path(
+    get,
+    path = "/contagem-pessoas/stream",
+    responses(
+        (status = 200, description = "Server-sent `estimate` events, then a `count` one", content_type = "text/event-stream", body = String),
+    ),
+)]
+pub(crate) async fn count_person_stream(
+    State(repo): State<DynPersonRepo>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let (tx, rx) = mpsc::channel(8);
+
+    tokio::spawn(async move {
+        let exact = repo.count();
+        tokio::pin!(exact);
+        let mut progress = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            let event = tokio::select! {
+                result = &mut exact => {
+                    let event = match result {
+                        Ok(count) => Event::default().event("count").data(count.to_string()),
+                        Err(_) => Event::default().event("error").data("Unexpected error"),
+                    };
+                    let _ = tx.send(Ok(event)).await;
+                    return;
+                }
+                _ = progress.tick() => match repo.estimated_count().await {
+                    Ok(estimate) => Event::default().event("estimate").data(estimate.to_string()),
+                    Err(_) => continue,
+                },
+            };
+            // the client went away
+            if tx.send(Ok(event)).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    Sse::new(ReceiverStream::new(rx)).keep_alive(KeepAlive::default())
+}
+
+/// Streams the people created from now on.
+///
+/// A `person` event carries the JSON of each, and a `lagged` event how many were missed
+/// when the client reads too slowly to keep up.
+/// The stream ends once the server is shutting down, so it doesn't hold up the drain.
+#[utoipa::path(
+    get,
+    path = "/pessoas/events",
+    responses(
+        (status = 200, description = "Server-sent `person` and `lagged` events", content_type = "text/event-stream", body = String),
+    ),
+)]
+pub(crate) async fn person_events(
+    State(events): State<PersonEvents>,
+    State(shutdown): State<ShutdownNotice>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = futures::stream::unfold(events.subscribe(), |mut rx| async move {
+        let event = match rx.recv().await {
+            Ok(person) => Event::default().event("person").data(&*person),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                Event::default().event("lagged").data(missed.to_string())
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((Ok(event), rx))
+    })
+    .take_until(shutdown.fired());
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// People read per query while exporting.
+pub(crate) const EXPORT_PAGE_SIZE: i64 = 1000;
+
+/// Streams everyone as a gzipped JSON array, one page at a time so memory stays flat.
+///
+/// The status is sent before the first page is read, so a failure midway can only be
+/// logged and the download is cut short, leaving an invalid gzip file behind.
+#[utoipa::path(
+    get,
+    path = "/admin/export.json.gz",
+    responses(
+        (status = 200, description = "A gzipped JSON array of people", content_type = "application/gzip", body = [u8]),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn export_people(State(repo): State<DynPersonRepo>) -> impl IntoResponse {
+    let (tx, rx) = mpsc::channel::<Result<Vec<u8>, io::Error>>(2);
+
+    tokio::spawn(async move {
+        if let Err(err) = write_export(repo, &tx).await {
+            error!(error = %err, "export failed");
+            let _ = tx.send(Err(err)).await;
+        }
+    });
+
+    let filename = format!("people-{}.json.gz", Utc::now().format("%Y%m%dT%H%M%SZ"));
+    (
+        [
+            (CONTENT_TYPE, "application/gzip".to_owned()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        StreamBody::new(ReceiverStream::new(rx)),
+    )
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ExportFormat {
+    /// One JSON person per line.
+    #[default]
+    Ndjson,
+    Csv,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ExportQuery {
+    #[serde(default)]
+    pub(crate) format: ExportFormat,
+}
+
+/// Streams everyone straight from [`PersonRepository::stream_people`], a row at a time.
+///
+/// As with [`export_people`] a failure midway can only be logged and cuts the download short.
+#[utoipa::path(
+    get,
+    path = "/pessoas/export",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Everyone, one person per line or row", content(("application/x-ndjson" = String), ("text/csv" = String))),
+        (status = 400, description = "Missing, repeated or invalid query parameter", body = ErrorResponse),
+        (status = 401, description = "Without the `ADMIN_TOKEN` as a bearer token", body = ErrorResponse),
+    ),
+    security(("admin_token" = [])),
+)]
+pub(crate) async fn stream_export(
+    StrictQuery(query): StrictQuery<ExportQuery>,
+    State(repo): State<DynPersonRepo>,
+) -> impl IntoResponse {
+    let (content_type, extension) = match query.format {
+        ExportFormat::Ndjson => ("application/x-ndjson", "ndjson"),
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+    };
+    let header: Option<Result<Vec<u8>, io::Error>> = match query.format {
+        ExportFormat::Ndjson => None,
+        ExportFormat::Csv => Some(Ok(CSV_HEADER.as_bytes().to_vec())),
+    };
+    let rows = repo.stream_people().map(move |person| {
+        let person = person.map_err(|_| io::Error::other("failed to read people"))?;
+        Ok(match query.format {
+            ExportFormat::Ndjson => {
+                let mut line = serde_json::to_vec(&person)?;
+                line.push(b'\n');
+                line
+            }
+            ExportFormat::Csv => csv_row(&person).into_bytes(),
+        })
+    });
+    let body = futures::stream::iter(header).chain(rows).inspect(|chunk| {
+        if let Err(err) = chunk {
+            error!(error = %err, "export failed");
+        }
+    });
+
+    let filename = format!("people-{}.{extension}", Utc::now().format("%Y%m%dT%H%M%SZ"));
+    (
+        [
+            (CONTENT_TYPE, content_type.to_owned()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        StreamBody::new(body),
+    )
+}
+
+/// Sends the compressed export in chunks, returning early once the client went away.
+pub(crate) async fn write_export(
+    repo: DynPersonRepo,
+    tx: &mpsc::Sender<Result<Vec<u8>, io::Error>>,
+) -> Result<(), io::Error> {
+    let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+    gzip.write_all(b"[")?;
+    let mut after_id = None;
+    loop {
+        let page = repo
+            .list_people(after_id, EXPORT_PAGE_SIZE)
+            .await
+            .map_err(|_| io::Error::other("failed to read people"))?;
+        for (i, person) in page.iter().enumerate() {
+            if after_id.is_some() || i > 0 {
+                gzip.write_all(b",")?;
+            }
+            serde_json::to_writer(&mut gzip, person)?;
+        }
+        if page.len() < EXPORT_PAGE_SIZE as usize {
+            break;
+        }
+        after_id = page.last().map(|p| p.id);
+
+        let chunk = std::mem::take(gzip.get_mut());
+        if !chunk.is_empty() && tx.send(Ok(chunk)).await.is_err() {
+            return Ok(());
+        }
+    }
+    gzip.write_all(b"]")?;
+    let _ = tx.send(Ok(gzip.finish()?)).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use axum::body::HttpBody;
+    use axum::http::header::AUTHORIZATION;
+    use serde_json::json;
+
+    use super::*;
+    use crate::app::AppState;
+    use crate::domain::{
+        too_many_stacks, InvalidAges, Stack, INVALID_STACK, MAX_NICKNAMES_LOOKUP,
+        MAX_STACK_ENTRIES, MIN_DOB_YEAR,
+    };
+    use crate::repository::InMemoryPersonRepository;
+    use crate::test_support::{body_bytes, body_json, config, get, post_json, send, with_env};
+
+    fn ndjson(uri: &str, lines: Vec<String>) -> Request<axum::body::Body> {
+        Request::post(uri)
+            .header(CONTENT_TYPE, "application/x-ndjson")
+            .body(lines.join("\n").into())
+            .unwrap()
+    }
+
+    fn person_line(nickname: &str) -> String {
+        json!({ "apelido": nickname, "nome": "Ana Barros", "nascimento": "1985-09-23" }).to_string()
+    }
+
+    /// Creates a person through the API, returning its id.
+    async fn create(state: &AppState, nickname: &str, stacks: &[&str]) -> i64 {
+        create_json(
+            state,
+            json!({
+                "apelido": nickname,
+                "nome": "Ana Barros",
+                "nascimento": "1985-09-23",
+                "stack": stacks,
+            }),
+        )
+        .await
+    }
+
+    async fn create_json(state: &AppState, person: serde_json::Value) -> i64 {
+        let res = send(state, post_json("/pessoas", person)).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        body_json(res).await["id"].as_i64().unwrap()
+    }
+
+    async fn person(
+        state: &AppState,
+        nickname: &str,
+        name: &str,
+        dob: &str,
+        stacks: &[&str],
+    ) -> i64 {
+        create_json(
+            state,
+            json!({ "apelido": nickname, "nome": name, "nascimento": dob, "stack": stacks }),
+        )
+        .await
+    }
+
+    async fn born(state: &AppState, nickname: &str, dob: &str) -> i64 {
+        create_json(
+            state,
+            json!({ "apelido": nickname, "nome": "Ana Barros", "nascimento": dob }),
+        )
+        .await
+    }
+
+    fn nicknames(people: &serde_json::Value) -> Vec<&str> {
+        people
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["apelido"].as_str().unwrap())
+            .collect()
+    }
+
+    /// State whose admin routes take the bearer token of [`admin_get`].
+    fn admin_state() -> AppState {
+        let mut config = config();
+        config.admin_token = Some("secret".to_owned());
+        AppState::in_memory(config)
+    }
+
+    /// Admin state holding `people` as they are, valid or not as old rows may be.
+    fn seeded(people: Vec<Person>) -> AppState {
+        seeded_with(config(), people)
+    }
+
+    fn seeded_with(mut config: Config, people: Vec<Person>) -> AppState {
+        config.admin_token = Some("secret".to_owned());
+        let repo = InMemoryPersonRepository::new(&config);
+        for person in people {
+            repo.next_id.fetch_max(person.id + 1, Ordering::Relaxed);
+            repo.people_mut().insert(person.id, person);
+        }
+        AppState::new(Arc::new(repo), config, None)
+    }
+
+    fn row(id: i64, nickname: &str, name: &str, dob: &str) -> Person {
+        Person {
+            id,
+            nickname: nickname.to_owned(),
+            name: name.to_owned(),
+            dob: dob.parse().unwrap(),
+            ..Person::default()
+        }
+    }
+
+    /// `req` with the admin token of [`admin_state`] and [`seeded`].
+    fn as_admin(mut req: Request<axum::body::Body>) -> Request<axum::body::Body> {
+        req.headers_mut()
+            .insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        req
+    }
+
+    fn admin_get(uri: &str) -> Request<axum::body::Body> {
+        as_admin(get(uri))
+    }
+
+    fn merge_patch(uri: &str, patch: serde_json::Value) -> Request<axum::body::Body> {
+        Request::patch(uri)
+            .header(CONTENT_TYPE, MERGE_PATCH_CONTENT_TYPE)
+            .body(patch.to_string().into())
+            .unwrap()
+    }
+
+    fn put_json(uri: &str, body: serde_json::Value) -> Request<axum::body::Body> {
+        Request::put(uri)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string().into())
+            .unwrap()
+    }
+
+    async fn count(state: &AppState) -> String {
+        let res = send(state, get("/contagem-pessoas")).await;
+        String::from_utf8(body_bytes(res).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn validated_json_rejects_bodies_that_fail_to_parse_or_validate() {
+        let state = AppState::in_memory(config());
+        let extract = |body: serde_json::Value| {
+            let req = Request::post("/pessoas")
+                .header(CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap();
+            let state = state.clone();
+            async move { ValidatedJson::<CreatePersonPayload>::from_request(req, &state).await }
+        };
+
+        let valid = json!({ "apelido": "ana", "nome": "Ana Barros", "nascimento": "1985-09-23" });
+        let ValidatedJson(payload) = extract(valid).await.unwrap();
+        assert_eq!(payload.nickname, "ana");
+
+        let invalid = json!({ "apelido": "a".repeat(33), "nome": "", "nascimento": "1985-09-23" });
+        match extract(invalid).await {
+            Err(AppError::Validation(errors)) => assert_eq!(errors.len(), 2, "{errors:?}"),
+            other => panic!("expected a validation error, got {:?}", other.err()),
+        }
+
+        let unparsable = json!({ "apelido": "ana", "nascimento": "1985-09-23" });
+        assert!(matches!(
+            extract(unparsable).await,
+            Err(AppError::InvalidJsonRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn person_events_stream_created_people_until_shutdown() {
+        let state = AppState::in_memory(config());
+        let mut events = send(&state, get("/pessoas/events")).await.into_body();
+
+        let ana = json!({ "apelido": "ana", "nome": "Ana Barros", "nascimento": "1985-09-23" });
+        let created = send(&state, post_json("/pessoas", ana)).await;
+        assert_eq!(created.status(), StatusCode::CREATED);
+        let frame = events.data().await.unwrap().unwrap();
+        let frame = String::from_utf8(frame.to_vec()).unwrap();
+        assert!(frame.starts_with("event:person\n"), "{frame}");
+        assert!(frame.contains(r#""apelido":"ana""#), "{frame}");
+
+        state.shutdown.fire();
+        let end = tokio::time::timeout(Duration::from_secs(1), events.data()).await;
+        assert!(end.expect("the stream ends on shutdown").is_none());
+    }
+
+    #[tokio::test]
+    async fn similar_people_share_the_most_stacks_first() {
+        let state = AppState::in_memory(config());
+        let ana = create(&state, "ana", &["rust", "go", "sql"]).await;
+        create(&state, "bia", &["go", "java"]).await;
+        create(&state, "cris", &["sql", "rust", "go", "c"]).await;
+        create(&state, "dani", &["java"]).await;
+        create(&state, "eva", &["rust", "sql"]).await;
+        let loner = create(&state, "fabi", &[]).await;
+
+        let res = send(&state, get(&format!("/pessoas/{ana}/similares"))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(nicknames(&body_json(res).await), ["cris", "eva", "bia"]);
+
+        let res = send(&state, get(&format!("/pessoas/{ana}/similares?limit=2"))).await;
+        assert_eq!(nicknames(&body_json(res).await), ["cris", "eva"]);
+
+        let res = send(&state, get(&format!("/pessoas/{loner}/similares"))).await;
+        assert_eq!(body_json(res).await, json!([]));
+
+        let res = send(&state, get("/pessoas/999/similares")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn search_drops_the_rows_past_the_response_budget() {
+        let mut config = config();
+        config.max_search_response_bytes = 1000;
+        let state = AppState::in_memory(config);
+        let stacks: Vec<String> = (0..20).map(|i| format!("rust-framework-{i:02}")).collect();
+        let stacks: Vec<&str> = stacks.iter().map(String::as_str).collect();
+        for nickname in ["ana", "bia", "cris"] {
+            create(&state, nickname, &stacks).await;
+        }
+
+        let res = send(&state, get("/pessoas?t=rust-framework")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[&RESULTS_TRUNCATED], "true");
+        let body = body_bytes(res).await;
+        assert!(body.len() <= 1000, "{} bytes", body.len());
+        let people: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(nicknames(&people), ["ana"]);
+
+        create(&state, "dani", &["go"]).await;
+        let res = send(&state, get("/pessoas?t=go")).await;
+        assert!(res.headers().get(&RESULTS_TRUNCATED).is_none());
+        assert_eq!(nicknames(&body_json(res).await), ["dani"]);
+    }
+
+    #[tokio::test]
+    async fn search_counts_the_matches_only_when_asked() {
+        let state = AppState::in_memory(config());
+        for nickname in ["ana", "bia", "cris"] {
+            create(&state, nickname, &["go"]).await;
+        }
+
+        let res = send(&state, get("/pessoas?t=go&limit=1&with_total=true")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[&TOTAL_COUNT], "3");
+        assert_eq!(nicknames(&body_json(res).await), ["ana"]);
+
+        let res = send(&state, get("/pessoas?t=go&limit=1&with_total=false")).await;
+        assert!(res.headers().get(&TOTAL_COUNT).is_none());
+        assert_eq!(nicknames(&body_json(res).await), ["ana"]);
+
+        let res = send(&state, get("/pessoas?t=go&limit=1")).await;
+        assert!(res.headers().get(&TOTAL_COUNT).is_none());
+    }
+
+    #[tokio::test]
+    async fn only_tracked_reads_count_as_views() {
+        let config = config();
+        let repo = Arc::new(InMemoryPersonRepository::new(&config));
+        let state = AppState::new(repo.clone(), config, None);
+        let id = create(&state, "ana", &[]).await;
+        let views = || repo.people()[&id].view_count;
+
+        let res = send(&state, get(&format!("/pessoas/{id}"))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(views(), 0);
+        let res = send(&state, get(&format!("/pessoas/{id}?track=false"))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(views(), 0);
+
+        for expected in 1..=2 {
+            let res = send(&state, get(&format!("/pessoas/{id}?track=true"))).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(body_json(res).await["apelido"], "ana");
+            assert_eq!(views(), expected);
+        }
+
+        let res = send(&state, get("/pessoas/999?track=true")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn deletes_honor_the_version_of_if_match() {
+        let state = AppState::in_memory(config());
+        let id = create(&state, "ana", &[]).await;
+        let uri = format!("/pessoas/{id}");
+        let delete = |if_match: HeaderValue| {
+            Request::delete(&uri)
+                .header(IF_MATCH, if_match)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        let res = send(&state, get(&uri)).await;
+        let read = res.headers()[ETAG].clone();
+        let res = send(&state, merge_patch(&uri, json!({ "nome": "Ana Souza" }))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let current = res.headers()[ETAG].clone();
+
+        // updated since it was read
+        let res = send(&state, delete(read)).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let res = send(&state, delete(HeaderValue::from_static("not-a-version"))).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(send(&state, get(&uri)).await.status(), StatusCode::OK);
+
+        let res = send(&state, delete(current)).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            send(&state, get(&uri)).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn masked_people_hide_the_name_and_birth_date() {
+        let state = AppState::in_memory(config());
+        let id = person(&state, "joao", "João Silva", "1985-09-23", &["rust"]).await;
+
+        let res = send(&state, get(&format!("/pessoas/{id}"))).await;
+        let unmasked = body_json(res).await;
+        assert_eq!(unmasked["nome"], "João Silva");
+        assert_eq!(unmasked["nascimento"], "1985-09-23");
+
+        let res = send(&state, get(&format!("/pessoas/{id}?mask=true"))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let masked = body_json(res).await;
+        assert_eq!(masked["nome"], "Jo** S****");
+        assert!(masked.get("nascimento").is_none(), "{masked}");
+        assert_eq!(masked["apelido"], "joao");
+        assert_eq!(masked["stack"], json!(["rust"]));
+        assert_eq!(masked["id"], id);
+    }
+
+    #[tokio::test]
+    async fn random_person_picks_someone_or_404s_when_nobody_exists() {
+        let state = AppState::in_memory(config());
+        let res = send(&state, get("/pessoas/aleatorio")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let ana = create(&state, "ana", &["go"]).await;
+        let bia = create(&state, "bia", &[]).await;
+        for _ in 0..5 {
+            let res = send(&state, get("/pessoas/aleatorio")).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let person = body_json(res).await;
+            assert!([ana, bia].contains(&person["id"].as_i64().unwrap()));
+            assert_eq!(person["nome"], "Ana Barros");
+            assert_eq!(person["nascimento"], "1985-09-23");
+        }
+    }
+
+    #[tokio::test]
+    async fn padded_search_terms_match_like_trimmed_ones() {
+        let state = AppState::in_memory(config());
+        create(&state, "ana", &["rust", "node"]).await;
+        create(&state, "bia", &["rust"]).await;
+
+        let res = send(&state, get("/pessoas?t=%20%20rust%20%20%20node%20")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(nicknames(&body_json(res).await), ["ana"]);
+
+        let res = send(&state, get("/pessoas?t=rust%20")).await;
+        assert_eq!(nicknames(&body_json(res).await), ["ana", "bia"]);
+
+        let res = send(&state, get("/pessoas?t=%20%20%20")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_json(res).await["detail"], "t must not be blank");
+    }
+
+    #[tokio::test]
+    async fn searches_without_matches_answer_the_configured_status() {
+        let state = AppState::in_memory(config());
+        create(&state, "ana", &["rust"]).await;
+        let res = send(&state, get("/pessoas?t=cobol")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_json(res).await, json!([]));
+
+        let config = with_env(&[("SEARCH_EMPTY_STATUS", "404")], Config::from_env).unwrap();
+        let state = AppState::in_memory(config);
+        create(&state, "ana", &["rust"]).await;
+        let res = send(&state, get("/pessoas?t=cobol")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body_json(res).await["code"], "NoResults");
+        // only an empty first page means nothing matched
+        let res = send(&state, get("/pessoas?t=rust&offset=10")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = send(&state, get("/pessoas?t=rust")).await;
+        assert_eq!(nicknames(&body_json(res).await), ["ana"]);
+    }
+
+    #[tokio::test]
+    async fn search_timing_is_reported_only_with_the_flag() {
+        let state = AppState::in_memory(config());
+        create(&state, "ana", &["rust"]).await;
+
+        let res = send(&state, get("/pessoas?t=rust")).await;
+        assert!(body_json(res).await.is_array());
+        let res = send(&state, get("/pessoas?t=rust&debug_timing=false")).await;
+        assert!(body_json(res).await.is_array());
+
+        let res = send(&state, get("/pessoas?t=rust&debug_timing=true")).await;
+        let timed = body_json(res).await;
+        assert!(timed["took_ms"].is_u64(), "{timed}");
+        assert_eq!(nicknames(&timed["items"]), ["ana"]);
+        // paging adds its own fields beside it
+        let res = send(&state, get("/pessoas?t=rust&debug_timing=true&paged=true")).await;
+        let timed = body_json(res).await;
+        assert!(timed["took_ms"].is_u64(), "{timed}");
+        assert_eq!(timed["total"], 1);
+    }
+
+    #[tokio::test]
+    async fn paged_searches_stop_after_the_last_page() {
+        let state = AppState::in_memory(config());
+        for nickname in ["ana", "bia", "cris", "duda"] {
+            create(&state, nickname, &["rust"]).await;
+        }
+
+        let res = send(&state, get("/pessoas?t=rust&paged=true&limit=2")).await;
+        let page = body_json(res).await;
+        assert_eq!(nicknames(&page["items"]), ["ana", "bia"]);
+        assert_eq!(page["total"], 4);
+        assert_eq!(page["next_offset"], 2);
+        // a full last page has nothing after it
+        let res = send(&state, get("/pessoas?t=rust&paged=true&limit=2&offset=2")).await;
+        let page = body_json(res).await;
+        assert_eq!(nicknames(&page["items"]), ["cris", "duda"]);
+        assert_eq!(page["next_offset"], json!(null));
+
+        let res = send(&state, get("/pessoas?t=rust&paged=true&limit=3&offset=3")).await;
+        let page = body_json(res).await;
+        assert_eq!(nicknames(&page["items"]), ["duda"]);
+        assert_eq!(page["next_offset"], json!(null));
+    }
+
+    #[tokio::test]
+    async fn repeated_query_parameters_are_bad_requests() {
+        let state = AppState::in_memory(config());
+        create(&state, "ana", &["rust"]).await;
+
+        for uri in [
+            "/pessoas?t=rust&t=go",
+            "/pessoas?t=rust&limit=1&limit=2",
+            "/pessoas?t=a&t=a",
+        ] {
+            let res = send(&state, get(uri)).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{uri}");
+            let error = body_json(res).await;
+            assert!(
+                error["detail"]
+                    .as_str()
+                    .unwrap()
+                    .contains("must not be repeated"),
+                "{error}"
+            );
+        }
+        // an encoded key is the same parameter
+        let res = send(&state, get("/pessoas?t=rust&%74=go")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = send(&state, get("/pessoas?t=rust&limit=1")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn search_excludes_the_negated_terms() {
+        let state = AppState::in_memory(config());
+        create(&state, "ana", &["rust", "java"]).await;
+        create(&state, "bia", &["rust"]).await;
+        create(&state, "cris", &["java"]).await;
+        create(&state, "java-dev", &["rust"]).await;
+
+        let res = send(&state, get("/pessoas?t=rust%20-java")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(nicknames(&body_json(res).await), ["bia"]);
+
+        // exclusions ignore case
+        let res = send(&state, get("/pessoas?t=rust%20-JAVA")).await;
+        assert_eq!(nicknames(&body_json(res).await), ["bia"]);
+
+        let res = send(&state, get("/pessoas?t=rust")).await;
+        assert_eq!(nicknames(&body_json(res).await), ["ana", "bia", "java-dev"]);
+    }
+
+    #[tokio::test]
+    async fn people_are_found_by_nicknames_ignoring_case() {
+        let state = AppState::in_memory(config());
+        create(&state, "Ana", &[]).await;
+        create(&state, "bia", &[]).await;
+        create(&state, "cris", &[]).await;
+
+        let lookup = |nicknames: serde_json::Value| {
+            post_json("/pessoas/by-nicknames", json!({ "apelidos": nicknames }))
+        };
+        let res = send(&state, lookup(json!(["ana", "BIA", "nobody"]))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let mut found = nicknames(&body_json(res).await)
+            .into_iter()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, ["Ana", "bia"]);
+
+        let res = send(&state, lookup(json!(["nobody"]))).await;
+        assert_eq!(body_json(res).await, json!([]));
+
+        let too_many = vec!["x"; MAX_NICKNAMES_LOOKUP + 1];
+        let res = send(&state, lookup(json!(too_many))).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn people_are_created_fetched_and_searched_without_a_database() {
+        let state = AppState::in_memory(config());
+        let ana = json!({
+            "apelido": "ana",
+            "nome": "Ana Barros",
+            "nascimento": "1985-09-23",
+            "stack": ["Rust", "Go"],
+        });
+
+        let res = send(&state, post_json("/pessoas", ana.clone())).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let created = body_json(res).await;
+        let res = send(&state, get(&format!("/pessoas/{}", created["id"]))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_json(res).await, created);
+
+        let res = send(&state, post_json("/pessoas", ana)).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let res = send(&state, get("/pessoas/999")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // substrings of the nickname, name or stacks, matched like `LIKE` does
+        for term in ["an", "Barros", "Rus"] {
+            let res = send(&state, get(&format!("/pessoas?t={term}"))).await;
+            assert_eq!(nicknames(&body_json(res).await), ["ana"], "{term}");
+        }
+        let res = send(&state, get("/pessoas?t=java")).await;
+        assert_eq!(body_json(res).await, json!([]));
+    }
+
+    #[tokio::test]
+    async fn created_people_are_located_by_their_id() {
+        let state = AppState::in_memory(config());
+        create(&state, "ana", &[]).await;
+        let res = send(
+            &state,
+            post_json(
+                "/pessoas",
+                json!({ "apelido": "bia", "nome": "Bia", "nascimento": "1990-01-01" }),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let location = res.headers()[LOCATION].to_str().unwrap().to_owned();
+        let created = body_json(res).await;
+        assert_eq!(location, format!("/pessoas/{}", created["id"]));
+
+        let res = send(&state, get(&location)).await;
+        assert_eq!(body_json(res).await, created);
+    }
+
+    #[tokio::test]
+    async fn nicknames_with_invalid_characters_are_unprocessable() {
+        let state = AppState::in_memory(config());
+        let res = send(
+            &state,
+            post_json(
+                "/pessoas",
+                json!({ "apelido": "ana/b", "nome": "Ana", "nascimento": "1985-09-23" }),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let error = body_json(res).await;
+        assert!(error["detail"].as_str().unwrap().contains("'/'"), "{error}");
+        assert_eq!(count(&state).await, "0");
+    }
+
+    #[tokio::test]
+    async fn taken_nicknames_are_unique_violations() {
+        let state = AppState::in_memory(config());
+        create(&state, "ana", &[]).await;
+
+        let again = json!({ "apelido": "ana", "nome": "Ana Souza", "nascimento": "1990-01-02" });
+        let res = send(&state, post_json("/pessoas", again)).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let error = body_json(res).await;
+        assert_eq!(error["code"], "UniqueViolation");
+        assert_eq!(error["detail"], "'apelido' value 'ana' is already taken");
+    }
+
+    #[tokio::test]
+    async fn admins_page_through_everyone_by_id() {
+        let state = admin_state();
+        let mut ids = Vec::new();
+        for nickname in ["ana", "bia", "cris", "dani", "eva"] {
+            ids.push(create(&state, nickname, &[]).await);
+        }
+
+        let res = send(&state, get("/admin/pessoas")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let mut listed = Vec::new();
+        let mut uri = "/admin/pessoas?limit=2".to_owned();
+        let mut pages = 0;
+        loop {
+            let res = send(&state, admin_get(&uri)).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let page = body_json(res).await;
+            let items = page["items"].as_array().unwrap();
+            assert!(items.len() <= 2);
+            listed.extend(items.iter().map(|p| p["id"].as_i64().unwrap()));
+            pages += 1;
+            match page["next_cursor"].as_i64() {
+                Some(cursor) => uri = format!("/admin/pessoas?limit=2&cursor={cursor}"),
+                None => break,
+            }
+        }
+        assert_eq!(pages, 3);
+        assert_eq!(listed, ids);
+    }
+
+    #[tokio::test]
+    async fn oversized_and_deeply_nested_payloads_are_rejected() {
+        let state = AppState::in_memory(config());
+        let with_stack = |stack: serde_json::Value| json!({ "apelido": "ana", "nome": "Ana", "nascimento": "1985-09-23", "stack": stack });
+
+        let many: Vec<String> = (0..=MAX_STACK_ENTRIES).map(|i| format!("s{i}")).collect();
+        let res = send(&state, post_json("/pessoas", with_stack(json!(many)))).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let error = body_json(res).await;
+        assert!(
+            error["detail"]
+                .as_str()
+                .unwrap()
+                .contains(&too_many_stacks()),
+            "{error}"
+        );
+
+        let huge = "x".repeat(10_000);
+        let res = send(&state, post_json("/pessoas", with_stack(json!([huge])))).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // past serde_json's recursion limit of 128
+        let nested = format!(
+            r#"{{"apelido": "ana", "nome": "Ana", "nascimento": "1985-09-23", "stack": {}"rust"{}}}"#,
+            "[".repeat(200),
+            "]".repeat(200)
+        );
+        let req = Request::post("/pessoas")
+            .header(CONTENT_TYPE, "application/json")
+            .body(nested.into())
+            .unwrap();
+        let res = send(&state, req).await;
+        // serde_json reports it as a syntax error, a 400 like any body that isn't JSON
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let error = body_json(res).await;
+        assert!(
+            error["detail"]
+                .as_str()
+                .unwrap()
+                .contains("recursion limit"),
+            "{error}"
+        );
+        assert_eq!(count(&state).await, "0");
+    }
+
+    #[tokio::test]
+    async fn id_ranges_include_both_boundaries() {
+        let rows = (1..=6).map(|id| row(id, &format!("dev{id}"), "Ana Barros", "1985-09-23"));
+        let state = seeded(rows.collect());
+        let ids = |people: serde_json::Value| -> Vec<i64> {
+            people
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["id"].as_i64().unwrap())
+                .collect()
+        };
+
+        let res = send(&state, get("/admin/pessoas/range?from_id=2&to_id=4")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = send(&state, admin_get("/admin/pessoas/range?from_id=2&to_id=4")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(&RESULTS_TRUNCATED).is_none());
+        assert_eq!(ids(body_json(res).await), [2, 3, 4]);
+        let res = send(&state, admin_get("/admin/pessoas/range?from_id=5&to_id=5")).await;
+        assert_eq!(ids(body_json(res).await), [5]);
+        let res = send(&state, admin_get("/admin/pessoas/range?from_id=4&to_id=2")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let mut config = config();
+        config.admin_token = Some("secret".to_owned());
+        config.id_range_batch_size = 2;
+        let repo = InMemoryPersonRepository::new(&config);
+        for id in 1..=6 {
+            repo.people_mut()
+                .insert(id, row(id, &format!("dev{id}"), "Ana Barros", "1985-09-23"));
+        }
+        let state = AppState::new(Arc::new(repo), config, None);
+        let res = send(&state, admin_get("/admin/pessoas/range?from_id=1&to_id=6")).await;
+        assert_eq!(res.headers()[&RESULTS_TRUNCATED], "true");
+        assert_eq!(ids(body_json(res).await), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn stacks_past_the_byte_budget_are_rejected() {
+        let mut config = config();
+        // `["ab","cd"]`
+        config.max_stacks_bytes = 11;
+        let state = AppState::in_memory(config);
+        let id = create(&state, "ana", &["ab", "cd"]).await;
+
+        let over = json!({ "apelido": "bia", "nome": "Bia", "nascimento": "1990-01-02", "stack": ["ab", "cde"] });
+        let res = send(&state, post_json("/pessoas", over)).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let error = body_json(res).await;
+        assert_eq!(
+            error["detail"],
+            "stack must be at most 11 bytes when serialized, got 12"
+        );
+
+        let uri = format!("/pessoas/{id}");
+        let res = send(&state, merge_patch(&uri, json!({ "stack": ["abc", "cd"] }))).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let res = send(&state, merge_patch(&uri, json!({ "stack": ["cd", "ab"] }))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn people_are_counted_by_birth_year() {
+        let state = AppState::in_memory(config());
+        let res = send(&state, get("/pessoas/stats/por-ano")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_json(res).await, json!([]));
+
+        born(&state, "ana", "1990-12-31").await;
+        born(&state, "bia", "1985-01-01").await;
+        born(&state, "cris", "1990-01-01").await;
+        born(&state, "dani", "2001-06-15").await;
+
+        let res = send(&state, get("/pessoas/stats/por-ano")).await;
+        assert_eq!(
+            body_json(res).await,
+            json!([
+                { "ano": 1990, "count": 2 },
+                { "ano": 1985, "count": 1 },
+                { "ano": 2001, "count": 1 },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn aggregation_ties_are_broken_by_key() {
+        let state = AppState::in_memory(config());
+        // inserted out of key order, so only the sort can put the ties in order
+        person(&state, "ana", "Ana", "2001-01-01", &["rust", "zig"]).await;
+        person(&state, "bia", "Bia", "1985-01-01", &["go", "zig"]).await;
+        person(&state, "cris", "Cris", "1999-01-01", &["rust", "go", "ada"]).await;
+
+        for _ in 0..3 {
+            let res = send(&state, get("/stacks/trending")).await;
+            assert_eq!(
+                body_json(res).await,
+                json!([
+                    { "stack": "go", "count": 2 },
+                    { "stack": "rust", "count": 2 },
+                    { "stack": "zig", "count": 2 },
+                    { "stack": "ada", "count": 1 },
+                ])
+            );
+            let res = send(&state, get("/pessoas/stats/por-ano")).await;
+            assert_eq!(
+                body_json(res).await,
+                json!([
+                    { "ano": 1985, "count": 1 },
+                    { "ano": 1999, "count": 1 },
+                    { "ano": 2001, "count": 1 },
+                ])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn count_breakdown_splits_the_total_by_stacks() {
+        let state = AppState::in_memory(config());
+        create(&state, "ana", &["rust"]).await;
+        create(&state, "bia", &["go", "rust"]).await;
+        create(&state, "cris", &[]).await;
+        create_json(
+            &state,
+            json!({ "apelido": "dani", "nome": "Dani", "nascimento": "1990-01-01", "stack": null }),
+        )
+        .await;
+
+        let res = send(&state, get("/contagem-pessoas?breakdown=true")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let breakdown = body_json(res).await;
+        assert_eq!(
+            breakdown,
+            json!({ "total": 4, "with_stacks": 2, "without_stacks": 2 })
+        );
+        assert_eq!(count(&state).await, "4");
+    }
+
+    #[tokio::test]
+    async fn db_info_is_reported_to_admins_only() {
+        let state = admin_state();
+        let res = send(&state, get("/info/db")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = send(&state, admin_get("/info/db")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            body_json(res).await,
+            json!({ "migration_version": null, "server_version": "in-memory" })
+        );
+    }
+
+    #[tokio::test]
+    async fn incomplete_people_lack_the_stacks_detail_of_their_stacks() {
+        let with_stacks = |id, detailed: bool| {
+            let stacks = vec!["rust".to_owned()];
+            Person {
+                stacks_detail: detailed.then(|| {
+                    sqlx::types::Json(vec![Stack {
+                        name: "rust".to_owned(),
+                        level: Some(3),
+                    }])
+                }),
+                stacks: Some(stacks),
+                ..row(id, &format!("dev{id}"), "Ana Barros", "1985-09-23")
+            }
+        };
+        let state = seeded(vec![
+            with_stacks(1, false),
+            with_stacks(2, true),
+            row(3, "dev3", "Ana Barros", "1985-09-23"),
+            with_stacks(4, false),
+            with_stacks(5, false),
+        ]);
+
+        let res = send(&state, get("/admin/pessoas/incompletas")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = send(&state, admin_get("/admin/pessoas/incompletas?limit=2")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let page = body_json(res).await;
+        assert_eq!(page["items"][0]["id"], 1);
+        assert_eq!(page["items"][1]["id"], 4);
+        assert_eq!(page["next_cursor"], 4);
+
+        let res = send(&state, admin_get("/admin/pessoas/incompletas?cursor=4")).await;
+        let page = body_json(res).await;
+        assert_eq!(page["items"].as_array().unwrap().len(), 1);
+        assert_eq!(page["items"][0]["id"], 5);
+        assert_eq!(page["next_cursor"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn dashboard_stats_bundle_the_counts_stacks_ages_and_newest() {
+        let state = AppState::in_memory(config());
+        let res = send(&state, get("/stats")).await;
+        let empty = body_json(res).await;
+        assert_eq!(empty["total"], 0);
+        assert_eq!(empty["age"]["min"], serde_json::Value::Null);
+        assert_eq!(empty["newest"], serde_json::Value::Null);
+
+        person(&state, "ana", "Ana", "1970-01-01", &["rust", "go", "ada"]).await;
+        person(&state, "bia", "Bia", "1990-06-15", &["rust", "go", "c"]).await;
+        person(&state, "cris", "Cris", "2000-12-31", &["rust", "zig", "d"]).await;
+        person(&state, "dani", "Dani", "2000-12-31", &[]).await;
+
+        let res = send(&state, get("/stats")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let stats = body_json(res).await;
+        assert_eq!(stats["total"], 4);
+        assert_eq!(stats["with_stacks"], 3);
+        assert_eq!(stats["without_stacks"], 1);
+        assert_eq!(
+            stats["top_stacks"],
+            json!([
+                { "stack": "rust", "count": 3 },
+                { "stack": "go", "count": 2 },
+                { "stack": "ada", "count": 1 },
+                { "stack": "c", "count": 1 },
+                { "stack": "d", "count": 1 },
+            ])
+        );
+        let today = Utc::now().date_naive();
+        let age = |dob: &str| crate::repository::age_in_years(dob.parse().unwrap(), today);
+        let ages = [
+            age("1970-01-01"),
+            age("1990-06-15"),
+            age("2000-12-31"),
+            age("2000-12-31"),
+        ];
+        assert_eq!(stats["age"]["min"], ages[2]);
+        assert_eq!(stats["age"]["max"], ages[0]);
+        assert_eq!(
+            stats["age"]["avg"],
+            f64::from(ages.iter().sum::<i32>()) / 4.0
+        );
+        assert_eq!(stats["age"]["excluded"], 0);
+        assert_eq!(stats["newest"]["apelido"], "dani");
+    }
+
+    #[tokio::test]
+    async fn similar_names_are_paired_as_duplicate_candidates() {
+        let state = seeded(vec![
+            row(1, "ana", "Ana Barros", "1985-09-23"),
+            row(2, "bia", "Bia Souza", "1990-01-01"),
+            row(3, "anab", "ana barros", "1985-09-23"),
+            row(4, "cris", "Cris Lima", "1999-01-01"),
+            row(5, "bias", "BIA SOUZA", "1990-01-01"),
+        ]);
+
+        let res = send(&state, get("/admin/pessoas/duplicados")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = send(&state, admin_get("/admin/pessoas/duplicados")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let pairs = body_json(res).await;
+        assert_eq!(pairs.as_array().unwrap().len(), 2);
+        assert_eq!(pairs[0]["first_id"], 1);
+        assert_eq!(pairs[0]["second_id"], 3);
+        assert_eq!(pairs[0]["second_name"], "ana barros");
+        assert_eq!(pairs[1]["first_id"], 2);
+        assert_eq!(pairs[1]["second_id"], 5);
+
+        let res = send(
+            &state,
+            admin_get("/admin/pessoas/duplicados?limit=1&offset=1"),
+        )
+        .await;
+        let page = body_json(res).await;
+        assert_eq!(page.as_array().unwrap().len(), 1);
+        assert_eq!(page[0]["first_id"], 2);
+    }
+
+    #[tokio::test]
+    async fn self_test_succeeds_every_step_and_leaves_nobody_behind() {
+        let state = admin_state();
+        let self_test = || {
+            as_admin(
+                Request::post("/admin/selftest")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        let res = send(&state, self_test()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let report = body_json(res).await;
+        assert_eq!(report["ok"], true);
+        let steps = report["steps"].as_array().unwrap();
+        let names: Vec<&str> = steps.iter().map(|s| s["step"].as_str().unwrap()).collect();
+        assert_eq!(names, ["create", "read", "search", "delete"]);
+        for step in steps {
+            assert_eq!(step["ok"], true, "{step}");
+            assert!(step["took_ms"].is_u64(), "{step}");
+        }
+        assert_eq!(count(&state).await, "0");
+
+        let mut config = config();
+        config.admin_token = Some("secret".to_owned());
+        config.production = true;
+        let state = AppState::in_memory(config);
+        let res = send(&state, self_test()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn count_stream_ends_with_the_exact_count() {
+        let state = AppState::in_memory(config());
+        create(&state, "ana", &[]).await;
+        create(&state, "bia", &[]).await;
+
+        let res = send(&state, get("/contagem-pessoas/stream")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/event-stream");
+        // the stream closes once the count is sent
+        let body = tokio::time::timeout(Duration::from_secs(5), body_bytes(res))
+            .await
+            .unwrap();
+        let body = String::from_utf8(body).unwrap();
+        let events: Vec<&str> = body.split("\n\n").filter(|e| !e.is_empty()).collect();
+        assert_eq!(events.last(), Some(&"event:count\ndata:2"), "{body}");
+        for event in &events[..events.len() - 1] {
+            assert!(event.starts_with("event:estimate\n"), "{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn suspicious_people_are_the_ones_today_validation_would_flag() {
+        let state = seeded(vec![
+            row(1, "ana", "Ana Barros", "1985-09-23"),
+            row(2, "bia", "BIA", "1990-01-02"),
+            row(3, "cris", "C", "1990-01-02"),
+            row(4, "dani", "Dani Lopes", "1850-01-01"),
+            row(5, "eva", "Eva Melo", "2999-01-01"),
+        ]);
+
+        let res = send(&state, get("/admin/pessoas/suspeitas")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = send(&state, admin_get("/admin/pessoas/suspeitas")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            nicknames(&body_json(res).await),
+            ["bia", "cris", "dani", "eva"]
+        );
+    }
+
+    #[tokio::test]
+    async fn photos_are_set_only_to_http_urls_of_existing_people() {
+        let state = AppState::in_memory(config());
+        let id = create(&state, "ana", &[]).await;
+        let uri = format!("/pessoas/{id}/foto");
+
+        let photo = json!({ "foto": "https://cdn.example/ana.png" });
+        let res = send(&state, put_json(&uri, photo)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_json(res).await["foto"], "https://cdn.example/ana.png");
+        let res = send(&state, get(&format!("/pessoas/{id}"))).await;
+        assert_eq!(body_json(res).await["foto"], "https://cdn.example/ana.png");
+
+        for invalid in ["ftp://cdn.example/ana.png", "ana.png", "https://"] {
+            let res = send(&state, put_json(&uri, json!({ "foto": invalid }))).await;
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY, "{invalid}");
+        }
+
+        let photo = json!({ "foto": "https://cdn.example/nobody.png" });
+        let res = send(&state, put_json("/pessoas/999/foto", photo)).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn removing_stacks_keeps_the_order_of_the_others() {
+        let state = AppState::in_memory(config());
+        let id = create(&state, "ana", &["rust", "go", "rust", "c", "zig"]).await;
+        let remove = |id: i64, body: serde_json::Value| {
+            post_json(&format!("/pessoas/{id}/stacks/remove"), body)
+        };
+
+        let res = send(
+            &state,
+            remove(id, json!({ "stack": ["rust", "c", "cobol"] })),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_json(res).await["stack"], json!(["go", "zig"]));
+        // already removed, which is no error
+        let res = send(&state, remove(id, json!({ "stack": ["rust"] }))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_json(res).await["stack"], json!(["go", "zig"]));
+        let res = send(&state, get(&format!("/pessoas/{id}"))).await;
+        assert_eq!(body_json(res).await["stack"], json!(["go", "zig"]));
+
+        let res = send(&state, remove(id, json!({ "stack": [] }))).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let res = send(&state, remove(id, json!({ "stack": [" "] }))).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let res = send(&state, remove(id + 1, json!({ "stack": ["go"] }))).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn merge_patches_clear_null_fields_and_keep_absent_ones() {
+        let state = AppState::in_memory(config());
+        let id = create(&state, "ana", &["rust", "go"]).await;
+        let uri = format!("/pessoas/{id}");
+
+        let res = send(&state, merge_patch(&uri, json!({ "stack": null }))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let person = body_json(res).await;
+        assert_eq!(person["stack"], serde_json::Value::Null);
+        assert_eq!(person["nome"], "Ana Barros");
+        assert_eq!(person["apelido"], "ana");
+
+        let res = send(
+            &state,
+            merge_patch(&uri, json!({ "nascimento": "1990-01-02" })),
+        )
+        .await;
+        let person = body_json(res).await;
+        assert_eq!(person["nascimento"], "1990-01-02");
+        assert_eq!(person["nome"], "Ana Barros");
+        assert_eq!(person["stack"], serde_json::Value::Null);
+
+        // the name is required, so it can't be cleared
+        let res = send(&state, merge_patch(&uri, json!({ "nome": null }))).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let plain = Request::patch(&uri)
+            .header(CONTENT_TYPE, "application/json")
+            .body(json!({ "nome": "Ana" }).to_string().into())
+            .unwrap();
+        let res = send(&state, plain).await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn revalidation_reports_the_rows_failing_todays_rules() {
+        let state = seeded(vec![
+            row(1, "ana", "Ana Barros", "1985-09-23"),
+            row(2, "bia", "Bia Souza", "1850-01-01"),
+            row(3, "cris", &"C".repeat(150), "1990-01-02"),
+            row(4, "dani", "Dani Lopes", "1990-01-02"),
+            row(5, &"e".repeat(40), "Eva Melo", "1990-01-02"),
+        ]);
+        let revalidate =
+            |uri: &str| as_admin(Request::post(uri).body(axum::body::Body::empty()).unwrap());
+
+        let res = send(&state, post_json("/admin/pessoas/revalidate", json!({}))).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = send(&state, revalidate("/admin/pessoas/revalidate?limit=3")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let page = body_json(res).await;
+        let failing: Vec<i64> = page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(failing, [2, 3]);
+        assert!(page["items"][0]["errors"][0]
+            .as_str()
+            .unwrap()
+            .contains("1900-01-01"));
+        assert_eq!(page["next_cursor"], 3);
+
+        let res = send(
+            &state,
+            revalidate("/admin/pessoas/revalidate?limit=3&cursor=3"),
+        )
+        .await;
+        let page = body_json(res).await;
+        assert_eq!(page["items"][0]["id"], 5);
+        assert_eq!(page["items"].as_array().unwrap().len(), 1);
+        assert_eq!(page["next_cursor"], serde_json::Value::Null);
+        // nothing was changed
+        let res = send(&state, get("/pessoas/2")).await;
+        assert_eq!(body_json(res).await["nascimento"], "1850-01-01");
+    }
+
+    #[tokio::test]
+    async fn search_results_come_as_escaped_csv_on_request() {
+        let state = AppState::in_memory(config());
+        create_json(
+            &state,
+            json!({
+                "apelido": "ana",
+                "nome": "Ana \"Aninha\" Barros, Jr",
+                "nascimento": "1985-09-23",
+                "stack": ["rust", "c,c++"],
+            }),
+        )
+        .await;
+        create(&state, "bia", &[]).await;
+        let csv = Request::get("/pessoas?t=Barros")
+            .header(ACCEPT, "text/csv")
+            .body(axum::body::Body::empty())
+            .unwrap();
+
+        let res = send(&state, csv).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/csv; charset=utf-8");
+        let body = String::from_utf8(body_bytes(res).await).unwrap();
+        assert_eq!(
+            body,
+            "apelido,nome,nascimento,stack\r\n\
+             ana,\"Ana \"\"Aninha\"\" Barros, Jr\",1985-09-23,\"rust;c,c++\"\r\n\
+             bia,Ana Barros,1985-09-23,\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn trending_stacks_count_only_the_recently_created_people() {
+        let created = |id, days_ago, stacks: &[&str]| Person {
+            stacks: Some(stacks.iter().map(|s| s.to_string()).collect()),
+            created_at: Some(Utc::now() - chrono::Duration::days(days_ago)),
+            ..row(id, &format!("dev{id}"), "Ana Barros", "1985-09-23")
+        };
+        let state = seeded(vec![
+            created(1, 1, &["rust", "go"]),
+            created(2, 10, &["rust"]),
+            created(3, 45, &["cobol", "rust"]),
+            created(4, 50, &["cobol"]),
+            created(5, 400, &["fortran"]),
+        ]);
+
+        let res = send(&state, get("/stacks/trending?days=30")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            body_json(res).await,
+            json!([{ "stack": "rust", "count": 2 }, { "stack": "go", "count": 1 }])
+        );
+        let res = send(&state, get("/stacks/trending?days=60")).await;
+        assert_eq!(
+            body_json(res).await,
+            json!([
+                { "stack": "rust", "count": 3 },
+                { "stack": "cobol", "count": 2 },
+                { "stack": "go", "count": 1 },
+            ])
+        );
+
+        for days in ["0", "366"] {
+            let res = send(&state, get(&format!("/stacks/trending?days={days}"))).await;
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    #[tokio::test]
+    async fn stack_counts_are_enriched_with_the_admin_curated_metadata() {
+        let state = admin_state();
+        create(&state, "ana", &["rust", "go"]).await;
+        create(&state, "bia", &["rust"]).await;
+        let meta = json!({ "display_name": "Rust", "icon": "https://icons.example/rust.svg" });
+
+        let res = send(&state, get("/meta/stacks/rust")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = send(&state, put_json("/meta/stacks/rust", meta.clone())).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = send(&state, as_admin(put_json("/meta/stacks/rust", meta))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = send(&state, get("/meta/stacks/rust")).await;
+        assert_eq!(
+            body_json(res).await,
+            json!({ "name": "rust", "display_name": "Rust", "icon": "https://icons.example/rust.svg" })
+        );
+        let res = send(&state, get("/stacks/trending")).await;
+        assert_eq!(
+            body_json(res).await,
+            json!([
+                {
+                    "stack": "rust",
+                    "count": 2,
+                    "display_name": "Rust",
+                    "icon": "https://icons.example/rust.svg",
+                },
+                { "stack": "go", "count": 1 },
+            ])
+        );
+        let res = send(&state, get("/stacks/go/relacionadas")).await;
+        assert_eq!(body_json(res).await[0]["display_name"], "Rust");
+    }
+
+    #[tokio::test]
+    async fn related_stacks_rank_the_ones_most_often_alongside() {
+        let state = AppState::in_memory(config());
+        create(&state, "ana", &["rust", "go", "sql"]).await;
+        create(&state, "bia", &["rust", "go"]).await;
+        // counted once per person however often it repeats
+        create(&state, "cris", &["rust", "sql", "sql", "rust"]).await;
+        create(&state, "dani", &["rust", "zig"]).await;
+        create(&state, "eva", &["java", "go", "kotlin"]).await;
+
+        let res = send(&state, get("/stacks/rust/relacionadas")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            body_json(res).await,
+            json!([
+                { "stack": "go", "count": 2 },
+                { "stack": "sql", "count": 2 },
+                { "stack": "zig", "count": 1 },
+            ])
+        );
+        let res = send(&state, get("/stacks/rust/relacionadas?limit=1")).await;
+        assert_eq!(body_json(res).await, json!([{ "stack": "go", "count": 2 }]));
+        let res = send(&state, get("/stacks/cobol/relacionadas")).await;
+        assert_eq!(body_json(res).await, json!([]));
+    }
+
+    #[tokio::test]
+    async fn numeric_stack_elements_are_rejected_naming_the_expected_shape() {
+        let state = AppState::in_memory(config());
+        let numeric = json!({
+            "apelido": "ana",
+            "nome": "Ana Barros",
+            "nascimento": "1985-09-23",
+            "stack": [1, 2, 3],
+        });
+
+        let res = send(&state, post_json("/pessoas", numeric)).await;
+        // as the Rinha spec wants, a field of the wrong type is a 400 rather than a 422
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let error = body_json(res).await;
+        let detail = error["detail"].as_str().unwrap();
+        assert!(detail.contains("stack"), "{detail}");
+        assert!(detail.contains(INVALID_STACK), "{detail}");
+        assert_eq!(count(&state).await, "0");
+    }
+
+    #[tokio::test]
+    async fn advanced_search_matches_exact_stacks_and_nickname_prefixes() {
+        let state = AppState::in_memory(config());
+        create(&state, "anab", &["Rust", "go"]).await;
+        create(&state, "anac", &["rustacean"]).await;
+        create(&state, "bia", &["rust"]).await;
+        create(&state, "xana", &["rust"]).await;
+        let search = |query: &str| {
+            let uri = format!("/pessoas/search-advanced?{query}");
+            let state = state.clone();
+            async move {
+                let res = send(&state, get(&uri)).await;
+                assert_eq!(res.status(), StatusCode::OK, "{uri}");
+                let people = body_json(res).await;
+                nicknames(&people)
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // by default the stack is exact and the nickname a prefix, both ignoring case
+        assert_eq!(search("apelido=ana&stack=rust").await, ["anab"]);
+        assert_eq!(search("apelido=ANA&stack=RUST").await, ["anab"]);
+        assert_eq!(
+            search("apelido=ana&stack=rust&stack_match=prefix").await,
+            ["anab", "anac"]
+        );
+        assert_eq!(
+            search("apelido=ana&apelido_match=substring&stack=rust").await,
+            ["anab", "xana"]
+        );
+        assert_eq!(
+            search("apelido=ana&apelido_match=exact&stack=rust").await,
+            Vec::<String>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn advanced_search_combines_the_term_dates_and_stack() {
+        let state = AppState::in_memory(config());
+        person(&state, "ana", "Ana Barros", "1985-09-23", &["rust", "go"]).await;
+        person(&state, "bia", "Bia Barros", "1989-01-02", &["rust"]).await;
+        person(&state, "cris", "Cris Barros", "1995-05-05", &["rust"]).await;
+        person(&state, "dani", "Dani Barros", "1987-07-07", &["java"]).await;
+        person(&state, "eva", "Eva Melo", "1986-06-06", &["rust"]).await;
+
+        let res = send(
+            &state,
+            get("/pessoas/search-advanced?t=Barros&from=1980-01-01&to=1990-12-31&stack=rust"),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(nicknames(&body_json(res).await), ["ana", "bia"]);
+
+        let res = send(
+            &state,
+            get("/pessoas/search-advanced?t=Barros&from=1980-01-01&to=1990-12-31&stack=rust&sort=-nascimento"),
+        )
+        .await;
+        assert_eq!(nicknames(&body_json(res).await), ["bia", "ana"]);
+
+        let res = send(
+            &state,
+            get("/pessoas/search-advanced?t=Barros&to=1986-01-01"),
+        )
+        .await;
+        assert_eq!(nicknames(&body_json(res).await), ["ana"]);
+
+        let res = send(&state, get("/pessoas/search-advanced?sort=dob")).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let res = send(
+            &state,
+            get("/pessoas/search-advanced?from=1990-01-01&to=1980-01-01"),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn timeline_pages_newest_first_across_a_shared_timestamp() {
+        let at = |id, created_at: &str| Person {
+            created_at: Some(created_at.parse().unwrap()),
+            ..row(id, &format!("dev{id}"), "Ana Barros", "1985-09-23")
+        };
+        let state = seeded(vec![
+            at(1, "2024-01-01T11:00:00Z"),
+            at(2, "2024-01-01T12:00:00Z"),
+            at(3, "2024-01-01T12:00:00Z"),
+            at(4, "2024-01-01T12:00:00Z"),
+            at(5, "2024-01-01T13:00:00Z"),
+        ]);
+
+        let mut pages = Vec::new();
+        let mut uri = "/pessoas/timeline?limit=2".to_owned();
+        loop {
+            let res = send(&state, get(&uri)).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let page = body_json(res).await;
+            let ids: Vec<i64> = page["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["id"].as_i64().unwrap())
+                .collect();
+            pages.push(ids);
+            match (
+                page["next_before"].as_str(),
+                page["next_before_id"].as_i64(),
+            ) {
+                (Some(before), Some(id)) => {
+                    uri = format!("/pessoas/timeline?limit=2&before={before}&before_id={id}")
+                }
+                _ => break,
+            }
+        }
+        assert_eq!(pages, [vec![5, 4], vec![3, 2], vec![1]]);
+
+        // without an id the boundary timestamp itself is excluded
+        let res = send(&state, get("/pessoas/timeline?before=2024-01-01T12:00:00Z")).await;
+        let page = body_json(res).await;
+        assert_eq!(page["items"][0]["id"], 1);
+        assert_eq!(page["items"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn exports_are_gzipped_json_arrays_of_everyone() {
+        use std::io::Read;
+
+        let state = admin_state();
+        // more than a page of them
+        let lines: Vec<String> = (0..EXPORT_PAGE_SIZE + 3)
+            .map(|i| person_line(&format!("dev{i}")))
+            .collect();
+        let res = send(&state, ndjson("/pessoas/import", lines)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = send(&state, get("/admin/export.json.gz")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = send(&state, admin_get("/admin/export.json.gz")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/gzip");
+        let disposition = res.headers()[CONTENT_DISPOSITION].to_str().unwrap();
+        assert!(
+            disposition.starts_with("attachment; filename=\"people-")
+                && disposition.ends_with(".json.gz\""),
+            "{disposition}"
+        );
+        let gzipped = body_bytes(res).await;
+        let mut json = String::new();
+        flate2::read::GzDecoder::new(gzipped.as_slice())
+            .read_to_string(&mut json)
+            .unwrap();
+        let people: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let people = people.as_array().unwrap();
+        assert_eq!(people.len() as i64, EXPORT_PAGE_SIZE + 3);
+        assert_eq!(people[0]["apelido"], "dev0");
+    }
+
+    #[tokio::test]
+    async fn import_counts_a_dataset_spanning_many_batches() {
+        let state = AppState::in_memory(config());
+        let mut lines: Vec<String> = (0..IMPORT_BATCH_SIZE * 3)
+            .map(|i| person_line(&format!("dev{i}")))
+            .collect();
+        lines.extend((0..10).map(|i| person_line(&format!("dev{i}"))));
+        lines.extend((0..5).map(|_| r#"{"apelido": "no-name"}"#.to_owned()));
+        lines.push(String::new());
+
+        let res = send(&state, ndjson("/pessoas/import", lines)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let report = body_json(res).await;
+        assert_eq!(report["imported"], IMPORT_BATCH_SIZE * 3);
+        assert_eq!(report["duplicates"], 10);
+        assert_eq!(report["invalid"], 5);
+        assert_eq!(report["errors"][0]["line"], IMPORT_BATCH_SIZE * 3 + 11);
+        assert_eq!(count(&state).await, (IMPORT_BATCH_SIZE * 3).to_string());
+    }
+
+    #[tokio::test]
+    async fn dry_run_imports_report_without_changing_anything() {
+        let state = AppState::in_memory(config());
+        create(&state, "ana", &[]).await;
+        let lines = vec![
+            person_line("ana"),
+            person_line("bia"),
+            person_line("cris"),
+            r#"{"apelido": "no-name"}"#.to_owned(),
+        ];
+
+        let res = send(
+            &state,
+            ndjson("/pessoas/import?dry_run=true", lines.clone()),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let report = body_json(res).await;
+        assert_eq!(report["dry_run"], true);
+        assert_eq!(report["imported"], 2);
+        assert_eq!(report["duplicates"], 1);
+        assert_eq!(report["invalid"], 1);
+        assert_eq!(count(&state).await, "1");
+        let res = send(&state, get("/pessoas?t=bia")).await;
+        assert_eq!(body_json(res).await, json!([]));
+
+        // the preview matches what the real import then does
+        let res = send(&state, ndjson("/pessoas/import", lines)).await;
+        let report = body_json(res).await;
+        assert_eq!(report["dry_run"], false);
+        assert_eq!(report["imported"], 2);
+        assert_eq!(count(&state).await, "3");
+    }
+
+    #[tokio::test]
+    async fn import_dropped_midway_keeps_nobody() {
+        let state = AppState::in_memory(config());
+        let (mut body, stream) = axum::body::Body::channel();
+        let lines: Vec<String> = (0..IMPORT_BATCH_SIZE + 1)
+            .map(|i| person_line(&format!("dev{i}")) + "\n")
+            .collect();
+        body.send_data(lines.concat().into()).await.unwrap();
+        let req = Request::post("/pessoas/import")
+            .header(CONTENT_TYPE, "application/x-ndjson")
+            .body(stream)
+            .unwrap();
+
+        // the body stays open, so the import is still reading when its request is dropped
+        let import = tokio::spawn({
+            let state = state.clone();
+            async move { send(&state, req).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        import.abort();
+        assert!(import.await.unwrap_err().is_cancelled());
+        drop(body);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(count(&state).await, "0");
+    }
+
+    #[tokio::test]
+    async fn import_cut_short_keeps_nobody() {
+        let mut config = config();
+        config.max_body_bytes = 4096;
+        let state = AppState::in_memory(config);
+        let mut lines: Vec<String> = (0..IMPORT_BATCH_SIZE * 2)
+            .map(|i| person_line(&format!("dev{i}")))
+            .collect();
+        lines.push(person_line(&"x".repeat(8192)));
+
+        let res = send(&state, ndjson("/pessoas/import", lines)).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(count(&state).await, "0");
+    }
+
+    #[tokio::test]
+    async fn reindex_is_scheduled_and_answered_right_away() {
+        let state = admin_state();
+        let start = || {
+            as_admin(
+                Request::post("/admin/reindex")
+                    .body(Default::default())
+                    .unwrap(),
+            )
+        };
+        let res = send(
+            &state,
+            Request::post("/admin/reindex")
+                .body(Default::default())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(state.reindex.status().state, ReindexState::Idle);
+
+        let res = send(&state, start()).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert_eq!(res.headers()[LOCATION], "/admin/reindex");
+        let status = body_json(res).await;
+        assert_eq!(status["state"], "running");
+        assert!(status["started_at"].is_string());
+
+        // the work runs in the background, the status endpoint reports when it's done
+        let mut status = json!(null);
+        for _ in 0..100 {
+            status = body_json(send(&state, admin_get("/admin/reindex")).await).await;
+            if status["state"] != "running" {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(status["state"], "succeeded");
+        assert!(status["finished_at"].is_string());
+    }
+
+    #[tokio::test]
+    async fn name_sorted_pages_have_no_duplicates_or_gaps() {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+        let state = AppState::in_memory(config());
+        // repeated names make the id tiebreak of the cursor matter
+        for (nickname, name) in [
+            ("d", "Caio"),
+            ("a", "Bia"),
+            ("e", "Ana"),
+            ("b", "Bia"),
+            ("f", "Caio"),
+            ("c", "Bia"),
+            ("g", "Davi"),
+        ] {
+            person(&state, nickname, name, "1990-01-01", &[]).await;
+        }
+
+        let mut seen = Vec::new();
+        let mut uri = "/pessoas/search-advanced?sort=nome&limit=2".to_owned();
+        let mut pages = 0;
+        loop {
+            let res = send(&state, get(&uri)).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let cursor = res.headers().get(&NEXT_CURSOR).cloned();
+            let page = body_json(res).await;
+            seen.extend(nicknames(&page).into_iter().map(str::to_owned));
+            pages += 1;
+            let Some(cursor) = cursor else { break };
+            let cursor = cursor.to_str().unwrap().to_owned();
+            let tuple: (String, i64) =
+                serde_json::from_slice(&URL_SAFE_NO_PAD.decode(&cursor).unwrap()).unwrap();
+            assert_eq!(
+                Some(tuple.0.as_str()),
+                page.as_array()
+                    .unwrap()
+                    .last()
+                    .and_then(|p| p["nome"].as_str())
+            );
+            uri = format!("/pessoas/search-advanced?sort=nome&limit=2&cursor={cursor}");
+        }
+        assert_eq!(pages, 4);
+        assert_eq!(seen, ["e", "a", "b", "c", "d", "f", "g"]);
+
+        let res = send(
+            &state,
+            get("/pessoas/search-advanced?sort=nome&cursor=nope"),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn negotiation_picks_the_preferred_supported_media_type() {
+        let accept = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, HeaderValue::from_static(value));
+            negotiate(&headers, SEARCH_MEDIA_TYPES)
+        };
+        assert_eq!(
+            negotiate(&HeaderMap::new(), SEARCH_MEDIA_TYPES).unwrap(),
+            "application/json"
+        );
+        assert_eq!(accept("text/*").unwrap(), "text/csv");
+        assert_eq!(
+            accept("application/json;q=0.5, text/csv").unwrap(),
+            "text/csv"
+        );
+        assert!(matches!(
+            accept("text/csv;q=0, text/html"),
+            Err(AppError::NotAcceptable { supported }) if supported == SEARCH_MEDIA_TYPES
+        ));
+    }
+
+    #[tokio::test]
+    async fn unsupported_media_types_are_not_acceptable() {
+        let state = AppState::in_memory(config());
+        let mut req = get("/pessoas?t=rust");
+        req.headers_mut()
+            .insert(ACCEPT, HeaderValue::from_static("application/xml"));
+        let res = send(&state, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+        let body = body_json(res).await;
+        assert_eq!(body["code"], "NotAcceptable");
+        assert_eq!(
+            body["detail"],
+            "Responses are only available as 'application/json', 'text/csv'"
+        );
+    }
+
+    #[tokio::test]
+    async fn implausible_birth_dates_are_excluded_from_the_age_stats() {
+        let today = Utc::now().date_naive();
+        let legacy = || {
+            vec![
+                row(1, "ana", "Ana Barros", "1990-06-15"),
+                row(2, "bia", "Bia Souza", "1800-01-01"),
+                row(
+                    3,
+                    "caio",
+                    "Caio Lima",
+                    &(today + chrono::Days::new(30)).to_string(),
+                ),
+            ]
+        };
+        let age = crate::repository::age_in_years("1990-06-15".parse().unwrap(), today);
+
+        let res = send(&seeded(legacy()), get("/stats")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let stats = body_json(res).await;
+        assert_eq!(
+            stats["age"],
+            json!({ "min": age, "max": age, "avg": f64::from(age), "excluded": 2 })
+        );
+
+        let mut config = config();
+        config.invalid_ages = InvalidAges::Clamp;
+        let stats = body_json(send(&seeded_with(config, legacy()), get("/stats")).await).await;
+        let oldest = crate::repository::age_in_years(
+            NaiveDate::from_ymd_opt(MIN_DOB_YEAR, 1, 1).unwrap(),
+            today,
+        );
+        assert_eq!(stats["age"]["min"], 0);
+        assert_eq!(stats["age"]["max"], oldest);
+        assert_eq!(stats["age"]["excluded"], 0);
+    }
+
+    #[tokio::test]
+    async fn idempotency_keys_of_failed_attempts_can_be_retried() {
+        let state = AppState::in_memory(config());
+        let keyed = |nickname: &str| {
+            let mut req = post_json(
+                "/pessoas",
+                json!({ "apelido": nickname, "nome": "Ana Barros", "nascimento": "1985-09-23" }),
+            );
+            req.headers_mut()
+                .insert(IDEMPOTENCY_KEY.clone(), HeaderValue::from_static("k1"));
+            req
+        };
+        let holder = create(&state, "ana", &[]).await;
+
+        // the first attempt fails after the key is checked, which leaves it unused
+        let res = send(&state, keyed("ana")).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let res = send(
+            &state,
+            Request::delete(format!("/pessoas/{holder}"))
+                .body(Default::default())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        let res = send(&state, keyed("ana")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(res.headers().get(&IDEMPOTENT_REPLAYED).is_none());
+        let created = body_json(res).await;
+
+        let res = send(&state, keyed("ana")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers()[&IDEMPOTENT_REPLAYED], "true");
+        assert_eq!(body_json(res).await, created);
+
+        let res = send(&state, keyed("bia")).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(body_json(res).await["code"], "IdempotencyKeyReused");
+    }
+
+    #[tokio::test]
+    async fn every_problem_of_a_payload_comes_back_in_one_response() {
+        let state = AppState::in_memory(config());
+        let tomorrow = Utc::now().date_naive().succ_opt().unwrap();
+        let res = send(
+            &state,
+            post_json(
+                "/pessoas",
+                json!({ "apelido": "", "nome": "Ana Barros", "nascimento": tomorrow.to_string() }),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body_json(res).await["detail"],
+            "apelido must not be empty; nascimento must not be in the future"
+        );
+        assert_eq!(count(&state).await, "0");
+    }
+}
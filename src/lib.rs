@@ -0,0 +1,27 @@
+//! People API of the [rinha de backend 2023](https://github.com/Tagliatti/rinha-de-backend-2023-q3/blob/main/INSTRUCOES.md) challenge.
+//!
+//! [`run`] serves it as the binary does, [`app`] builds the router alone, e.g. to embed
+//! it in another server or to call it in process with an [`AppState::in_memory`], and
+//! [`build_router`] builds it over a given [`PersonRepository`], such as an
+//! [`InMemoryPersonRepository`].
+
+mod app;
+mod config;
+mod domain;
+mod error;
+mod handlers;
+mod repository;
+#[cfg(test)]
+mod test_support;
+
+pub use app::{
+    app, build_router, install_settings, run, AppState, DynPersonRepo, SettingsConflict,
+};
+pub use config::{Config, LogFormat};
+pub use domain::{
+    AdvancedSearch, BirthYearCount, CountBreakdown, CreatePersonPayload, DashboardStats, DbInfo,
+    Dob, DuplicateCandidate, IdempotencyRecord, IdempotentCreation, Person, PersonMergePatch,
+    Stack, StackCount, StackMeta,
+};
+pub use error::{AppError, RepositoryError};
+pub use repository::{InMemoryPersonRepository, PersonRepository};
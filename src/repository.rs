@@ -0,0 +1,4327 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::async_trait;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use serde::Serialize;
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{PgArguments, PgConnection, PgDatabaseError, PgExecutor};
+use sqlx::query::QueryAs;
+use sqlx::{Connection, Pool, Postgres, QueryBuilder};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, error, warn, Instrument};
+
+use crate::config::{Config, FuzzyWeights, StacksStorage};
+use crate::domain::{
+    stack_names, validate_dob, AdvancedSearch, AgeStats, BirthYearCount, CountBreakdown,
+    CreatePersonPayload, CursorValue, DashboardStats, DbInfo, DuplicateCandidate, FieldMatch,
+    IdempotencyRecord, IdempotentCreation, InvalidAges, MatchMode, Person, PersonMergePatch,
+    SortOrder, Stack, StackCount, StackMeta, DASHBOARD_TOP_STACKS, MIN_DOB_YEAR, PURGE_BATCH_SIZE,
+};
+use crate::error::{AppError, RepositoryError};
+
+#[async_trait]
+pub trait PersonRepository {
+    async fn create_person(&self, person: CreatePersonPayload) -> Result<Person, AppError>;
+    /// Creates `person` and records it under `key`, for the payload hashed as `fingerprint`,
+    /// or returns the creation made with `key` in the last [`Config::idempotency_key_ttl`] instead.
+    /// Both are stored at once, so a creation that fails or is dropped leaves `key` unused.
+    async fn create_person_idempotently(
+        &self,
+        key: String,
+        fingerprint: String,
+        person: CreatePersonPayload,
+    ) -> Result<IdempotentCreation, AppError>;
+    /// Creates `people` at once, in order, with `None` for those whose nickname is taken,
+    /// including by an earlier entry.
+    async fn create_people_bulk(
+        &self,
+        people: Vec<CreatePersonPayload>,
+    ) -> Result<Vec<Option<Person>>, AppError>;
+    /// Inserts every batch received, skipping taken nicknames, until `batches` is closed.
+    /// The people are only kept if `finished` was sent by then, so an import dropped
+    /// midway, which closes `batches` too, leaves none behind and fails.
+    /// A dry run does it all in a transaction that is rolled back.
+    /// Returns how many people were received and how many were inserted.
+    async fn import_people(
+        &self,
+        batches: mpsc::Receiver<Vec<CreatePersonPayload>>,
+        finished: oneshot::Receiver<()>,
+        dry_run: bool,
+    ) -> Result<(u64, u64), AppError>;
+    async fn get_person(&self, id: i64) -> Result<Person, AppError>;
+    /// Like [`PersonRepository::get_person`], but also finds soft deleted people.
+    async fn get_person_including_deleted(&self, id: i64) -> Result<Person, AppError>;
+    /// [`PersonRepository::get_person`] that also counts the view.
+    async fn get_and_track(&self, id: i64) -> Result<Person, AppError>;
+    async fn search_person(
+        &self,
+        term: String,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Person>, AppError>;
+    async fn count_search(&self, term: String) -> Result<i64, AppError>;
+    async fn fuzzy_search(
+        &self,
+        term: String,
+        threshold: f32,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Person>, AppError>;
+    async fn advanced_search(&self, search: AdvancedSearch) -> Result<Vec<Person>, AppError>;
+    async fn count(&self) -> Result<i64, AppError>;
+    async fn count_breakdown(&self) -> Result<CountBreakdown, AppError>;
+    async fn db_info(&self) -> Result<DbInfo, AppError>;
+    async fn dashboard_stats(&self) -> Result<DashboardStats, AppError>;
+    /// Cheap approximation of [`PersonRepository::count`], may lag behind recent writes.
+    async fn estimated_count(&self) -> Result<i64, AppError>;
+    async fn similar_by_stacks(&self, id: i64, limit: i64) -> Result<Vec<Person>, AppError>;
+    async fn random_person(&self) -> Result<Person, AppError>;
+    async fn get_by_nicknames(&self, nicknames: Vec<String>) -> Result<Vec<Person>, AppError>;
+    /// Everyone ordered by id, read as the stream is consumed so memory stays flat.
+    fn stream_people(&self) -> BoxStream<'static, Result<Person, AppError>>;
+    async fn list_people(&self, after_id: Option<i64>, limit: i64)
+        -> Result<Vec<Person>, AppError>;
+    /// People with `from_id <= id <= to_id`, ordered by id.
+    async fn people_in_id_range(
+        &self,
+        from_id: i64,
+        to_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError>;
+    async fn timeline(
+        &self,
+        before: Option<(DateTime<Utc>, Option<i64>)>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError>;
+    /// Like every aggregation, ordered by count descending then by key, so ties are stable.
+    async fn count_by_birth_year(&self) -> Result<Vec<BirthYearCount>, AppError>;
+    async fn suspicious_people(&self, limit: i64) -> Result<Vec<Person>, AppError>;
+    /// Pairs whose names' trigram similarity exceeds `threshold`, most similar first,
+    /// each pair once with the lower id first.
+    async fn duplicate_candidates(
+        &self,
+        threshold: f32,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<DuplicateCandidate>, AppError>;
+    /// People whose columns derived from others are missing, for backfills. Paginated by id.
+    async fn incomplete_people(
+        &self,
+        after_id: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError>;
+    /// Ordered by count descending then by stack name, with their [`StackMeta`].
+    async fn trending_stacks(&self, days: i32, limit: i64) -> Result<Vec<StackCount>, AppError>;
+    /// The other stacks of the people who have `name`, counted once per person, with their [`StackMeta`].
+    async fn related_stacks(&self, name: String, limit: i64) -> Result<Vec<StackCount>, AppError>;
+    async fn get_stack_meta(&self, name: String) -> Result<StackMeta, AppError>;
+    async fn put_stack_meta(&self, meta: StackMeta) -> Result<StackMeta, AppError>;
+    async fn purge_deleted(&self, older_than_days: i32) -> Result<u64, AppError>;
+    async fn set_photo(&self, id: i64, photo: String) -> Result<Person, AppError>;
+    /// Removes every occurrence of `stacks` from the person's stacks, keeping the order of the others.
+    async fn remove_stacks(&self, id: i64, stacks: Vec<String>) -> Result<Person, AppError>;
+    async fn patch_person(&self, id: i64, patch: PersonMergePatch) -> Result<Person, AppError>;
+    /// With a `version`, only deletes the person if it still is at that version.
+    async fn delete_person(&self, id: i64, version: Option<i64>) -> Result<(), AppError>;
+    /// Rebuilds the person indexes and refreshes the planner statistics, e.g. after a bulk import.
+    async fn reindex(&self) -> Result<(), AppError>;
+    /// Fails when the storage can't serve queries.
+    async fn ping(&self) -> Result<(), AppError>;
+    /// Which of [`EXPECTED_INDEXES`] the storage lacks, e.g. after an incomplete migration.
+    async fn missing_indexes(&self) -> Result<Vec<String>, AppError>;
+}
+
+/// Indexes the API relies on: without the first nicknames are no longer unique,
+/// without the second every search scans the whole table.
+pub(crate) const EXPECTED_INDEXES: &[&str] =
+    &["person_active_nickname_idx", "person_search_trgm_idx"];
+
+pub(crate) struct PostgresPersonRepository {
+    pub(crate) pool: Pool<Postgres>,
+    pub(crate) stacks_storage: StacksStorage,
+    pub(crate) tag_queries: bool,
+    pub(crate) cancel_abandoned: bool,
+    pub(crate) like_escape: char,
+    pub(crate) fuzzy_weights: FuzzyWeights,
+    pub(crate) deadlock_retries: u32,
+    /// Shared by the queries endpoints run in parallel, see [`PostgresPersonRepository::fanned_out`].
+    pub(crate) fan_out: Semaphore,
+    pub(crate) invalid_ages: InvalidAges,
+    pub(crate) write_behind: Option<WriteBehind>,
+    pub(crate) idempotency_key_ttl: Duration,
+}
+
+impl PostgresPersonRepository {
+    pub(crate) fn new(pool: Pool<Postgres>, config: &Config) -> Self {
+        PostgresPersonRepository {
+            pool,
+            stacks_storage: config.stacks_storage,
+            tag_queries: config.tag_queries,
+            cancel_abandoned: config.cancel_abandoned_searches,
+            like_escape: config.search_like_escape,
+            fuzzy_weights: config.fuzzy_weights,
+            deadlock_retries: config.deadlock_retries,
+            fan_out: Semaphore::new(config.db_fan_out_concurrency.get()),
+            invalid_ages: config.invalid_ages,
+            write_behind: None,
+            idempotency_key_ttl: config.idempotency_key_ttl,
+        }
+    }
+
+    /// Accepts creations right away and writes them in batches, see [`WriteBehind`].
+    pub(crate) fn with_write_behind(self, write_behind: WriteBehind) -> Self {
+        PostgresPersonRepository {
+            write_behind: Some(write_behind),
+            ..self
+        }
+    }
+
+    /// Runs `query`, one of several an endpoint runs in parallel, once a permit is free,
+    /// so concurrent dashboards can't take more than [`Config::db_fan_out_concurrency`]
+    /// pooled connections between them.
+    pub(crate) async fn fanned_out<T>(&self, query: impl Future<Output = T>) -> T {
+        let _permit = self.fan_out.acquire().await.expect("never closed");
+        query.await
+    }
+
+    /// Runs `query` on a pooled connection. With tagging enabled it runs inside a
+    /// transaction whose `application_name` is `person_api:<route>`, so slow
+    /// queries in `pg_stat_activity` can be traced back to their endpoint.
+    pub(crate) async fn tagged<T, F>(&self, route: &str, query: F) -> Result<T, sqlx::Error>
+    where
+        F: for<'c> FnOnce(&'c mut PgConnection) -> BoxFuture<'c, Result<T, sqlx::Error>>,
+    {
+        let mut conn = self.pool.acquire().await?;
+        self.tagged_on(&mut conn, route, query).await
+    }
+
+    /// Like [`Self::tagged`], but with [`Config::cancel_abandoned_searches`] the statement
+    /// is cancelled when the returned future is dropped before it completes.
+    pub(crate) async fn cancellable<T, F>(&self, route: &str, query: F) -> Result<T, sqlx::Error>
+    where
+        F: for<'c> FnOnce(&'c mut PgConnection) -> BoxFuture<'c, Result<T, sqlx::Error>>,
+    {
+        if !self.cancel_abandoned {
+            return self.tagged(route, query).await;
+        }
+        let conn = self.pool.acquire().await?;
+        let mut guard = CancelOnDrop::arm(self.pool.clone(), conn).await?;
+        let result = self.tagged_on(guard.conn(), route, query).await;
+        guard.disarm();
+        result
+    }
+
+    pub(crate) async fn tagged_on<T, F>(
+        &self,
+        conn: &mut PgConnection,
+        route: &str,
+        query: F,
+    ) -> Result<T, sqlx::Error>
+    where
+        F: for<'c> FnOnce(&'c mut PgConnection) -> BoxFuture<'c, Result<T, sqlx::Error>>,
+    {
+        if !self.tag_queries {
+            return query(conn).await;
+        }
+
+        let mut tx = conn.begin().await?;
+        sqlx::query("SELECT set_config('application_name', $1, true)")
+            .bind(format!("person_api:{route}"))
+            .execute(&mut *tx)
+            .await?;
+        let result = query(&mut tx).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Runs `op` again, after a short jittered pause, while it fails with a
+    /// deadlock (40P01) and [`Config::deadlock_retries`] allows it.
+    pub(crate) async fn retry_on_deadlock<T, F, Fut>(&self, mut op: F) -> Result<T, sqlx::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Err(err) if attempt < self.deadlock_retries && is_deadlock(&err) => {
+                    attempt += 1;
+                    warn!(attempt, "deadlock detected, retrying");
+                    tokio::time::sleep(deadlock_backoff(attempt)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub(crate) fn patch_query(
+        &self,
+        id: i64,
+        patch: &PersonMergePatch,
+    ) -> QueryBuilder<'static, Postgres> {
+        let mut builder = QueryBuilder::new("UPDATE active_person SET ");
+        let mut set = builder.separated(", ");
+        set.push("version = version + 1");
+        set.push("updated_at = now()");
+        if let Some(Some(nickname)) = &patch.nickname {
+            set.push("nickname = ")
+                .push_bind_unseparated(nickname.clone());
+        }
+        if let Some(Some(name)) = &patch.name {
+            set.push("name = ").push_bind_unseparated(name.clone());
+        }
+        if let Some(Some(dob)) = patch.dob {
+            set.push("dob = ").push_bind_unseparated(dob);
+        }
+        if let Some(stacks) = &patch.stacks {
+            set.push("stacks = ")
+                .push_bind_unseparated(stacks.as_deref().map(stack_names));
+            if self.stacks_storage == StacksStorage::Jsonb {
+                set.push("stacks_detail = ")
+                    .push_bind_unseparated(stacks.clone().map(sqlx::types::Json));
+            }
+        }
+        builder
+            .push(" WHERE id = ")
+            .push_bind(id)
+            .push(" RETURNING *");
+        builder
+    }
+}
+
+/// People sent to the database in one write-behind `INSERT`, also how many ids are reserved at a time.
+pub(crate) const WRITE_BEHIND_BATCH_SIZE: usize = 500;
+/// Accepted creations waiting to be written, past which creating waits for the writer.
+pub(crate) const WRITE_BEHIND_QUEUE_SIZE: usize = 10_000;
+
+/// First wait before retrying a write-behind batch, doubled by every failure.
+pub(crate) const WRITE_BEHIND_MIN_BACKOFF: Duration = Duration::from_millis(100);
+pub(crate) const WRITE_BEHIND_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Whether `err` may go away on its own: a lost or refused connection, an exhausted pool or
+/// server, or a transaction rolled back by a serialization failure or a deadlock.
+pub(crate) fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(err) => err.code().is_some_and(|code| {
+            ["08", "40", "53", "57P"]
+                .iter()
+                .any(|class| code.starts_with(class))
+        }),
+        _ => false,
+    }
+}
+
+/// People of a write-behind batch by what became of them.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct WrittenBatch {
+    pub(crate) written: u64,
+    pub(crate) dead_lettered: u64,
+}
+
+/// Inserts `batch`, retrying transient errors however many attempts it takes. A batch the
+/// database rejects for good is written person by person instead, and whoever is still
+/// rejected is dead-lettered: logged with all their fields and given up on.
+pub(crate) async fn write_batch(
+    pool: &Pool<Postgres>,
+    batch: Vec<Person>,
+    stacks_storage: StacksStorage,
+) -> WrittenBatch {
+    let size = batch.len();
+    let err = match insert_retrying(pool, batch.clone(), stacks_storage).await {
+        Ok(written) => {
+            return WrittenBatch {
+                written,
+                dead_lettered: 0,
+            }
+        }
+        Err(err) => err,
+    };
+    error!(error = %err, size, "write-behind batch rejected, writing its people one by one");
+    let mut outcome = WrittenBatch::default();
+    for person in batch {
+        match insert_retrying(pool, vec![person.clone()], stacks_storage).await {
+            Ok(written) => outcome.written += written,
+            Err(err) => {
+                error!(error = %err, ?person, "dead-lettered a write-behind person");
+                outcome.dead_lettered += 1;
+            }
+        }
+    }
+    outcome
+}
+
+/// Inserts `people` while the failures are transient, returning how many were written.
+pub(crate) async fn insert_retrying(
+    pool: &Pool<Postgres>,
+    people: Vec<Person>,
+    stacks_storage: StacksStorage,
+) -> Result<u64, sqlx::Error> {
+    let size = people.len();
+    let mut backoff = WRITE_BEHIND_MIN_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let mut builder = insert_batch(people.clone(), stacks_storage);
+        match builder.build().execute(pool).await {
+            Ok(result) => return Ok(result.rows_affected()),
+            Err(err) if is_transient(&err) => {
+                error!(
+                    error = %err,
+                    size,
+                    attempt,
+                    retry_in_ms = backoff.as_millis() as u64,
+                    "failed to write a write-behind batch"
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(WRITE_BEHIND_MAX_BACKOFF);
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Write-behind creation for stress workloads: a created person gets an id reserved from the
+/// sequence and is answered right away, while a background task writes the queued people with
+/// one multi-row `INSERT` per batch.
+///
+/// Until their batch is written people can be read by id but don't show up in searches or
+/// counts. Their nickname is checked against the queued and the stored people before they are
+/// answered, so only one stored in between makes the write skip that person with a warning.
+/// A batch failing for a transient reason is retried, backing off up to
+/// [`WRITE_BEHIND_MAX_BACKOFF`], and its people stay pending until it is written: they are
+/// only lost if the process stops first. One the database rejects for good is dead-lettered,
+/// see [`write_batch`].
+#[derive(Clone)]
+pub(crate) struct WriteBehind {
+    pub(crate) queue: mpsc::Sender<Person>,
+    /// The queued people by id, so reads find them before they are written.
+    pub(crate) pending: Arc<Mutex<HashMap<i64, Person>>>,
+    /// Ids taken from `person_id_seq` ahead of the creations that use them.
+    pub(crate) ids: Arc<tokio::sync::Mutex<Vec<i64>>>,
+}
+
+impl WriteBehind {
+    /// Starts the writer, which flushes what was queued every `flush_interval`
+    /// and stops once every handle is dropped and the queue is written.
+    pub(crate) fn spawn(
+        pool: Pool<Postgres>,
+        stacks_storage: StacksStorage,
+        flush_interval: Duration,
+    ) -> (Self, tokio::task::JoinHandle<()>) {
+        let (queue, mut queued) = mpsc::channel(WRITE_BEHIND_QUEUE_SIZE);
+        let write_behind = WriteBehind {
+            queue,
+            pending: Arc::default(),
+            ids: Arc::default(),
+        };
+        let pending = write_behind.pending.clone();
+        let writer = tokio::spawn(async move {
+            while let Some(first) = queued.recv().await {
+                tokio::time::sleep(flush_interval).await;
+                let mut batch = vec![first];
+                while batch.len() < WRITE_BEHIND_BATCH_SIZE {
+                    match queued.try_recv() {
+                        Ok(person) => batch.push(person),
+                        Err(_) => break,
+                    }
+                }
+                let ids: Vec<i64> = batch.iter().map(|person| person.id).collect();
+                let size = batch.len() as u64;
+                let WrittenBatch {
+                    written,
+                    dead_lettered,
+                } = write_batch(&pool, batch, stacks_storage).await;
+                if written + dead_lettered < size {
+                    warn!(
+                        skipped = size - written - dead_lettered,
+                        "write-behind skipped people whose nickname was already taken"
+                    );
+                } else {
+                    debug!(size, "write-behind batch written");
+                }
+                let mut pending = pending.lock().unwrap_or_else(|err| err.into_inner());
+                for id in ids {
+                    pending.remove(&id);
+                }
+            }
+        });
+        (write_behind, writer)
+    }
+
+    pub(crate) fn pending(&self) -> MutexGuard<'_, HashMap<i64, Person>> {
+        self.pending.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) async fn next_id(&self, pool: &Pool<Postgres>) -> Result<i64, sqlx::Error> {
+        let mut ids = self.ids.lock().await;
+        if ids.is_empty() {
+            *ids = sqlx::query_scalar(
+                "SELECT nextval('person_id_seq') FROM generate_series(1, $1) ORDER BY 1 DESC",
+            )
+            .bind(WRITE_BEHIND_BATCH_SIZE as i64)
+            .fetch_all(pool)
+            .await?;
+        }
+        Ok(ids.pop().expect("ids were just reserved"))
+    }
+
+    /// Queues `person` under a reserved id, failing if a queued or stored person has the same
+    /// nickname.
+    pub(crate) async fn create(
+        &self,
+        pool: &Pool<Postgres>,
+        person: CreatePersonPayload,
+        stacks_storage: StacksStorage,
+    ) -> Result<Person, AppError> {
+        let taken: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM active_person WHERE nickname = $1)")
+                .bind(&person.nickname)
+                .fetch_one(pool)
+                .await
+                .map_err(PostgresPersonRepository::handle_unexpected_error)?;
+        if taken {
+            return Err(RepositoryError::UniqueViolation {
+                field: "apelido",
+                value: person.nickname,
+            }
+            .into());
+        }
+        let id = self
+            .next_id(pool)
+            .await
+            .map_err(PostgresPersonRepository::handle_unexpected_error)?;
+        let now = Utc::now();
+        let person = Person {
+            id,
+            stacks: person.stack_names(),
+            stacks_detail: match stacks_storage {
+                StacksStorage::Array => None,
+                StacksStorage::Jsonb => person.stacks.map(sqlx::types::Json),
+            },
+            nickname: person.nickname,
+            name: person.name,
+            dob: person.dob.0,
+            created_at: Some(now),
+            updated_at: Some(now),
+            version: 1,
+            ..Person::default()
+        };
+        {
+            let mut pending = self.pending();
+            if pending.values().any(|p| p.nickname == person.nickname) {
+                return Err(RepositoryError::UniqueViolation {
+                    field: "apelido",
+                    value: person.nickname,
+                }
+                .into());
+            }
+            pending.insert(id, person.clone());
+        }
+        if self.queue.send(person.clone()).await.is_err() {
+            self.pending().remove(&id);
+            error!("write-behind writer stopped");
+            return Err(RepositoryError::Unexpected.into());
+        }
+        Ok(person)
+    }
+}
+
+/// A single `INSERT` of queued people, with the ids and timestamps they were answered with.
+pub(crate) fn insert_batch(
+    people: Vec<Person>,
+    stacks_storage: StacksStorage,
+) -> QueryBuilder<'static, Postgres> {
+    let jsonb = stacks_storage == StacksStorage::Jsonb;
+    let mut builder = QueryBuilder::new(if jsonb {
+        "INSERT INTO person (id, nickname, name, dob, stacks, created_at, updated_at, stacks_detail) "
+    } else {
+        "INSERT INTO person (id, nickname, name, dob, stacks, created_at, updated_at) "
+    });
+    builder.push_values(people, |mut row, person| {
+        row.push_bind(person.id)
+            .push_bind(person.nickname)
+            .push_bind(person.name)
+            .push_bind(person.dob)
+            .push_bind(person.stacks)
+            .push_bind(person.created_at)
+            .push_bind(person.updated_at);
+        if jsonb {
+            row.push_bind(person.stacks_detail);
+        }
+    });
+    builder.push(" ON CONFLICT DO NOTHING");
+    builder
+}
+
+/// Rows [`PostgresPersonRepository::stream_people`] reads ahead of its consumer.
+pub(crate) const STREAM_BUFFER_ROWS: usize = 256;
+
+pub(crate) fn is_deadlock(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == "40P01")
+}
+
+/// 10ms per attempt plus up to 25ms of jitter, so the retried transactions don't collide again.
+pub(crate) fn deadlock_backoff(attempt: u32) -> Duration {
+    let jitter = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.subsec_nanos() % 25);
+    Duration::from_millis(10 * u64::from(attempt) + u64::from(jitter))
+}
+
+/// Holds a pooled connection and cancels the statement running on it if dropped before
+/// [`CancelOnDrop::disarm`].
+///
+/// axum drops the handler future when the client disconnects, but that only stops
+/// waiting for the query, Postgres would still run it to completion. The connection is
+/// detached from the pool first, so the cancel can't land on whatever the next borrower
+/// of that backend runs.
+pub(crate) struct CancelOnDrop {
+    pub(crate) pool: Pool<Postgres>,
+    pub(crate) conn: Option<PoolConnection<Postgres>>,
+    pub(crate) backend_pid: i32,
+}
+
+impl CancelOnDrop {
+    /// Costs one round trip for the backend pid of `conn`, a connection of `pool`.
+    pub(crate) async fn arm(
+        pool: Pool<Postgres>,
+        mut conn: PoolConnection<Postgres>,
+    ) -> Result<Self, sqlx::Error> {
+        let backend_pid = sqlx::query_scalar("SELECT pg_backend_pid()")
+            .fetch_one(&mut *conn)
+            .await?;
+        Ok(CancelOnDrop {
+            pool,
+            conn: Some(conn),
+            backend_pid,
+        })
+    }
+
+    pub(crate) fn conn(&mut self) -> &mut PgConnection {
+        self.conn
+            .as_mut()
+            .expect("only taken when disarmed or dropped")
+    }
+
+    /// Hands the connection back to the pool.
+    pub(crate) fn disarm(mut self) {
+        self.conn.take();
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        let Some(conn) = self.conn.take() else {
+            return;
+        };
+        let conn = conn.detach();
+        let pool = self.pool.clone();
+        let pid = self.backend_pid;
+        tokio::spawn(async move {
+            // best effort, the query may already be done
+            let _ = sqlx::query("SELECT pg_cancel_backend($1)")
+                .bind(pid)
+                .execute(&pool)
+                .await;
+            drop(conn);
+        });
+    }
+}
+
+impl PostgresPersonRepository {
+    /// The `INSERT` of a single person, returning it.
+    pub(crate) fn insert_person(
+        &self,
+        person: CreatePersonPayload,
+    ) -> QueryAs<'static, Postgres, Person, PgArguments> {
+        let stack_names = person.stack_names();
+
+        let sql = match self.stacks_storage {
+            StacksStorage::Array => {
+                "INSERT INTO person (nickname, name, dob, stacks)
+                VALUES ($1, $2, $3, $4)
+                RETURNING *"
+            }
+            // the flat names are still written to `stacks` so search works the same in both modes
+            StacksStorage::Jsonb => {
+                "INSERT INTO person (nickname, name, dob, stacks, stacks_detail)
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING *"
+            }
+        };
+
+        let query = sqlx::query_as(sql)
+            .bind(person.nickname)
+            .bind(person.name)
+            .bind(person.dob)
+            .bind(stack_names);
+
+        match self.stacks_storage {
+            StacksStorage::Array => query,
+            StacksStorage::Jsonb => query.bind(person.stacks.map(sqlx::types::Json)),
+        }
+    }
+
+    /// A single `INSERT` of `people` that skips taken nicknames.
+    pub(crate) fn insert_people(
+        &self,
+        people: Vec<CreatePersonPayload>,
+    ) -> QueryBuilder<'static, Postgres> {
+        let jsonb = self.stacks_storage == StacksStorage::Jsonb;
+        let mut builder = QueryBuilder::new(if jsonb {
+            "INSERT INTO person (nickname, name, dob, stacks, stacks_detail) "
+        } else {
+            "INSERT INTO person (nickname, name, dob, stacks) "
+        });
+        builder.push_values(people, |mut row, person| {
+            let stack_names = person.stack_names();
+            row.push_bind(person.nickname)
+                .push_bind(person.name)
+                .push_bind(person.dob)
+                .push_bind(stack_names);
+            if jsonb {
+                row.push_bind(person.stacks.map(sqlx::types::Json));
+            }
+        });
+        // the target names the partial unique index, which only covers the active people
+        builder.push(" ON CONFLICT (nickname) WHERE deleted_at IS NULL DO NOTHING");
+        builder
+    }
+
+    pub(crate) fn handle_create_error(err: sqlx::Error, nickname: String) -> AppError {
+        if let Some(pg_error) = err
+            .as_database_error()
+            .and_then(|e| e.try_downcast_ref::<PgDatabaseError>())
+        {
+            // the active nicknames are the only unique values
+            if pg_error.code() == "23505" {
+                return RepositoryError::UniqueViolation {
+                    field: "apelido",
+                    value: nickname,
+                }
+                .into();
+            }
+        }
+        error!(error = %err, "failed to create person");
+        RepositoryError::Unexpected.into()
+    }
+
+    pub(crate) fn handle_unexpected_error(err: sqlx::Error) -> AppError {
+        error!(error = %err, "unexpected database error");
+        RepositoryError::Unexpected.into()
+    }
+}
+
+/// Trims the term and collapses inner runs of whitespace, so `"  rust   node "` becomes `"rust node"`.
+pub(crate) fn normalize_search_term(raw: &str) -> String {
+    raw.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// A parsed search term such as `rust -java`: every `include` term must
+/// match some field and no `exclude` term (prefixed with `-`) may.
+#[derive(Debug, Default)]
+pub(crate) struct SearchTerms {
+    pub(crate) include: Vec<String>,
+    pub(crate) exclude: Vec<String>,
+}
+
+impl SearchTerms {
+    pub(crate) fn parse(raw: &str) -> Self {
+        let mut terms = SearchTerms::default();
+        for word in raw.split_whitespace() {
+            match word.strip_prefix('-') {
+                Some(negated) if !negated.is_empty() => terms.exclude.push(negated.to_owned()),
+                _ => terms.include.push(word.to_owned()),
+            }
+        }
+        terms
+    }
+}
+
+/// Appends a `WHERE` clause matching `terms` against nickname, name or any of the stacks.
+/// Exclusions are case-insensitive so `-java` also drops "Java".
+///
+/// The fields are matched through the `search` column, which joins them with spaces and
+/// has a trigram index. Terms never contain whitespace, so a match can't span two fields.
+pub(crate) fn push_search_filter(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    terms: &SearchTerms,
+    escape: char,
+) {
+    builder.push(" WHERE TRUE");
+    for term in &terms.include {
+        builder.push(" AND ");
+        push_field_match(builder, "LIKE", term, escape);
+    }
+    for term in &terms.exclude {
+        builder.push(" AND NOT ");
+        push_field_match(builder, "ILIKE", term, escape);
+    }
+}
+
+pub(crate) fn push_field_match(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    operator: &str,
+    term: &str,
+    escape: char,
+) {
+    let pattern = format!("%{}%", escape_like(term, escape));
+    builder
+        .push(format_args!("search {operator} "))
+        .push_bind(pattern)
+        .push(format_args!(" ESCAPE '{escape}'"));
+}
+
+/// Case-insensitive comparison of `column` and a field filter, the trigram indexes serve `ILIKE`.
+pub(crate) fn push_column_match(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    column: &str,
+    field: &FieldMatch,
+    escape: char,
+) {
+    let escaped = escape_like(&field.value, escape);
+    let pattern = match field.mode {
+        MatchMode::Exact => {
+            builder
+                .push(format_args!("lower({column}) = lower("))
+                .push_bind(field.value.clone())
+                .push(")");
+            return;
+        }
+        MatchMode::Prefix => format!("{escaped}%"),
+        MatchMode::Substring => format!("%{escaped}%"),
+    };
+    builder
+        .push(format_args!("{column} ILIKE "))
+        .push_bind(pattern)
+        .push(format_args!(" ESCAPE '{escape}'"));
+}
+
+/// Makes `%`, `_` and the escape character itself match literally in a `LIKE` pattern.
+pub(crate) fn escape_like(term: &str, escape: char) -> String {
+    let mut escaped = String::with_capacity(term.len());
+    for c in term.chars() {
+        if c == '%' || c == '_' || c == escape {
+            escaped.push(escape);
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[async_trait]
+impl PersonRepository for PostgresPersonRepository {
+    async fn create_person(&self, person: CreatePersonPayload) -> Result<Person, AppError> {
+        if let Some(write_behind) = &self.write_behind {
+            return write_behind
+                .create(&self.pool, person, self.stacks_storage)
+                .await;
+        }
+        let nickname = person.nickname.clone();
+        self.insert_person(person)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|err| Self::handle_create_error(err, nickname))
+    }
+
+    async fn create_person_idempotently(
+        &self,
+        key: String,
+        fingerprint: String,
+        person: CreatePersonPayload,
+    ) -> Result<IdempotentCreation, AppError> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(Self::handle_unexpected_error)?;
+        // waits for a creation running with the same key, then conflicts if it was kept;
+        // expired keys are taken over, so a key can be reused once its replay window has passed
+        let claimed = sqlx::query(
+            "INSERT INTO idempotency_key (key, fingerprint, person_id) VALUES ($1, $2, 0)
+            ON CONFLICT (key) DO UPDATE
+            SET fingerprint = EXCLUDED.fingerprint, created_at = now()
+            WHERE idempotency_key.created_at < now() - make_interval(secs => $3)
+            RETURNING key",
+        )
+        .bind(&key)
+        .bind(&fingerprint)
+        .bind(self.idempotency_key_ttl.as_secs_f64())
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(Self::handle_unexpected_error)?;
+        if claimed.is_none() {
+            let existing =
+                sqlx::query_as("SELECT fingerprint, person_id FROM idempotency_key WHERE key = $1")
+                    .bind(key)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(Self::handle_unexpected_error)?;
+            return Ok(IdempotentCreation::Existing(existing));
+        }
+
+        let person = match &self.write_behind {
+            // answered from the queue as any write-behind creation is, the key only once it's queued
+            Some(write_behind) => {
+                write_behind
+                    .create(&self.pool, person, self.stacks_storage)
+                    .await?
+            }
+            None => {
+                let nickname = person.nickname.clone();
+                self.insert_person(person)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .map_err(|err| Self::handle_create_error(err, nickname))?
+            }
+        };
+        sqlx::query("UPDATE idempotency_key SET person_id = $1 WHERE key = $2")
+            .bind(person.id)
+            .bind(key)
+            .execute(&mut *tx)
+            .await
+            .map_err(Self::handle_unexpected_error)?;
+        tx.commit().await.map_err(Self::handle_unexpected_error)?;
+        Ok(IdempotentCreation::Created(person))
+    }
+
+    async fn create_people_bulk(
+        &self,
+        people: Vec<CreatePersonPayload>,
+    ) -> Result<Vec<Option<Person>>, AppError> {
+        let nicknames: Vec<String> = people.iter().map(|p| p.nickname.clone()).collect();
+        let mut builder = self.insert_people(people);
+        builder.push(" RETURNING *");
+        let created: Vec<Person> = builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(Self::handle_unexpected_error)?;
+
+        // nicknames are unique, so each row belongs to the first entry with its nickname
+        let mut created: HashMap<String, Person> = created
+            .into_iter()
+            .map(|person| (person.nickname.clone(), person))
+            .collect();
+        Ok(nicknames
+            .iter()
+            .map(|nickname| created.remove(nickname))
+            .collect())
+    }
+
+    async fn import_people(
+        &self,
+        mut batches: mpsc::Receiver<Vec<CreatePersonPayload>>,
+        finished: oneshot::Receiver<()>,
+        dry_run: bool,
+    ) -> Result<(u64, u64), AppError> {
+        // dropped along with the future, the transaction is rolled back
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(Self::handle_unexpected_error)?;
+
+        let (mut received, mut inserted) = (0, 0);
+        while let Some(batch) = batches.recv().await {
+            received += batch.len() as u64;
+            inserted += self
+                .insert_people(batch)
+                .build()
+                .execute(&mut *tx)
+                .await
+                .map_err(Self::handle_unexpected_error)?
+                .rows_affected();
+        }
+
+        let abandoned = finished.await.is_err();
+        match dry_run || abandoned {
+            true => tx.rollback().await,
+            false => tx.commit().await,
+        }
+        .map_err(Self::handle_unexpected_error)?;
+        if abandoned {
+            warn!(received, "import abandoned midway, rolled back");
+            return Err(RepositoryError::Unexpected.into());
+        }
+        Ok((received, inserted))
+    }
+
+    async fn get_person(&self, id: i64) -> Result<Person, AppError> {
+        if let Some(person) = self
+            .write_behind
+            .as_ref()
+            .and_then(|write_behind| write_behind.pending().get(&id).cloned())
+        {
+            return Ok(person);
+        }
+        let result = self
+            .tagged("get_person", |conn| {
+                Box::pin(
+                    sqlx::query_as("SELECT * FROM active_person WHERE id = $1")
+                        .bind(id)
+                        .fetch_optional(conn),
+                )
+            })
+            .await;
+
+        match result {
+            Ok(Some(person)) => Ok(person),
+            Ok(None) => Err(RepositoryError::NotFound {
+                resoure_name: "person",
+                resource_id: id,
+            }
+            .into()),
+            Err(err) => Err(Self::handle_unexpected_error(err)),
+        }
+    }
+
+    async fn get_person_including_deleted(&self, id: i64) -> Result<Person, AppError> {
+        sqlx::query_as("SELECT * FROM person WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(Self::handle_unexpected_error)?
+            .ok_or_else(|| {
+                RepositoryError::NotFound {
+                    resoure_name: "person",
+                    resource_id: id,
+                }
+                .into()
+            })
+    }
+
+    async fn get_and_track(&self, id: i64) -> Result<Person, AppError> {
+        // the row lock only holds back other tracked reads, plain ones don't wait for it
+        let result = sqlx::query_as(
+            "UPDATE active_person SET view_count = view_count + 1 WHERE id = $1 RETURNING *",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await;
+
+        match result {
+            Ok(Some(person)) => Ok(person),
+            Ok(None) => Err(RepositoryError::NotFound {
+                resoure_name: "person",
+                resource_id: id,
+            }
+            .into()),
+            Err(err) => Err(Self::handle_unexpected_error(err)),
+        }
+    }
+    async fn search_person(
+        &self,
+        term: String,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        let like_escape = self.like_escape;
+        self.cancellable("search", |conn| {
+            Box::pin(async move {
+                let mut builder = QueryBuilder::new("SELECT * FROM active_person");
+                push_search_filter(&mut builder, &SearchTerms::parse(&term), like_escape);
+                builder
+                    .push(" ORDER BY id LIMIT ")
+                    .push_bind(limit)
+                    .push(" OFFSET ")
+                    .push_bind(offset);
+
+                builder.build_query_as().fetch_all(&mut *conn).await
+            })
+        })
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn count_search(&self, term: String) -> Result<i64, AppError> {
+        let like_escape = self.like_escape;
+        self.cancellable("search", |conn| {
+            Box::pin(async move {
+                let mut builder = QueryBuilder::new("SELECT COUNT(*) FROM active_person");
+                push_search_filter(&mut builder, &SearchTerms::parse(&term), like_escape);
+
+                builder.build_query_scalar().fetch_one(&mut *conn).await
+            })
+        })
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn advanced_search(&self, search: AdvancedSearch) -> Result<Vec<Person>, AppError> {
+        let mut builder = QueryBuilder::new("SELECT * FROM active_person");
+        push_search_filter(&mut builder, &search.terms, self.like_escape);
+        if let Some(from) = search.from {
+            builder.push(" AND dob >= ").push_bind(from);
+        }
+        if let Some(to) = search.to {
+            builder.push(" AND dob <= ").push_bind(to);
+        }
+        if let Some(nickname) = &search.nickname {
+            builder.push(" AND ");
+            push_column_match(&mut builder, "nickname", nickname, self.like_escape);
+        }
+        if let Some(name) = &search.name {
+            builder.push(" AND ");
+            push_column_match(&mut builder, "name", name, self.like_escape);
+        }
+        if let Some(stack) = &search.stack {
+            builder.push(" AND EXISTS (SELECT 1 FROM UNNEST(stacks) s WHERE ");
+            push_column_match(&mut builder, "s", stack, self.like_escape);
+            builder.push(")");
+        }
+        let SortOrder { column, descending } = search.sort;
+        // keyset on (column, id), where id always ascends
+        if let Some(after) = search.after {
+            let op = if descending { "<" } else { ">" };
+            builder.push(" AND ");
+            match after.value {
+                CursorValue::Text(value) => {
+                    builder
+                        .push(format_args!("({column} {op} "))
+                        .push_bind(value.clone())
+                        .push(format_args!(" OR ({column} = "))
+                        .push_bind(value)
+                        .push(" AND id > ")
+                        .push_bind(after.id)
+                        .push("))");
+                }
+                CursorValue::Date(value) => {
+                    builder
+                        .push(format_args!("({column} {op} "))
+                        .push_bind(value)
+                        .push(format_args!(" OR ({column} = "))
+                        .push_bind(value)
+                        .push(" AND id > ")
+                        .push_bind(after.id)
+                        .push("))");
+                }
+                CursorValue::Id => {
+                    builder.push(format_args!("id {op} ")).push_bind(after.id);
+                }
+            }
+        }
+        builder.push(format_args!(
+            " ORDER BY {column} {}, id",
+            if descending { "DESC" } else { "ASC" }
+        ));
+        builder
+            .push(" LIMIT ")
+            .push_bind(search.limit)
+            .push(" OFFSET ")
+            .push_bind(search.offset);
+
+        builder
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn fuzzy_search(
+        &self,
+        term: String,
+        threshold: f32,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(Self::handle_unexpected_error)?;
+
+        // local to the transaction so the pooled connection keeps the default threshold
+        sqlx::query("SELECT set_config('pg_trgm.word_similarity_threshold', $1, true)")
+            .bind(threshold.to_string())
+            .execute(&mut *tx)
+            .await
+            .map_err(Self::handle_unexpected_error)?;
+
+        let people = sqlx::query_as(
+            "SELECT * FROM active_person
+            WHERE
+                $1 <% nickname
+                OR $1 <% name
+                OR EXISTS (
+                    SELECT 1 FROM UNNEST(stacks) s
+                    WHERE $1 <% s
+                )
+            ORDER BY
+                $2 * word_similarity($1, nickname)
+                + $3 * word_similarity($1, name)
+                + $4 * COALESCE((SELECT MAX(word_similarity($1, s)) FROM UNNEST(stacks) s), 0)
+                DESC,
+                id
+            LIMIT $5 OFFSET $6",
+        )
+        .bind(term)
+        .bind(self.fuzzy_weights.nickname)
+        .bind(self.fuzzy_weights.name)
+        .bind(self.fuzzy_weights.stack)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(Self::handle_unexpected_error)?;
+
+        tx.commit().await.map_err(Self::handle_unexpected_error)?;
+        Ok(people)
+    }
+
+    async fn dashboard_stats(&self) -> Result<DashboardStats, AppError> {
+        let top_stacks = sqlx::query_as(
+            "SELECT stack, COUNT(*) AS count
+            FROM active_person, UNNEST(stacks) AS stack
+            GROUP BY stack
+            ORDER BY count DESC, stack
+            LIMIT $1",
+        )
+        .bind(DASHBOARD_TOP_STACKS)
+        .fetch_all(&self.pool);
+        // a NULL age is an excluded person
+        let age = sqlx::query_as(
+            "WITH ages AS (
+                SELECT EXTRACT(YEAR FROM age(CASE
+                    WHEN dob BETWEEN make_date($1, 1, 1) AND CURRENT_DATE THEN dob
+                    WHEN $2 THEN LEAST(GREATEST(dob, make_date($1, 1, 1)), CURRENT_DATE)
+                END)) AS age
+                FROM active_person
+            )
+            SELECT
+                MIN(age)::INT AS min,
+                MAX(age)::INT AS max,
+                AVG(age)::FLOAT8 AS avg,
+                COUNT(*) FILTER (WHERE age IS NULL) AS excluded
+            FROM ages",
+        )
+        .bind(MIN_DOB_YEAR)
+        .bind(self.invalid_ages == InvalidAges::Clamp)
+        .fetch_one(&self.pool);
+        let newest =
+            sqlx::query_as("SELECT * FROM active_person ORDER BY created_at DESC, id DESC LIMIT 1")
+                .fetch_optional(&self.pool);
+
+        // these run on separate pooled connections
+        let (counts, (top_stacks, age, newest)) =
+            tokio::try_join!(self.fanned_out(self.count_breakdown()), async {
+                tokio::try_join!(
+                    self.fanned_out(top_stacks),
+                    self.fanned_out(age),
+                    self.fanned_out(newest),
+                )
+                .map_err(Self::handle_unexpected_error)
+            })?;
+
+        Ok(DashboardStats {
+            counts,
+            top_stacks,
+            age,
+            newest,
+        })
+    }
+
+    async fn db_info(&self) -> Result<DbInfo, AppError> {
+        let server_version = sqlx::query_scalar("SHOW server_version")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(Self::handle_unexpected_error)?;
+
+        let has_migrations: bool =
+            sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+                .fetch_one(&self.pool)
+                .await
+                .map_err(Self::handle_unexpected_error)?;
+        let migration_version = if has_migrations {
+            sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+                .fetch_one(&self.pool)
+                .await
+                .map_err(Self::handle_unexpected_error)?
+        } else {
+            None
+        };
+
+        Ok(DbInfo {
+            migration_version,
+            server_version,
+        })
+    }
+
+    async fn count_breakdown(&self) -> Result<CountBreakdown, AppError> {
+        // an empty array counts as no stacks, like a NULL one
+        sqlx::query_as(
+            "SELECT
+                COUNT(*) AS total,
+                COUNT(*) FILTER (WHERE cardinality(stacks) > 0) AS with_stacks,
+                COUNT(*) FILTER (WHERE COALESCE(cardinality(stacks), 0) = 0) AS without_stacks
+            FROM active_person",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn count(&self) -> Result<i64, AppError> {
+        self.tagged("count", |conn| {
+            Box::pin(sqlx::query_scalar("SELECT COUNT(*) FROM active_person").fetch_one(conn))
+        })
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn estimated_count(&self) -> Result<i64, AppError> {
+        // reltuples is -1 for a table that was never vacuumed/analyzed
+        sqlx::query_scalar(
+            "SELECT GREATEST(reltuples, 0)::BIGINT FROM pg_class WHERE oid = 'person'::regclass",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn similar_by_stacks(&self, id: i64, limit: i64) -> Result<Vec<Person>, AppError> {
+        sqlx::query_as(
+            "SELECT p.* FROM active_person p
+            JOIN active_person target ON target.id = $1
+            WHERE
+                p.id <> target.id
+                AND p.stacks && target.stacks
+            ORDER BY
+                (SELECT COUNT(*) FROM UNNEST(p.stacks) s WHERE s = ANY(target.stacks)) DESC,
+                p.id
+            LIMIT $2",
+        )
+        .bind(id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn random_person(&self) -> Result<Person, AppError> {
+        // sorts the whole table, TABLESAMPLE would be cheaper but may come back empty on small tables
+        let result = sqlx::query_as("SELECT * FROM active_person ORDER BY random() LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await;
+
+        match result {
+            Ok(Some(person)) => Ok(person),
+            Ok(None) => Err(RepositoryError::Empty {
+                resoure_name: "person",
+            }
+            .into()),
+            Err(err) => Err(Self::handle_unexpected_error(err)),
+        }
+    }
+
+    async fn get_by_nicknames(&self, nicknames: Vec<String>) -> Result<Vec<Person>, AppError> {
+        let nicknames: Vec<String> = nicknames.iter().map(|n| n.to_lowercase()).collect();
+
+        sqlx::query_as("SELECT * FROM active_person WHERE lower(nickname) = ANY($1)")
+            .bind(nicknames)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(Self::handle_unexpected_error)
+    }
+
+    fn stream_people(&self) -> BoxStream<'static, Result<Person, AppError>> {
+        // the rows are forwarded by a task owning the pool, the stream can't borrow `self`
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER_ROWS);
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            let mut rows = sqlx::query_as("SELECT * FROM active_person ORDER BY id").fetch(&pool);
+            while let Some(row) = rows.next().await {
+                let row = row.map_err(Self::handle_unexpected_error);
+                let failed = row.is_err();
+                if tx.send(row).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        ReceiverStream::new(rx).boxed()
+    }
+
+    async fn list_people(
+        &self,
+        after_id: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        sqlx::query_as(
+            "SELECT * FROM active_person
+            WHERE $1::BIGINT IS NULL OR id > $1
+            ORDER BY id
+            LIMIT $2",
+        )
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn people_in_id_range(
+        &self,
+        from_id: i64,
+        to_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        sqlx::query_as(
+            "SELECT * FROM active_person
+            WHERE id BETWEEN $1 AND $2
+            ORDER BY id
+            LIMIT $3",
+        )
+        .bind(from_id)
+        .bind(to_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn timeline(
+        &self,
+        before: Option<(DateTime<Utc>, Option<i64>)>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        let (before, before_id) = before.unzip();
+        // ids start at 1, so a missing `before_id` leaves out the whole `before` instant
+        sqlx::query_as(
+            "SELECT * FROM active_person
+            WHERE $1::TIMESTAMPTZ IS NULL OR (created_at, id) < ($1, COALESCE($2, 0))
+            ORDER BY created_at DESC, id DESC
+            LIMIT $3",
+        )
+        .bind(before)
+        .bind(before_id.flatten())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn count_by_birth_year(&self) -> Result<Vec<BirthYearCount>, AppError> {
+        sqlx::query_as(
+            "SELECT EXTRACT(YEAR FROM dob)::INT AS year, COUNT(*) AS count
+            FROM active_person
+            GROUP BY year
+            ORDER BY count DESC, year",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn trending_stacks(&self, days: i32, limit: i64) -> Result<Vec<StackCount>, AppError> {
+        sqlx::query_as(
+            "SELECT stack, COUNT(*) AS count, meta.display_name, meta.icon
+            FROM active_person, UNNEST(stacks) AS stack
+            LEFT JOIN stacks_meta meta ON meta.name = stack
+            WHERE created_at >= now() - make_interval(days => $1)
+            GROUP BY stack, meta.name
+            ORDER BY count DESC, stack
+            LIMIT $2",
+        )
+        .bind(days)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn related_stacks(&self, name: String, limit: i64) -> Result<Vec<StackCount>, AppError> {
+        sqlx::query_as(
+            "SELECT other AS stack, COUNT(DISTINCT id) AS count, meta.display_name, meta.icon
+            FROM active_person, UNNEST(stacks) AS other
+            LEFT JOIN stacks_meta meta ON meta.name = other
+            WHERE $1 = ANY(stacks) AND other <> $1
+            GROUP BY other, meta.name
+            ORDER BY count DESC, other
+            LIMIT $2",
+        )
+        .bind(name)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn get_stack_meta(&self, name: String) -> Result<StackMeta, AppError> {
+        let result = sqlx::query_as("SELECT * FROM stacks_meta WHERE name = $1")
+            .bind(&name)
+            .fetch_optional(&self.pool)
+            .await;
+
+        match result {
+            Ok(Some(meta)) => Ok(meta),
+            Ok(None) => Err(RepositoryError::NotFoundByName {
+                resoure_name: "stack",
+                name,
+            }
+            .into()),
+            Err(err) => Err(Self::handle_unexpected_error(err)),
+        }
+    }
+
+    async fn put_stack_meta(&self, meta: StackMeta) -> Result<StackMeta, AppError> {
+        sqlx::query_as(
+            "INSERT INTO stacks_meta (name, display_name, icon)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO UPDATE
+            SET display_name = EXCLUDED.display_name, icon = EXCLUDED.icon
+            RETURNING *",
+        )
+        .bind(meta.name)
+        .bind(meta.display_name)
+        .bind(meta.icon)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn purge_deleted(&self, older_than_days: i32) -> Result<u64, AppError> {
+        let mut purged = 0;
+        loop {
+            let deleted = sqlx::query(
+                "DELETE FROM person
+                WHERE id IN (
+                    SELECT id FROM person
+                    WHERE deleted_at < now() - make_interval(days => $1)
+                    LIMIT $2
+                    FOR UPDATE SKIP LOCKED
+                )",
+            )
+            .bind(older_than_days)
+            .bind(PURGE_BATCH_SIZE)
+            .execute(&self.pool)
+            .await
+            .map_err(Self::handle_unexpected_error)?
+            .rows_affected();
+
+            purged += deleted;
+            if deleted < PURGE_BATCH_SIZE as u64 {
+                return Ok(purged);
+            }
+        }
+    }
+
+    async fn incomplete_people(
+        &self,
+        after_id: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        // `stacks_detail` only exists for people created with STACKS_STORAGE=jsonb
+        sqlx::query_as(
+            "SELECT * FROM active_person
+            WHERE
+                ($1::BIGINT IS NULL OR id > $1)
+                AND stacks IS NOT NULL
+                AND stacks_detail IS NULL
+            ORDER BY id
+            LIMIT $2",
+        )
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn suspicious_people(&self, limit: i64) -> Result<Vec<Person>, AppError> {
+        // rows that would not pass today's validation or look like placeholders
+        sqlx::query_as(
+            "SELECT * FROM active_person
+            WHERE
+                lower(name) = lower(nickname)
+                OR length(trim(name)) <= 1
+                OR dob > CURRENT_DATE
+                OR dob < make_date($1, 1, 1)
+            ORDER BY id
+            LIMIT $2",
+        )
+        .bind(MIN_DOB_YEAR)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)
+    }
+
+    async fn duplicate_candidates(
+        &self,
+        threshold: f32,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<DuplicateCandidate>, AppError> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(Self::handle_unexpected_error)?;
+
+        // `%` uses this threshold and, unlike comparing similarity() directly, the trigram index
+        sqlx::query("SELECT set_config('pg_trgm.similarity_threshold', $1, true)")
+            .bind(threshold.to_string())
+            .execute(&mut *tx)
+            .await
+            .map_err(Self::handle_unexpected_error)?;
+
+        let pairs = sqlx::query_as(
+            "SELECT
+                a.id AS first_id, a.name AS first_name,
+                b.id AS second_id, b.name AS second_name,
+                similarity(a.name, b.name) AS similarity
+            FROM active_person a
+            JOIN active_person b ON a.id < b.id AND a.name % b.name
+            WHERE similarity(a.name, b.name) > $1
+            ORDER BY similarity DESC, a.id, b.id
+            LIMIT $2 OFFSET $3",
+        )
+        .bind(threshold)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(Self::handle_unexpected_error)?;
+
+        tx.commit().await.map_err(Self::handle_unexpected_error)?;
+        Ok(pairs)
+    }
+
+    async fn set_photo(&self, id: i64, photo: String) -> Result<Person, AppError> {
+        let result = sqlx::query_as(
+            "UPDATE active_person SET photo = $2, version = version + 1, updated_at = now()
+            WHERE id = $1
+            RETURNING *",
+        )
+        .bind(id)
+        .bind(photo)
+        .fetch_optional(&self.pool)
+        .await;
+
+        match result {
+            Ok(Some(person)) => Ok(person),
+            Ok(None) => Err(RepositoryError::NotFound {
+                resoure_name: "person",
+                resource_id: id,
+            }
+            .into()),
+            Err(err) => Err(Self::handle_unexpected_error(err)),
+        }
+    }
+
+    async fn remove_stacks(&self, id: i64, stacks: Vec<String>) -> Result<Person, AppError> {
+        // NULL stays NULL rather than becoming an empty array
+        let result = sqlx::query_as(
+            "UPDATE active_person SET
+                stacks = CASE WHEN stacks IS NULL THEN NULL ELSE ARRAY(
+                    SELECT s FROM unnest(stacks) WITH ORDINALITY AS t(s, i)
+                    WHERE s <> ALL($2)
+                    ORDER BY i
+                ) END,
+                stacks_detail = CASE WHEN stacks_detail IS NULL THEN NULL ELSE COALESCE((
+                    SELECT jsonb_agg(e ORDER BY i)
+                    FROM jsonb_array_elements(stacks_detail) WITH ORDINALITY AS t(e, i)
+                    WHERE e->>'name' <> ALL($2)
+                ), '[]') END,
+                version = version + 1,
+                updated_at = now()
+            WHERE id = $1
+            RETURNING *",
+        )
+        .bind(id)
+        .bind(stacks)
+        .fetch_optional(&self.pool)
+        .await;
+
+        match result {
+            Ok(Some(person)) => Ok(person),
+            Ok(None) => Err(RepositoryError::NotFound {
+                resoure_name: "person",
+                resource_id: id,
+            }
+            .into()),
+            Err(err) => Err(Self::handle_unexpected_error(err)),
+        }
+    }
+
+    async fn patch_person(&self, id: i64, patch: PersonMergePatch) -> Result<Person, AppError> {
+        if patch.is_empty() {
+            return self.get_person(id).await;
+        }
+
+        let nickname = patch.nickname.clone().flatten().unwrap_or_default();
+        // concurrent patches of the same rows may deadlock, which is safe to retry
+        let result = self
+            .retry_on_deadlock(|| async {
+                let mut builder = self.patch_query(id, &patch);
+                builder.build_query_as().fetch_optional(&self.pool).await
+            })
+            .await;
+
+        match result {
+            Ok(Some(person)) => Ok(person),
+            Ok(None) => Err(RepositoryError::NotFound {
+                resoure_name: "person",
+                resource_id: id,
+            }
+            .into()),
+            Err(err) => Err(Self::handle_create_error(err, nickname)),
+        }
+    }
+
+    async fn delete_person(&self, id: i64, version: Option<i64>) -> Result<(), AppError> {
+        // the row is kept until the admin purge removes it, but its nickname is free right away
+        let result = sqlx::query(
+            "UPDATE active_person
+            SET deleted_at = now(), updated_at = now(), version = version + 1
+            WHERE id = $1 AND ($2::BIGINT IS NULL OR version = $2)",
+        )
+        .bind(id)
+        .bind(version)
+        .execute(&self.pool)
+        .await
+        .map_err(Self::handle_unexpected_error)?;
+
+        if result.rows_affected() == 0 {
+            // tells a stale version apart from a missing person, which fails with a 404 here
+            if version.is_some() {
+                self.get_person(id).await?;
+                return Err(RepositoryError::StaleVersion {
+                    resoure_name: "person",
+                    resource_id: id,
+                }
+                .into());
+            }
+            return Err(RepositoryError::NotFound {
+                resoure_name: "person",
+                resource_id: id,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    async fn reindex(&self) -> Result<(), AppError> {
+        // CONCURRENTLY keeps the table writable meanwhile, it can't run in a transaction
+        for sql in ["REINDEX TABLE CONCURRENTLY person", "ANALYZE person"] {
+            sqlx::query(sql)
+                .execute(&self.pool)
+                .await
+                .map_err(Self::handle_unexpected_error)?;
+        }
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(Self::handle_unexpected_error)?;
+        Ok(())
+    }
+
+    async fn missing_indexes(&self) -> Result<Vec<String>, AppError> {
+        missing_indexes_in(&self.pool)
+            .await
+            .map_err(Self::handle_unexpected_error)
+    }
+}
+
+/// Which of [`EXPECTED_INDEXES`] `conn` can't see in `pg_indexes`.
+pub(crate) async fn missing_indexes_in(
+    conn: impl PgExecutor<'_>,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT expected FROM unnest($1::TEXT[]) AS expected
+        WHERE NOT EXISTS (
+            SELECT 1 FROM pg_indexes
+            WHERE tablename = 'person' AND indexname = expected
+        )",
+    )
+    .bind(EXPECTED_INDEXES)
+    .fetch_all(conn)
+    .await
+}
+
+/// Keeps everyone in memory, so the API can run without Postgres (`PERSON_REPOSITORY=memory`).
+///
+/// Mirrors [`PostgresPersonRepository`] closely enough for local runs and handler tests,
+/// except for the fuzzy search, which falls back to a case-insensitive substring match,
+/// and the duplicate candidates, only names equal but for case.
+pub struct InMemoryPersonRepository {
+    /// Behind read-write locks, so concurrent reads don't queue behind one another.
+    pub(crate) people: RwLock<HashMap<i64, Person>>,
+    /// Soft deleted people, out of `people` so no read has to skip them.
+    pub(crate) deleted: RwLock<HashMap<i64, Person>>,
+    pub(crate) stacks_meta: RwLock<HashMap<String, StackMeta>>,
+    pub(crate) next_id: AtomicI64,
+    pub(crate) stacks_storage: StacksStorage,
+    pub(crate) invalid_ages: InvalidAges,
+    /// A plain mutex, only ever held to claim a key and create its person at once.
+    pub(crate) idempotency_keys: Mutex<HashMap<String, (IdempotencyRecord, Instant)>>,
+    pub(crate) idempotency_key_ttl: Duration,
+}
+
+impl InMemoryPersonRepository {
+    pub fn new(config: &Config) -> Self {
+        InMemoryPersonRepository {
+            people: RwLock::default(),
+            deleted: RwLock::default(),
+            stacks_meta: RwLock::default(),
+            next_id: AtomicI64::new(1),
+            stacks_storage: config.stacks_storage,
+            invalid_ages: config.invalid_ages,
+            idempotency_keys: Mutex::default(),
+            idempotency_key_ttl: config.idempotency_key_ttl,
+        }
+    }
+
+    pub(crate) fn people(&self) -> RwLockReadGuard<'_, HashMap<i64, Person>> {
+        // the map is never left half updated, so a panic elsewhere doesn't poison it for good
+        self.people.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn people_mut(&self) -> RwLockWriteGuard<'_, HashMap<i64, Person>> {
+        self.people.write().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Creates `person` unless its nickname is taken.
+    pub(crate) fn insert(&self, person: CreatePersonPayload) -> Result<Person, AppError> {
+        let mut people = self.people_mut();
+        if nickname_taken(&people, &person.nickname, None) {
+            return Err(RepositoryError::UniqueViolation {
+                field: "apelido",
+                value: person.nickname,
+            }
+            .into());
+        }
+        let person = self.to_person(person);
+        people.insert(person.id, person.clone());
+        Ok(person)
+    }
+
+    pub(crate) fn deleted(&self) -> RwLockReadGuard<'_, HashMap<i64, Person>> {
+        self.deleted.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn deleted_mut(&self) -> RwLockWriteGuard<'_, HashMap<i64, Person>> {
+        self.deleted.write().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Everyone matching `filter`, ordered by id.
+    pub(crate) fn select(&self, filter: impl Fn(&Person) -> bool) -> Vec<Person> {
+        let mut people: Vec<Person> = self
+            .people()
+            .values()
+            .filter(|p| filter(p))
+            .cloned()
+            .collect();
+        people.sort_by_key(|p| p.id);
+        people
+    }
+
+    pub(crate) fn to_person(&self, payload: CreatePersonPayload) -> Person {
+        let stacks = payload.stack_names();
+        let now = Utc::now();
+        Person {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            nickname: payload.nickname,
+            name: payload.name,
+            dob: payload.dob.0,
+            stacks,
+            stacks_detail: match self.stacks_storage {
+                StacksStorage::Array => None,
+                StacksStorage::Jsonb => payload.stacks.map(sqlx::types::Json),
+            },
+            photo: None,
+            created_at: Some(now),
+            updated_at: Some(now),
+            deleted_at: None,
+            view_count: 0,
+            version: 1,
+        }
+    }
+
+    pub(crate) fn attach_stacks_meta(&self, stacks: &mut [StackCount]) {
+        let stacks_meta = self
+            .stacks_meta
+            .read()
+            .unwrap_or_else(|err| err.into_inner());
+        for stack in stacks {
+            if let Some(meta) = stacks_meta.get(&stack.stack) {
+                stack.display_name.clone_from(&meta.display_name);
+                stack.icon.clone_from(&meta.icon);
+            }
+        }
+    }
+
+    pub(crate) fn not_found(id: i64) -> AppError {
+        RepositoryError::NotFound {
+            resoure_name: "person",
+            resource_id: id,
+        }
+        .into()
+    }
+}
+
+pub(crate) fn nickname_taken(
+    people: &HashMap<i64, Person>,
+    nickname: &str,
+    except_id: Option<i64>,
+) -> bool {
+    people
+        .values()
+        .any(|p| p.nickname == nickname && Some(p.id) != except_id)
+}
+
+/// The in-memory counterpart of [`push_search_filter`]: `LIKE` is case-sensitive, `ILIKE` is not.
+pub(crate) fn matches_search(person: &Person, terms: &SearchTerms) -> bool {
+    let fields = || {
+        [person.nickname.as_str(), person.name.as_str()]
+            .into_iter()
+            .chain(person.stacks.iter().flatten().map(String::as_str))
+    };
+    terms
+        .include
+        .iter()
+        .all(|term| fields().any(|field| field.contains(term.as_str())))
+        && !terms.exclude.iter().any(|term| {
+            let term = term.to_lowercase();
+            fields().any(|field| field.to_lowercase().contains(&term))
+        })
+}
+
+/// Counts every stack of `people`, ordered by count descending then by stack name.
+pub(crate) fn count_stacks<'a>(
+    people: impl Iterator<Item = &'a Person>,
+    limit: i64,
+) -> Vec<StackCount> {
+    let mut counts: HashMap<&str, i64> = HashMap::new();
+    for stack in people.flat_map(|p| p.stacks.iter().flatten()) {
+        *counts.entry(stack).or_default() += 1;
+    }
+    let mut counts: Vec<StackCount> = counts
+        .into_iter()
+        .map(|(stack, count)| StackCount {
+            stack: stack.to_owned(),
+            count,
+            display_name: None,
+            icon: None,
+        })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.stack.cmp(&b.stack)));
+    counts.truncate(usize::try_from(limit).unwrap_or_default());
+    counts
+}
+
+/// Whole years between `dob` and `today`, like Postgres' `age()`.
+pub(crate) fn age_in_years(dob: NaiveDate, today: NaiveDate) -> i32 {
+    let had_birthday = (today.month(), today.day()) >= (dob.month(), dob.day());
+    today.year() - dob.year() - i32::from(!had_birthday)
+}
+
+pub(crate) fn page<T>(items: Vec<T>, limit: i64, offset: i64) -> Vec<T> {
+    items
+        .into_iter()
+        .skip(usize::try_from(offset).unwrap_or_default())
+        .take(usize::try_from(limit).unwrap_or_default())
+        .collect()
+}
+
+#[async_trait]
+impl PersonRepository for InMemoryPersonRepository {
+    async fn create_person(&self, person: CreatePersonPayload) -> Result<Person, AppError> {
+        self.insert(person)
+    }
+
+    async fn create_person_idempotently(
+        &self,
+        key: String,
+        fingerprint: String,
+        person: CreatePersonPayload,
+    ) -> Result<IdempotentCreation, AppError> {
+        // held throughout, so a creation with the same key waits for this one
+        let mut keys = self
+            .idempotency_keys
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if let Some((record, created_at)) = keys.get(&key) {
+            if created_at.elapsed() < self.idempotency_key_ttl {
+                return Ok(IdempotentCreation::Existing(record.clone()));
+            }
+        }
+        let person = self.insert(person)?;
+        let record = IdempotencyRecord {
+            fingerprint,
+            person_id: person.id,
+        };
+        keys.insert(key, (record, Instant::now()));
+        Ok(IdempotentCreation::Created(person))
+    }
+
+    async fn create_people_bulk(
+        &self,
+        batch: Vec<CreatePersonPayload>,
+    ) -> Result<Vec<Option<Person>>, AppError> {
+        let mut people = self.people_mut();
+        let mut created = Vec::with_capacity(batch.len());
+        for person in batch {
+            if nickname_taken(&people, &person.nickname, None) {
+                created.push(None);
+                continue;
+            }
+            let person = self.to_person(person);
+            people.insert(person.id, person.clone());
+            created.push(Some(person));
+        }
+        Ok(created)
+    }
+
+    async fn import_people(
+        &self,
+        mut batches: mpsc::Receiver<Vec<CreatePersonPayload>>,
+        finished: oneshot::Receiver<()>,
+        dry_run: bool,
+    ) -> Result<(u64, u64), AppError> {
+        // held back until every batch is in, like the Postgres transaction
+        let mut would_take = HashSet::new();
+        let (mut received, mut staged) = (0, Vec::new());
+        while let Some(batch) = batches.recv().await {
+            received += batch.len() as u64;
+            let people = self.people();
+            for payload in batch {
+                if nickname_taken(&people, &payload.nickname, None)
+                    || !would_take.insert(payload.nickname.clone())
+                {
+                    continue;
+                }
+                staged.push(payload);
+            }
+        }
+        if finished.await.is_err() {
+            return Err(RepositoryError::Unexpected.into());
+        }
+        let mut inserted = 0;
+        let mut people = self.people_mut();
+        for payload in staged {
+            // taken by a creation since it was staged
+            if nickname_taken(&people, &payload.nickname, None) {
+                continue;
+            }
+            inserted += 1;
+            if !dry_run {
+                let person = self.to_person(payload);
+                people.insert(person.id, person);
+            }
+        }
+        Ok((received, inserted))
+    }
+
+    async fn get_person(&self, id: i64) -> Result<Person, AppError> {
+        self.people()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| Self::not_found(id))
+    }
+
+    async fn get_person_including_deleted(&self, id: i64) -> Result<Person, AppError> {
+        match self.get_person(id).await {
+            Err(_) => self
+                .deleted()
+                .get(&id)
+                .cloned()
+                .ok_or_else(|| Self::not_found(id)),
+            found => found,
+        }
+    }
+
+    async fn get_and_track(&self, id: i64) -> Result<Person, AppError> {
+        let mut people = self.people_mut();
+        let person = people.get_mut(&id).ok_or_else(|| Self::not_found(id))?;
+        person.view_count += 1;
+        Ok(person.clone())
+    }
+
+    async fn search_person(
+        &self,
+        term: String,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        let terms = SearchTerms::parse(&term);
+        Ok(page(
+            self.select(|p| matches_search(p, &terms)),
+            limit,
+            offset,
+        ))
+    }
+
+    async fn count_search(&self, term: String) -> Result<i64, AppError> {
+        let terms = SearchTerms::parse(&term);
+        Ok(self.select(|p| matches_search(p, &terms)).len() as i64)
+    }
+
+    async fn fuzzy_search(
+        &self,
+        term: String,
+        _threshold: f32,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        let term = term.to_lowercase();
+        let people = self.select(|p| {
+            [&p.nickname, &p.name]
+                .into_iter()
+                .chain(p.stacks.iter().flatten())
+                .any(|field| field.to_lowercase().contains(&term))
+        });
+        Ok(page(people, limit, offset))
+    }
+
+    async fn advanced_search(&self, search: AdvancedSearch) -> Result<Vec<Person>, AppError> {
+        let field_matches = |filter: &Option<FieldMatch>, field: &str| {
+            filter
+                .as_ref()
+                .is_none_or(|f| f.mode.matches(field, &f.value))
+        };
+        let mut people = self.select(|p| {
+            matches_search(p, &search.terms)
+                && search.from.is_none_or(|from| p.dob >= from)
+                && search.to.is_none_or(|to| p.dob <= to)
+                && field_matches(&search.nickname, &p.nickname)
+                && field_matches(&search.name, &p.name)
+                && search.stack.as_ref().is_none_or(|stack| {
+                    p.stacks
+                        .iter()
+                        .flatten()
+                        .any(|s| stack.mode.matches(s, &stack.value))
+                })
+        });
+        let SortOrder { column, descending } = search.sort;
+        // stable, so people sorting equal stay ordered by id
+        people.sort_by(|a, b| {
+            let order = match column {
+                "nickname" => a.nickname.cmp(&b.nickname),
+                "name" => a.name.cmp(&b.name),
+                "dob" => a.dob.cmp(&b.dob),
+                _ => a.id.cmp(&b.id),
+            };
+            if descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+        if let Some(after) = &search.after {
+            people.retain(|p| after.precedes(p, search.sort));
+        }
+        Ok(page(people, search.limit, search.offset))
+    }
+
+    async fn count(&self) -> Result<i64, AppError> {
+        Ok(self.people().len() as i64)
+    }
+
+    async fn count_breakdown(&self) -> Result<CountBreakdown, AppError> {
+        let people = self.people();
+        let with_stacks = people
+            .values()
+            .filter(|p| p.stacks.as_ref().is_some_and(|s| !s.is_empty()))
+            .count() as i64;
+        let total = people.len() as i64;
+        Ok(CountBreakdown {
+            total,
+            with_stacks,
+            without_stacks: total - with_stacks,
+        })
+    }
+
+    async fn db_info(&self) -> Result<DbInfo, AppError> {
+        Ok(DbInfo {
+            migration_version: None,
+            server_version: "in-memory".to_owned(),
+        })
+    }
+
+    async fn dashboard_stats(&self) -> Result<DashboardStats, AppError> {
+        let counts = self.count_breakdown().await?;
+        let people = self.people();
+        let today = Utc::now().date_naive();
+        let min_dob = NaiveDate::from_ymd_opt(MIN_DOB_YEAR, 1, 1).expect("valid date");
+        let ages: Vec<i32> = people
+            .values()
+            .filter_map(|p| match validate_dob(p.dob, today) {
+                Ok(()) => Some(age_in_years(p.dob, today)),
+                Err(_) if self.invalid_ages == InvalidAges::Clamp => {
+                    Some(age_in_years(p.dob.clamp(min_dob, today), today))
+                }
+                Err(_) => None,
+            })
+            .collect();
+        let excluded = (people.len() - ages.len()) as i64;
+        let avg = (!ages.is_empty()).then(|| ages.iter().sum::<i32>() as f64 / ages.len() as f64);
+        Ok(DashboardStats {
+            counts,
+            top_stacks: count_stacks(people.values(), DASHBOARD_TOP_STACKS),
+            age: AgeStats {
+                min: ages.iter().copied().min(),
+                max: ages.iter().copied().max(),
+                avg,
+                excluded,
+            },
+            newest: people
+                .values()
+                .max_by_key(|p| (p.created_at, p.id))
+                .cloned(),
+        })
+    }
+
+    async fn estimated_count(&self) -> Result<i64, AppError> {
+        self.count().await
+    }
+
+    async fn similar_by_stacks(&self, id: i64, limit: i64) -> Result<Vec<Person>, AppError> {
+        let Some(target) = self.people().get(&id).and_then(|p| p.stacks.clone()) else {
+            return Ok(Vec::new());
+        };
+        let shared = |p: &Person| {
+            p.stacks
+                .iter()
+                .flatten()
+                .filter(|s| target.contains(s))
+                .count()
+        };
+        let mut people = self.select(|p| p.id != id && shared(p) > 0);
+        people.sort_by_key(|p| Reverse(shared(p)));
+        Ok(page(people, limit, 0))
+    }
+
+    async fn random_person(&self) -> Result<Person, AppError> {
+        let people = self.people();
+        if people.is_empty() {
+            return Err(RepositoryError::Empty {
+                resoure_name: "person",
+            }
+            .into());
+        }
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.subsec_nanos());
+        let person = people.values().nth(nanos as usize % people.len());
+        Ok(person.cloned().unwrap_or_default())
+    }
+
+    async fn get_by_nicknames(&self, nicknames: Vec<String>) -> Result<Vec<Person>, AppError> {
+        let nicknames: Vec<String> = nicknames.iter().map(|n| n.to_lowercase()).collect();
+        Ok(self.select(|p| nicknames.contains(&p.nickname.to_lowercase())))
+    }
+
+    fn stream_people(&self) -> BoxStream<'static, Result<Person, AppError>> {
+        let people = self.select(|_| true);
+        futures::stream::iter(people.into_iter().map(Ok)).boxed()
+    }
+
+    async fn list_people(
+        &self,
+        after_id: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        let people = self.select(|p| after_id.is_none_or(|after_id| p.id > after_id));
+        Ok(page(people, limit, 0))
+    }
+
+    async fn people_in_id_range(
+        &self,
+        from_id: i64,
+        to_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        let people = self.select(|p| (from_id..=to_id).contains(&p.id));
+        Ok(page(people, limit, 0))
+    }
+
+    async fn timeline(
+        &self,
+        before: Option<(DateTime<Utc>, Option<i64>)>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        let mut people = self.select(|p| match before {
+            Some((before, before_id)) => {
+                (p.created_at, p.id) < (Some(before), before_id.unwrap_or(0))
+            }
+            None => true,
+        });
+        people.sort_by_key(|p| Reverse((p.created_at, p.id)));
+        Ok(page(people, limit, 0))
+    }
+
+    async fn count_by_birth_year(&self) -> Result<Vec<BirthYearCount>, AppError> {
+        let mut counts: HashMap<i32, i64> = HashMap::new();
+        for person in self.people().values() {
+            *counts.entry(person.dob.year()).or_default() += 1;
+        }
+        let mut counts: Vec<BirthYearCount> = counts
+            .into_iter()
+            .map(|(year, count)| BirthYearCount { year, count })
+            .collect();
+        counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.year.cmp(&b.year)));
+        Ok(counts)
+    }
+
+    async fn suspicious_people(&self, limit: i64) -> Result<Vec<Person>, AppError> {
+        let today = Utc::now().date_naive();
+        let people = self.select(|p| {
+            p.name.to_lowercase() == p.nickname.to_lowercase()
+                || p.name.trim().chars().count() <= 1
+                || validate_dob(p.dob, today).is_err()
+        });
+        Ok(page(people, limit, 0))
+    }
+
+    async fn duplicate_candidates(
+        &self,
+        _threshold: f32,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<DuplicateCandidate>, AppError> {
+        let people = self.select(|_| true);
+        let mut pairs = Vec::new();
+        for (i, first) in people.iter().enumerate() {
+            for second in &people[i + 1..] {
+                if first.name.to_lowercase() == second.name.to_lowercase() {
+                    pairs.push(DuplicateCandidate {
+                        first_id: first.id,
+                        first_name: first.name.clone(),
+                        second_id: second.id,
+                        second_name: second.name.clone(),
+                        similarity: 1.0,
+                    });
+                }
+            }
+        }
+        Ok(page(pairs, limit, offset))
+    }
+
+    async fn incomplete_people(
+        &self,
+        after_id: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        let people = self.select(|p| {
+            after_id.is_none_or(|after_id| p.id > after_id)
+                && p.stacks.is_some()
+                && p.stacks_detail.is_none()
+        });
+        Ok(page(people, limit, 0))
+    }
+
+    async fn trending_stacks(&self, days: i32, limit: i64) -> Result<Vec<StackCount>, AppError> {
+        let since = Utc::now() - chrono::Duration::days(days.into());
+        let people = self.people();
+        let recent = people
+            .values()
+            .filter(|p| p.created_at.is_some_and(|created_at| created_at >= since));
+        let mut stacks = count_stacks(recent, limit);
+        self.attach_stacks_meta(&mut stacks);
+        Ok(stacks)
+    }
+
+    async fn related_stacks(&self, name: String, limit: i64) -> Result<Vec<StackCount>, AppError> {
+        let people = self.people();
+        let others = people
+            .values()
+            .filter_map(|p| p.stacks.as_ref())
+            .filter(|stacks| stacks.contains(&name))
+            .flat_map(|stacks| {
+                let others: HashSet<&String> = stacks.iter().filter(|s| **s != name).collect();
+                others
+            });
+        let mut counts: HashMap<&str, i64> = HashMap::new();
+        for other in others {
+            *counts.entry(other).or_default() += 1;
+        }
+        let mut stacks: Vec<StackCount> = counts
+            .into_iter()
+            .map(|(stack, count)| StackCount {
+                stack: stack.to_owned(),
+                count,
+                display_name: None,
+                icon: None,
+            })
+            .collect();
+        stacks.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.stack.cmp(&b.stack)));
+        stacks.truncate(usize::try_from(limit).unwrap_or_default());
+        self.attach_stacks_meta(&mut stacks);
+        Ok(stacks)
+    }
+
+    async fn get_stack_meta(&self, name: String) -> Result<StackMeta, AppError> {
+        let stacks_meta = self
+            .stacks_meta
+            .read()
+            .unwrap_or_else(|err| err.into_inner());
+        match stacks_meta.get(&name) {
+            Some(meta) => Ok(meta.clone()),
+            None => Err(RepositoryError::NotFoundByName {
+                resoure_name: "stack",
+                name,
+            }
+            .into()),
+        }
+    }
+
+    async fn put_stack_meta(&self, meta: StackMeta) -> Result<StackMeta, AppError> {
+        let mut stacks_meta = self
+            .stacks_meta
+            .write()
+            .unwrap_or_else(|err| err.into_inner());
+        stacks_meta.insert(meta.name.clone(), meta.clone());
+        Ok(meta)
+    }
+
+    async fn purge_deleted(&self, older_than_days: i32) -> Result<u64, AppError> {
+        let cutoff = Utc::now() - chrono::Duration::days(older_than_days.into());
+        let mut deleted = self.deleted_mut();
+        let before = deleted.len();
+        deleted.retain(|_, person| person.deleted_at.is_some_and(|at| at >= cutoff));
+        Ok((before - deleted.len()) as u64)
+    }
+
+    async fn set_photo(&self, id: i64, photo: String) -> Result<Person, AppError> {
+        let mut people = self.people_mut();
+        let person = people.get_mut(&id).ok_or_else(|| Self::not_found(id))?;
+        person.photo = Some(photo);
+        person.version += 1;
+        person.updated_at = Some(Utc::now());
+        Ok(person.clone())
+    }
+
+    async fn remove_stacks(&self, id: i64, stacks: Vec<String>) -> Result<Person, AppError> {
+        let mut people = self.people_mut();
+        let person = people.get_mut(&id).ok_or_else(|| Self::not_found(id))?;
+        if let Some(names) = &mut person.stacks {
+            names.retain(|name| !stacks.contains(name));
+        }
+        if let Some(detail) = &mut person.stacks_detail {
+            detail.0.retain(|stack| !stacks.contains(&stack.name));
+        }
+        person.version += 1;
+        person.updated_at = Some(Utc::now());
+        Ok(person.clone())
+    }
+
+    async fn patch_person(&self, id: i64, patch: PersonMergePatch) -> Result<Person, AppError> {
+        if patch.is_empty() {
+            return self.get_person(id).await;
+        }
+        let mut people = self.people_mut();
+        if let Some(Some(nickname)) = &patch.nickname {
+            if nickname_taken(&people, nickname, Some(id)) {
+                return Err(RepositoryError::UniqueViolation {
+                    field: "apelido",
+                    value: nickname.clone(),
+                }
+                .into());
+            }
+        }
+        let person = people.get_mut(&id).ok_or_else(|| Self::not_found(id))?;
+        if let Some(Some(nickname)) = patch.nickname {
+            person.nickname = nickname;
+        }
+        if let Some(Some(name)) = patch.name {
+            person.name = name;
+        }
+        if let Some(Some(dob)) = patch.dob {
+            person.dob = dob.0;
+        }
+        if let Some(stacks) = patch.stacks {
+            person.stacks = stacks.as_deref().map(stack_names);
+            if self.stacks_storage == StacksStorage::Jsonb {
+                person.stacks_detail = stacks.map(sqlx::types::Json);
+            }
+        }
+        person.version += 1;
+        person.updated_at = Some(Utc::now());
+        Ok(person.clone())
+    }
+
+    async fn delete_person(&self, id: i64, version: Option<i64>) -> Result<(), AppError> {
+        let mut people = self.people_mut();
+        let person = people.get(&id).ok_or_else(|| Self::not_found(id))?;
+        if version.is_some_and(|version| version != person.version) {
+            return Err(RepositoryError::StaleVersion {
+                resoure_name: "person",
+                resource_id: id,
+            }
+            .into());
+        }
+        if let Some(mut person) = people.remove(&id) {
+            person.deleted_at = Some(Utc::now());
+            person.updated_at = person.deleted_at;
+            person.version += 1;
+            self.deleted_mut().insert(id, person);
+        }
+        Ok(())
+    }
+
+    async fn reindex(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn missing_indexes(&self) -> Result<Vec<String>, AppError> {
+        Ok(vec![])
+    }
+}
+
+/// Wraps any [`PersonRepository`] with a `repository` span per call, logging how long
+/// it took and counting the calls made and those that failed, so implementations stay free of it.
+pub(crate) struct InstrumentedRepository<R> {
+    pub(crate) inner: R,
+    pub(crate) calls: AtomicU64,
+    pub(crate) errors: AtomicU64,
+}
+
+impl<R: PersonRepository> InstrumentedRepository<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        InstrumentedRepository {
+            inner,
+            calls: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) async fn instrument<T>(
+        &self,
+        method: &'static str,
+        call: impl Future<Output = Result<T, AppError>>,
+    ) -> Result<T, AppError> {
+        let started = Instant::now();
+        let result = call
+            .instrument(tracing::debug_span!("repository", method))
+            .await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        let calls = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        match &result {
+            Ok(_) => debug!(method, elapsed_ms, calls, "repository call succeeded"),
+            Err(_) => {
+                // not found and the like count too, they are still failed calls
+                let errors = self.errors.fetch_add(1, Ordering::Relaxed) + 1;
+                debug!(method, elapsed_ms, calls, errors, "repository call failed");
+            }
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<R: PersonRepository + Send + Sync> PersonRepository for InstrumentedRepository<R> {
+    async fn create_person(&self, person: CreatePersonPayload) -> Result<Person, AppError> {
+        self.instrument("create_person", self.inner.create_person(person))
+            .await
+    }
+
+    async fn create_person_idempotently(
+        &self,
+        key: String,
+        fingerprint: String,
+        person: CreatePersonPayload,
+    ) -> Result<IdempotentCreation, AppError> {
+        self.instrument(
+            "create_person_idempotently",
+            self.inner
+                .create_person_idempotently(key, fingerprint, person),
+        )
+        .await
+    }
+
+    async fn create_people_bulk(
+        &self,
+        people: Vec<CreatePersonPayload>,
+    ) -> Result<Vec<Option<Person>>, AppError> {
+        self.instrument("create_people_bulk", self.inner.create_people_bulk(people))
+            .await
+    }
+
+    async fn import_people(
+        &self,
+        batches: mpsc::Receiver<Vec<CreatePersonPayload>>,
+        finished: oneshot::Receiver<()>,
+        dry_run: bool,
+    ) -> Result<(u64, u64), AppError> {
+        self.instrument(
+            "import_people",
+            self.inner.import_people(batches, finished, dry_run),
+        )
+        .await
+    }
+
+    async fn get_person(&self, id: i64) -> Result<Person, AppError> {
+        self.instrument("get_person", self.inner.get_person(id))
+            .await
+    }
+
+    async fn get_person_including_deleted(&self, id: i64) -> Result<Person, AppError> {
+        self.instrument(
+            "get_person_including_deleted",
+            self.inner.get_person_including_deleted(id),
+        )
+        .await
+    }
+
+    async fn get_and_track(&self, id: i64) -> Result<Person, AppError> {
+        self.instrument("get_and_track", self.inner.get_and_track(id))
+            .await
+    }
+
+    async fn search_person(
+        &self,
+        term: String,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        self.instrument(
+            "search_person",
+            self.inner.search_person(term, limit, offset),
+        )
+        .await
+    }
+
+    async fn count_search(&self, term: String) -> Result<i64, AppError> {
+        self.instrument("count_search", self.inner.count_search(term))
+            .await
+    }
+
+    async fn fuzzy_search(
+        &self,
+        term: String,
+        threshold: f32,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        self.instrument(
+            "fuzzy_search",
+            self.inner.fuzzy_search(term, threshold, limit, offset),
+        )
+        .await
+    }
+
+    async fn advanced_search(&self, search: AdvancedSearch) -> Result<Vec<Person>, AppError> {
+        self.instrument("advanced_search", self.inner.advanced_search(search))
+            .await
+    }
+
+    async fn count(&self) -> Result<i64, AppError> {
+        self.instrument("count", self.inner.count()).await
+    }
+
+    async fn count_breakdown(&self) -> Result<CountBreakdown, AppError> {
+        self.instrument("count_breakdown", self.inner.count_breakdown())
+            .await
+    }
+
+    async fn db_info(&self) -> Result<DbInfo, AppError> {
+        self.instrument("db_info", self.inner.db_info()).await
+    }
+
+    async fn dashboard_stats(&self) -> Result<DashboardStats, AppError> {
+        self.instrument("dashboard_stats", self.inner.dashboard_stats())
+            .await
+    }
+
+    async fn estimated_count(&self) -> Result<i64, AppError> {
+        self.instrument("estimated_count", self.inner.estimated_count())
+            .await
+    }
+
+    async fn similar_by_stacks(&self, id: i64, limit: i64) -> Result<Vec<Person>, AppError> {
+        self.instrument("similar_by_stacks", self.inner.similar_by_stacks(id, limit))
+            .await
+    }
+
+    async fn random_person(&self) -> Result<Person, AppError> {
+        self.instrument("random_person", self.inner.random_person())
+            .await
+    }
+
+    async fn get_by_nicknames(&self, nicknames: Vec<String>) -> Result<Vec<Person>, AppError> {
+        self.instrument("get_by_nicknames", self.inner.get_by_nicknames(nicknames))
+            .await
+    }
+
+    fn stream_people(&self) -> BoxStream<'static, Result<Person, AppError>> {
+        debug!(method = "stream_people", "repository stream started");
+        self.inner.stream_people()
+    }
+
+    async fn list_people(
+        &self,
+        after_id: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        self.instrument("list_people", self.inner.list_people(after_id, limit))
+            .await
+    }
+
+    async fn people_in_id_range(
+        &self,
+        from_id: i64,
+        to_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        let call = self.inner.people_in_id_range(from_id, to_id, limit);
+        self.instrument("people_in_id_range", call).await
+    }
+
+    async fn timeline(
+        &self,
+        before: Option<(DateTime<Utc>, Option<i64>)>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        self.instrument("timeline", self.inner.timeline(before, limit))
+            .await
+    }
+
+    async fn count_by_birth_year(&self) -> Result<Vec<BirthYearCount>, AppError> {
+        self.instrument("count_by_birth_year", self.inner.count_by_birth_year())
+            .await
+    }
+
+    async fn suspicious_people(&self, limit: i64) -> Result<Vec<Person>, AppError> {
+        self.instrument("suspicious_people", self.inner.suspicious_people(limit))
+            .await
+    }
+
+    async fn incomplete_people(
+        &self,
+        after_id: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        self.instrument(
+            "incomplete_people",
+            self.inner.incomplete_people(after_id, limit),
+        )
+        .await
+    }
+
+    async fn trending_stacks(&self, days: i32, limit: i64) -> Result<Vec<StackCount>, AppError> {
+        self.instrument("trending_stacks", self.inner.trending_stacks(days, limit))
+            .await
+    }
+
+    async fn related_stacks(&self, name: String, limit: i64) -> Result<Vec<StackCount>, AppError> {
+        self.instrument("related_stacks", self.inner.related_stacks(name, limit))
+            .await
+    }
+
+    async fn get_stack_meta(&self, name: String) -> Result<StackMeta, AppError> {
+        self.instrument("get_stack_meta", self.inner.get_stack_meta(name))
+            .await
+    }
+
+    async fn put_stack_meta(&self, meta: StackMeta) -> Result<StackMeta, AppError> {
+        self.instrument("put_stack_meta", self.inner.put_stack_meta(meta))
+            .await
+    }
+
+    async fn duplicate_candidates(
+        &self,
+        threshold: f32,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<DuplicateCandidate>, AppError> {
+        let call = self.inner.duplicate_candidates(threshold, limit, offset);
+        self.instrument("duplicate_candidates", call).await
+    }
+
+    async fn purge_deleted(&self, older_than_days: i32) -> Result<u64, AppError> {
+        self.instrument("purge_deleted", self.inner.purge_deleted(older_than_days))
+            .await
+    }
+
+    async fn set_photo(&self, id: i64, photo: String) -> Result<Person, AppError> {
+        self.instrument("set_photo", self.inner.set_photo(id, photo))
+            .await
+    }
+
+    async fn remove_stacks(&self, id: i64, stacks: Vec<String>) -> Result<Person, AppError> {
+        self.instrument("remove_stacks", self.inner.remove_stacks(id, stacks))
+            .await
+    }
+
+    async fn patch_person(&self, id: i64, patch: PersonMergePatch) -> Result<Person, AppError> {
+        self.instrument("patch_person", self.inner.patch_person(id, patch))
+            .await
+    }
+
+    async fn delete_person(&self, id: i64, version: Option<i64>) -> Result<(), AppError> {
+        self.instrument("delete_person", self.inner.delete_person(id, version))
+            .await
+    }
+
+    async fn reindex(&self) -> Result<(), AppError> {
+        self.instrument("reindex", self.inner.reindex()).await
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        self.instrument("ping", self.inner.ping()).await
+    }
+
+    async fn missing_indexes(&self) -> Result<Vec<String>, AppError> {
+        self.instrument("missing_indexes", self.inner.missing_indexes())
+            .await
+    }
+}
+
+/// Where [`CachedPersonRepository`] keeps the people read by id and the nicknames known
+/// to be taken, each for the cache's TTL. A cache that fails is only a miss.
+#[async_trait]
+pub(crate) trait PersonCache {
+    async fn person(&self, id: i64) -> Option<Person>;
+    async fn nickname_taken(&self, nickname: &str) -> bool;
+    /// Caches `person`, which also takes its nickname.
+    async fn put(&self, person: &Person);
+    async fn take_nickname(&self, nickname: &str);
+    async fn forget_person(&self, id: i64);
+    async fn free_nickname(&self, nickname: &str);
+    /// Forgets every person and nickname.
+    async fn clear(&self);
+}
+
+/// Most people and nicknames [`LocalCache`] keeps, past which the expired ones are dropped.
+pub(crate) const CACHE_MAX_ENTRIES: usize = 100_000;
+
+/// A [`PersonCache`] in the process memory, so a person changed or deleted, or a nickname
+/// freed, through another replica is only noticed here once the entry expires.
+pub(crate) struct LocalCache {
+    pub(crate) ttl: Duration,
+    pub(crate) people: Mutex<HashMap<i64, (Person, Instant)>>,
+    /// Nicknames known to be taken, with when that was learnt.
+    pub(crate) nicknames: Mutex<HashMap<String, Instant>>,
+}
+
+impl LocalCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        LocalCache {
+            ttl,
+            people: Mutex::default(),
+            nicknames: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn people(&self) -> MutexGuard<'_, HashMap<i64, (Person, Instant)>> {
+        self.people.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn nicknames(&self) -> MutexGuard<'_, HashMap<String, Instant>> {
+        self.nicknames.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[async_trait]
+impl PersonCache for LocalCache {
+    async fn person(&self, id: i64) -> Option<Person> {
+        let people = self.people();
+        let (person, cached_at) = people.get(&id)?;
+        (cached_at.elapsed() < self.ttl).then(|| person.clone())
+    }
+
+    async fn nickname_taken(&self, nickname: &str) -> bool {
+        self.nicknames()
+            .get(nickname)
+            .is_some_and(|learnt_at| learnt_at.elapsed() < self.ttl)
+    }
+
+    async fn put(&self, person: &Person) {
+        {
+            let mut people = self.people();
+            if people.len() >= CACHE_MAX_ENTRIES {
+                people.retain(|_, (_, cached_at)| cached_at.elapsed() < self.ttl);
+            }
+            people.insert(person.id, (person.clone(), Instant::now()));
+        }
+        self.take_nickname(&person.nickname).await;
+    }
+
+    async fn take_nickname(&self, nickname: &str) {
+        let mut nicknames = self.nicknames();
+        if nicknames.len() >= CACHE_MAX_ENTRIES {
+            nicknames.retain(|_, learnt_at| learnt_at.elapsed() < self.ttl);
+        }
+        nicknames.insert(nickname.to_owned(), Instant::now());
+    }
+
+    async fn forget_person(&self, id: i64) {
+        self.people().remove(&id);
+    }
+
+    async fn free_nickname(&self, nickname: &str) {
+        self.nicknames().remove(nickname);
+    }
+
+    async fn clear(&self) {
+        self.people().clear();
+        self.nicknames().clear();
+    }
+}
+
+/// Prefix of every key [`RedisCache`] sets.
+pub(crate) const REDIS_KEY_PREFIX: &str = "people_api:";
+
+/// A [`PersonCache`] in Redis, shared by every replica using it: `people_api:person:<id>`
+/// holds a person as JSON and `people_api:nickname:<nickname>` marks a nickname taken,
+/// each expiring on its own after the TTL.
+#[derive(Clone)]
+pub(crate) struct RedisCache {
+    pub(crate) conn: redis::aio::ConnectionManager,
+    pub(crate) ttl: Duration,
+}
+
+/// A [`Person`] under its own field names, which the responses don't use.
+#[derive(Serialize)]
+pub(crate) struct StoredPerson<'a> {
+    id: i64,
+    nickname: &'a str,
+    name: &'a str,
+    dob: NaiveDate,
+    stacks: &'a Option<Vec<String>>,
+    stacks_detail: &'a Option<sqlx::types::Json<Vec<Stack>>>,
+    photo: &'a Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+    view_count: i64,
+    version: i64,
+}
+
+impl<'a> From<&'a Person> for StoredPerson<'a> {
+    fn from(person: &'a Person) -> Self {
+        StoredPerson {
+            id: person.id,
+            nickname: &person.nickname,
+            name: &person.name,
+            dob: person.dob,
+            stacks: &person.stacks,
+            stacks_detail: &person.stacks_detail,
+            photo: &person.photo,
+            created_at: person.created_at,
+            updated_at: person.updated_at,
+            deleted_at: person.deleted_at,
+            view_count: person.view_count,
+            version: person.version,
+        }
+    }
+}
+
+impl RedisCache {
+    pub(crate) async fn connect(url: &str, ttl: Duration) -> Result<Self, redis::RedisError> {
+        let conn = redis::Client::open(url)?.get_connection_manager().await?;
+        Ok(RedisCache { conn, ttl })
+    }
+
+    fn person_key(id: i64) -> String {
+        format!("{REDIS_KEY_PREFIX}person:{id}")
+    }
+
+    fn nickname_key(nickname: &str) -> String {
+        format!("{REDIS_KEY_PREFIX}nickname:{nickname}")
+    }
+
+    /// Runs `cmd`, logging a failure as a miss.
+    async fn query<T: redis::FromRedisValue>(&self, cmd: &redis::Cmd) -> Option<T> {
+        match cmd.query_async(&mut self.conn.clone()).await {
+            Ok(value) => Some(value),
+            Err(err) => {
+                warn!(%err, "person cache unavailable");
+                None
+            }
+        }
+    }
+
+    async fn set(&self, key: String, value: impl redis::ToRedisArgs) {
+        let ttl_ms = self.ttl.as_millis() as u64;
+        self.query::<()>(redis::cmd("SET").arg(key).arg(value).arg("PX").arg(ttl_ms))
+            .await;
+    }
+}
+
+#[async_trait]
+impl PersonCache for RedisCache {
+    async fn person(&self, id: i64) -> Option<Person> {
+        let json: Option<Vec<u8>> = self
+            .query(redis::cmd("GET").arg(Self::person_key(id)))
+            .await?;
+        serde_json::from_slice(&json?).ok()
+    }
+
+    async fn nickname_taken(&self, nickname: &str) -> bool {
+        self.query(redis::cmd("EXISTS").arg(Self::nickname_key(nickname)))
+            .await
+            .unwrap_or(false)
+    }
+
+    async fn put(&self, person: &Person) {
+        let json =
+            serde_json::to_vec(&StoredPerson::from(person)).expect("a person always serializes");
+        self.set(Self::person_key(person.id), json).await;
+        self.take_nickname(&person.nickname).await;
+    }
+
+    async fn take_nickname(&self, nickname: &str) {
+        self.set(Self::nickname_key(nickname), 1).await;
+    }
+
+    async fn forget_person(&self, id: i64) {
+        self.query::<()>(redis::cmd("DEL").arg(Self::person_key(id)))
+            .await;
+    }
+
+    async fn free_nickname(&self, nickname: &str) {
+        self.query::<()>(redis::cmd("DEL").arg(Self::nickname_key(nickname)))
+            .await;
+    }
+
+    async fn clear(&self) {
+        let mut cursor = 0;
+        loop {
+            let Some((next, keys)) = self
+                .query::<(u64, Vec<String>)>(
+                    redis::cmd("SCAN")
+                        .arg(cursor)
+                        .arg("MATCH")
+                        .arg(format!("{REDIS_KEY_PREFIX}*"))
+                        .arg("COUNT")
+                        .arg(1000),
+                )
+                .await
+            else {
+                return;
+            };
+            if !keys.is_empty() {
+                self.query::<()>(redis::cmd("DEL").arg(keys)).await;
+            }
+            if next == 0 {
+                return;
+            }
+            cursor = next;
+        }
+    }
+}
+
+/// Serves `GET /pessoas/:id` and the nickname conflicts of creations from a [`PersonCache`]
+/// for [`Config::person_cache_ttl`], so under load they don't reach the database.
+pub(crate) struct CachedPersonRepository<R> {
+    pub(crate) inner: R,
+    pub(crate) cache: Box<dyn PersonCache + Send + Sync>,
+}
+
+impl<R: PersonRepository> CachedPersonRepository<R> {
+    /// Cached in the process, see [`LocalCache`].
+    pub(crate) fn new(inner: R, ttl: Duration) -> Self {
+        Self::with_cache(inner, LocalCache::new(ttl))
+    }
+
+    pub(crate) fn with_cache(inner: R, cache: impl PersonCache + Send + Sync + 'static) -> Self {
+        CachedPersonRepository {
+            inner,
+            cache: Box::new(cache),
+        }
+    }
+
+    /// The nickname person `id` has before a change, from the cache or else the storage.
+    pub(crate) async fn current_nickname(&self, id: i64) -> Option<String> {
+        match self.cache.person(id).await {
+            Some(person) => Some(person.nickname),
+            None => self
+                .inner
+                .get_person(id)
+                .await
+                .ok()
+                .map(|person| person.nickname),
+        }
+    }
+}
+
+#[async_trait]
+impl<R: PersonRepository + Send + Sync> PersonRepository for CachedPersonRepository<R> {
+    async fn create_person(&self, person: CreatePersonPayload) -> Result<Person, AppError> {
+        if self.cache.nickname_taken(&person.nickname).await {
+            return Err(RepositoryError::UniqueViolation {
+                field: "apelido",
+                value: person.nickname,
+            }
+            .into());
+        }
+        let nickname = person.nickname.clone();
+        let result = self.inner.create_person(person).await;
+        match &result {
+            Ok(person) => self.cache.put(person).await,
+            // whoever took it did so elsewhere, e.g. through another replica
+            Err(AppError::Repo(RepositoryError::UniqueViolation { .. })) => {
+                self.cache.take_nickname(&nickname).await;
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    async fn create_person_idempotently(
+        &self,
+        key: String,
+        fingerprint: String,
+        person: CreatePersonPayload,
+    ) -> Result<IdempotentCreation, AppError> {
+        // the nickname is taken by the creation a retry replays, so there is no early check
+        let result = self
+            .inner
+            .create_person_idempotently(key, fingerprint, person)
+            .await;
+        if let Ok(IdempotentCreation::Created(person)) = &result {
+            self.cache.put(person).await;
+        }
+        result
+    }
+
+    async fn create_people_bulk(
+        &self,
+        people: Vec<CreatePersonPayload>,
+    ) -> Result<Vec<Option<Person>>, AppError> {
+        let created = self.inner.create_people_bulk(people).await?;
+        for person in created.iter().flatten() {
+            self.cache.put(person).await;
+        }
+        Ok(created)
+    }
+
+    async fn import_people(
+        &self,
+        batches: mpsc::Receiver<Vec<CreatePersonPayload>>,
+        finished: oneshot::Receiver<()>,
+        dry_run: bool,
+    ) -> Result<(u64, u64), AppError> {
+        self.inner.import_people(batches, finished, dry_run).await
+    }
+
+    async fn get_person(&self, id: i64) -> Result<Person, AppError> {
+        if let Some(person) = self.cache.person(id).await {
+            return Ok(person);
+        }
+        let person = self.inner.get_person(id).await?;
+        self.cache.put(&person).await;
+        Ok(person)
+    }
+
+    async fn get_person_including_deleted(&self, id: i64) -> Result<Person, AppError> {
+        // soft deleted people are not cached
+        self.inner.get_person_including_deleted(id).await
+    }
+
+    async fn get_and_track(&self, id: i64) -> Result<Person, AppError> {
+        let person = self.inner.get_and_track(id).await?;
+        self.cache.put(&person).await;
+        Ok(person)
+    }
+
+    async fn search_person(
+        &self,
+        term: String,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        self.inner.search_person(term, limit, offset).await
+    }
+
+    async fn count_search(&self, term: String) -> Result<i64, AppError> {
+        self.inner.count_search(term).await
+    }
+
+    async fn fuzzy_search(
+        &self,
+        term: String,
+        threshold: f32,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        self.inner
+            .fuzzy_search(term, threshold, limit, offset)
+            .await
+    }
+
+    async fn advanced_search(&self, search: AdvancedSearch) -> Result<Vec<Person>, AppError> {
+        self.inner.advanced_search(search).await
+    }
+
+    async fn count(&self) -> Result<i64, AppError> {
+        self.inner.count().await
+    }
+
+    async fn count_breakdown(&self) -> Result<CountBreakdown, AppError> {
+        self.inner.count_breakdown().await
+    }
+
+    async fn db_info(&self) -> Result<DbInfo, AppError> {
+        self.inner.db_info().await
+    }
+
+    async fn dashboard_stats(&self) -> Result<DashboardStats, AppError> {
+        self.inner.dashboard_stats().await
+    }
+
+    async fn estimated_count(&self) -> Result<i64, AppError> {
+        self.inner.estimated_count().await
+    }
+
+    async fn similar_by_stacks(&self, id: i64, limit: i64) -> Result<Vec<Person>, AppError> {
+        self.inner.similar_by_stacks(id, limit).await
+    }
+
+    async fn random_person(&self) -> Result<Person, AppError> {
+        self.inner.random_person().await
+    }
+
+    async fn get_by_nicknames(&self, nicknames: Vec<String>) -> Result<Vec<Person>, AppError> {
+        self.inner.get_by_nicknames(nicknames).await
+    }
+
+    fn stream_people(&self) -> BoxStream<'static, Result<Person, AppError>> {
+        self.inner.stream_people()
+    }
+
+    async fn list_people(
+        &self,
+        after_id: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        self.inner.list_people(after_id, limit).await
+    }
+
+    async fn people_in_id_range(
+        &self,
+        from_id: i64,
+        to_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        self.inner.people_in_id_range(from_id, to_id, limit).await
+    }
+
+    async fn timeline(
+        &self,
+        before: Option<(DateTime<Utc>, Option<i64>)>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        self.inner.timeline(before, limit).await
+    }
+
+    async fn count_by_birth_year(&self) -> Result<Vec<BirthYearCount>, AppError> {
+        self.inner.count_by_birth_year().await
+    }
+
+    async fn suspicious_people(&self, limit: i64) -> Result<Vec<Person>, AppError> {
+        self.inner.suspicious_people(limit).await
+    }
+
+    async fn incomplete_people(
+        &self,
+        after_id: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        self.inner.incomplete_people(after_id, limit).await
+    }
+
+    async fn trending_stacks(&self, days: i32, limit: i64) -> Result<Vec<StackCount>, AppError> {
+        self.inner.trending_stacks(days, limit).await
+    }
+
+    async fn related_stacks(&self, name: String, limit: i64) -> Result<Vec<StackCount>, AppError> {
+        self.inner.related_stacks(name, limit).await
+    }
+
+    async fn get_stack_meta(&self, name: String) -> Result<StackMeta, AppError> {
+        self.inner.get_stack_meta(name).await
+    }
+
+    async fn put_stack_meta(&self, meta: StackMeta) -> Result<StackMeta, AppError> {
+        self.inner.put_stack_meta(meta).await
+    }
+
+    async fn duplicate_candidates(
+        &self,
+        threshold: f32,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<DuplicateCandidate>, AppError> {
+        self.inner
+            .duplicate_candidates(threshold, limit, offset)
+            .await
+    }
+
+    async fn purge_deleted(&self, older_than_days: i32) -> Result<u64, AppError> {
+        let purged = self.inner.purge_deleted(older_than_days).await?;
+        // the purged nicknames aren't known
+        self.cache.clear().await;
+        Ok(purged)
+    }
+
+    async fn set_photo(&self, id: i64, photo: String) -> Result<Person, AppError> {
+        let person = self.inner.set_photo(id, photo).await?;
+        self.cache.put(&person).await;
+        Ok(person)
+    }
+
+    async fn remove_stacks(&self, id: i64, stacks: Vec<String>) -> Result<Person, AppError> {
+        let person = self.inner.remove_stacks(id, stacks).await?;
+        self.cache.put(&person).await;
+        Ok(person)
+    }
+
+    async fn patch_person(&self, id: i64, patch: PersonMergePatch) -> Result<Person, AppError> {
+        let old_nickname = match patch.nickname {
+            Some(_) => self.current_nickname(id).await,
+            None => None,
+        };
+        self.cache.forget_person(id).await;
+        let person = self.inner.patch_person(id, patch).await?;
+        if let Some(old_nickname) = old_nickname.filter(|old| *old != person.nickname) {
+            self.cache.free_nickname(&old_nickname).await;
+        }
+        self.cache.put(&person).await;
+        Ok(person)
+    }
+
+    async fn delete_person(&self, id: i64, version: Option<i64>) -> Result<(), AppError> {
+        let nickname = self.current_nickname(id).await;
+        self.inner.delete_person(id, version).await?;
+        self.cache.forget_person(id).await;
+        if let Some(nickname) = nickname {
+            self.cache.free_nickname(&nickname).await;
+        }
+        Ok(())
+    }
+
+    async fn reindex(&self) -> Result<(), AppError> {
+        self.inner.reindex().await
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        self.inner.ping().await
+    }
+
+    async fn missing_indexes(&self) -> Result<Vec<String>, AppError> {
+        self.inner.missing_indexes().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::pool_options;
+    use crate::domain::Stack;
+    use crate::test_support::config;
+
+    fn payload(nickname: &str) -> CreatePersonPayload {
+        serde_json::from_value(serde_json::json!({
+            "apelido": nickname,
+            "nome": "Ana Barros",
+            "nascimento": "1985-09-23",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn search_terms_are_trimmed_and_their_spaces_collapsed() {
+        assert_eq!(normalize_search_term("  rust   node "), "rust node");
+        assert_eq!(normalize_search_term("rust\tnode\n"), "rust node");
+        assert_eq!(normalize_search_term("rust"), "rust");
+        assert_eq!(normalize_search_term("   "), "");
+    }
+
+    #[test]
+    fn search_terms_split_the_negated_words_out() {
+        let terms = SearchTerms::parse("  rust -java  go -");
+        assert_eq!(terms.include, ["rust", "go", "-"]);
+        assert_eq!(terms.exclude, ["java"]);
+    }
+
+    #[test]
+    fn like_wildcards_and_the_escape_match_literally() {
+        assert_eq!(escape_like("50%", '\\'), "50\\%");
+        assert_eq!(escape_like("snake_case", '\\'), "snake\\_case");
+        assert_eq!(escape_like("c:\\dev", '\\'), "c:\\\\dev");
+        assert_eq!(escape_like("rust", '\\'), "rust");
+        // with another escape character the backslash is an ordinary one
+        assert_eq!(escape_like("c:\\dev_50%!", '!'), "c:\\dev!_50!%!!");
+
+        let mut builder = QueryBuilder::new("SELECT * FROM active_person");
+        push_search_filter(&mut builder, &SearchTerms::parse("50% -a_b"), '!');
+        assert_eq!(
+            builder.sql(),
+            "SELECT * FROM active_person WHERE TRUE AND search LIKE $1 ESCAPE '!' \
+             AND NOT search ILIKE $2 ESCAPE '!'"
+        );
+    }
+
+    #[tokio::test]
+    async fn patch_query_sets_only_the_fields_present() {
+        let config = config();
+        let pool = pool_options(&config)
+            .connect_lazy(&config.database_url)
+            .unwrap();
+        let repo = PostgresPersonRepository::new(pool, &config);
+        let patch = |json| serde_json::from_value::<PersonMergePatch>(json).unwrap();
+
+        let clear_stacks = repo.patch_query(1, &patch(serde_json::json!({ "stack": null })));
+        assert_eq!(
+            clear_stacks.sql(),
+            "UPDATE active_person SET version = version + 1, updated_at = now(), \
+             stacks = $1 WHERE id = $2 RETURNING *"
+        );
+
+        let rename = repo.patch_query(1, &patch(serde_json::json!({ "nome": "Ana" })));
+        assert_eq!(
+            rename.sql(),
+            "UPDATE active_person SET version = version + 1, updated_at = now(), \
+             name = $1 WHERE id = $2 RETURNING *"
+        );
+    }
+
+    /// A database error carrying only its SQLSTATE `code`.
+    #[derive(Debug)]
+    struct SqlState(&'static str);
+
+    impl std::fmt::Display for SqlState {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "error {}", self.0)
+        }
+    }
+
+    impl std::error::Error for SqlState {}
+
+    impl sqlx::error::DatabaseError for SqlState {
+        fn message(&self) -> &str {
+            self.0
+        }
+
+        fn code(&self) -> Option<std::borrow::Cow<'_, str>> {
+            Some(self.0.into())
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> sqlx::error::ErrorKind {
+            sqlx::error::ErrorKind::Other
+        }
+    }
+
+    #[tokio::test]
+    async fn deadlocks_are_retried_and_other_errors_are_not() {
+        let config = config();
+        let pool = pool_options(&config)
+            .connect_lazy(&config.database_url)
+            .unwrap();
+        let repo = PostgresPersonRepository::new(pool, &config);
+        let attempts = AtomicU64::new(0);
+        // fails with `codes` in turn, then succeeds
+        let failing = |codes: &'static [&'static str]| {
+            attempts.store(0, Ordering::Relaxed);
+            let attempts = &attempts;
+            move || async move {
+                let attempt = attempts.fetch_add(1, Ordering::Relaxed) as usize;
+                match codes.get(attempt) {
+                    Some(code) => Err(sqlx::Error::Database(Box::new(SqlState(code)))),
+                    None => Ok(attempt),
+                }
+            }
+        };
+
+        let result = repo.retry_on_deadlock(failing(&["40P01", "40P01"])).await;
+        assert_eq!(result.unwrap(), 2);
+
+        let result = repo.retry_on_deadlock(failing(&["23505", "40P01"])).await;
+        assert!(matches!(result, Err(sqlx::Error::Database(e)) if e.code().unwrap() == "23505"));
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+
+        let result = repo.retry_on_deadlock(failing(&["40P01"; 10])).await;
+        assert!(result.is_err_and(|e| is_deadlock(&e)));
+        assert_eq!(
+            attempts.load(Ordering::Relaxed),
+            u64::from(config.deadlock_retries) + 1
+        );
+    }
+
+    #[test]
+    fn only_connection_and_rollback_errors_are_transient() {
+        let database = |code| sqlx::Error::Database(Box::new(SqlState(code)));
+        for code in ["08006", "40001", "40P01", "53300", "57P01"] {
+            assert!(is_transient(&database(code)), "{code}");
+        }
+        for code in ["23505", "22021", "42703"] {
+            assert!(!is_transient(&database(code)), "{code}");
+        }
+        assert!(is_transient(&sqlx::Error::PoolTimedOut));
+        assert!(!is_transient(&sqlx::Error::RowNotFound));
+    }
+
+    #[tokio::test]
+    async fn fanned_out_queries_are_bounded_by_the_semaphore() {
+        let mut config = config();
+        config.db_fan_out_concurrency = 2.try_into().unwrap();
+        let pool = pool_options(&config)
+            .connect_lazy(&config.database_url)
+            .unwrap();
+        let repo = PostgresPersonRepository::new(pool, &config);
+        let (running, most) = (AtomicU64::new(0), AtomicU64::new(0));
+        // stands in for a query, counting how many run at once
+        let query = || async {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            running.fetch_sub(1, Ordering::SeqCst);
+        };
+
+        futures::future::join_all((0..6).map(|_| repo.fanned_out(query()))).await;
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        assert_eq!(repo.fan_out.available_permits(), 2);
+    }
+
+    #[tokio::test]
+    async fn in_memory_nicknames_stay_unique_under_concurrent_creates() {
+        let repo = Arc::new(InMemoryPersonRepository::new(&config()));
+        let creates: Vec<_> = (0..8)
+            .map(|_| {
+                let repo = repo.clone();
+                tokio::spawn(async move { repo.create_person(payload("ana")).await })
+            })
+            .collect();
+        let mut created = 0;
+        for create in creates {
+            match create.await.unwrap() {
+                Ok(_) => created += 1,
+                Err(err) => assert!(matches!(
+                    err,
+                    AppError::Repo(RepositoryError::UniqueViolation {
+                        field: "apelido",
+                        ..
+                    })
+                )),
+            }
+        }
+        assert_eq!(created, 1);
+
+        let bia = repo.create_person(payload("bia")).await.unwrap();
+        let rename = serde_json::from_value(serde_json::json!({ "apelido": "ana" })).unwrap();
+        assert!(matches!(
+            repo.patch_person(bia.id, rename).await,
+            Err(AppError::Repo(RepositoryError::UniqueViolation { .. }))
+        ));
+        // renaming to its own nickname isn't a clash
+        let keep = serde_json::from_value(serde_json::json!({ "apelido": "bia" })).unwrap();
+        assert!(repo.patch_person(bia.id, keep).await.is_ok());
+
+        repo.delete_person(bia.id, None).await.unwrap();
+        assert!(repo.create_person(payload("bia")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn in_memory_search_pages_the_matches_in_id_order() {
+        let repo = InMemoryPersonRepository::new(&config());
+        let mut ids = Vec::new();
+        for nickname in ["ana", "bia", "cris", "dani"] {
+            ids.push(repo.create_person(payload(nickname)).await.unwrap().id);
+        }
+        let mut other = payload("eva");
+        other.name = "Eva Lima".to_owned();
+        repo.create_person(other).await.unwrap();
+
+        let ids_of = |people: Vec<Person>| people.into_iter().map(|p| p.id).collect::<Vec<_>>();
+        let first = repo.search_person("Barros".to_owned(), 3, 0).await.unwrap();
+        let rest = repo.search_person("Barros".to_owned(), 3, 3).await.unwrap();
+        assert_eq!(ids_of(first), ids[..3]);
+        assert_eq!(ids_of(rest), ids[3..]);
+        assert_eq!(repo.count_search("Barros".to_owned()).await.unwrap(), 4);
+        assert_eq!(repo.count_search("Lima".to_owned()).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn purge_removes_only_the_people_deleted_long_enough_ago() {
+        let repo = InMemoryPersonRepository::new(&config());
+        let active = repo.create_person(payload("ana")).await.unwrap();
+        for (nickname, days_ago) in [("bia", 0), ("cris", 29), ("dani", 31), ("eva", 90)] {
+            let person = repo.create_person(payload(nickname)).await.unwrap();
+            repo.delete_person(person.id, None).await.unwrap();
+            repo.deleted_mut().get_mut(&person.id).unwrap().deleted_at =
+                Some(Utc::now() - chrono::Duration::days(days_ago));
+        }
+
+        assert_eq!(repo.purge_deleted(30).await.unwrap(), 2);
+        let mut kept: Vec<String> = repo
+            .deleted()
+            .values()
+            .map(|p| p.nickname.clone())
+            .collect();
+        kept.sort();
+        assert_eq!(kept, ["bia", "cris"]);
+        assert!(repo.get_person(active.id).await.is_ok());
+
+        assert_eq!(repo.purge_deleted(30).await.unwrap(), 0);
+        assert_eq!(repo.purge_deleted(0).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn instrumentation_forwards_results_and_counts_every_call() {
+        let repo = InstrumentedRepository::new(InMemoryPersonRepository::new(&config()));
+        let ana = repo.create_person(payload("ana")).await.unwrap();
+        assert_eq!(repo.get_person(ana.id).await.unwrap().nickname, "ana");
+        assert!(matches!(
+            repo.create_person(payload("ana")).await,
+            Err(AppError::Repo(RepositoryError::UniqueViolation { .. }))
+        ));
+        assert!(matches!(
+            repo.get_person(ana.id + 1).await,
+            Err(AppError::Repo(RepositoryError::NotFound { .. }))
+        ));
+        assert_eq!(repo.count().await.unwrap(), 1);
+
+        assert_eq!(repo.calls.load(Ordering::Relaxed), 5);
+        assert_eq!(repo.errors.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn cache_forgets_only_the_nickname_freed() {
+        let repo = CachedPersonRepository::new(
+            InMemoryPersonRepository::new(&config()),
+            Duration::from_secs(60),
+        );
+        let ana = repo.create_person(payload("ana")).await.unwrap();
+        let bia = repo.create_person(payload("bia")).await.unwrap();
+        repo.create_person(payload("cris")).await.unwrap();
+
+        repo.delete_person(ana.id, None).await.unwrap();
+        assert!(!repo.cache.nickname_taken("ana").await);
+        assert!(repo.cache.nickname_taken("bia").await);
+        assert!(repo.cache.nickname_taken("cris").await);
+
+        let patch = serde_json::from_value(serde_json::json!({ "apelido": "bea" })).unwrap();
+        repo.patch_person(bia.id, patch).await.unwrap();
+        assert!(!repo.cache.nickname_taken("bia").await);
+        assert!(repo.cache.nickname_taken("bea").await);
+        assert!(repo.cache.nickname_taken("cris").await);
+    }
+
+    #[test]
+    fn cached_people_read_back_as_they_were() {
+        let person = Person {
+            id: 7,
+            nickname: "ana".to_owned(),
+            name: "Ana Barros".to_owned(),
+            dob: NaiveDate::from_ymd_opt(1985, 9, 23).unwrap(),
+            stacks: Some(vec!["rust".to_owned()]),
+            stacks_detail: Some(sqlx::types::Json(vec![Stack {
+                name: "rust".to_owned(),
+                level: Some(3),
+            }])),
+            created_at: Some(Utc::now()),
+            version: 2,
+            ..Person::default()
+        };
+        let json = serde_json::to_vec(&StoredPerson::from(&person)).unwrap();
+        let read: Person = serde_json::from_slice(&json).unwrap();
+        assert_eq!(format!("{read:?}"), format!("{person:?}"));
+    }
+
+    #[tokio::test]
+    async fn stacks_detail_is_kept_only_with_jsonb_storage() {
+        let detailed = || {
+            serde_json::from_value::<CreatePersonPayload>(serde_json::json!({
+                "apelido": "ana",
+                "nome": "Ana Barros",
+                "nascimento": "1985-09-23",
+                "stack": ["go", { "name": "rust", "level": 3 }],
+            }))
+            .unwrap()
+        };
+        let mut config = config();
+
+        let repo = InMemoryPersonRepository::new(&config);
+        let person = repo.create_person(detailed()).await.unwrap();
+        assert_eq!(person.stacks.unwrap(), ["go", "rust"]);
+        assert!(person.stacks_detail.is_none());
+
+        config.stacks_storage = StacksStorage::Jsonb;
+        let repo = InMemoryPersonRepository::new(&config);
+        let person = repo.create_person(detailed()).await.unwrap();
+        let person = repo.get_person(person.id).await.unwrap();
+        assert_eq!(person.stacks.unwrap(), ["go", "rust"]);
+        assert_eq!(person.stacks_detail.unwrap().0[1].level, Some(3));
+    }
+
+    async fn postgres(config: &Config) -> PostgresPersonRepository {
+        let pool = pool_options(config)
+            .connect(&config.database_url)
+            .await
+            .unwrap();
+        PostgresPersonRepository::new(pool, config)
+    }
+
+    /// A nickname no other run has used, the database being shared.
+    fn unique(prefix: &str) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        format!("{prefix}{nanos}")
+    }
+
+    async fn forget(repo: &PostgresPersonRepository, ids: &[i64]) {
+        sqlx::query("DELETE FROM person WHERE id = ANY($1)")
+            .bind(ids)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn count_breakdown_sums_to_the_total() {
+        let repo = postgres(&config()).await;
+        let mut without = payload(&unique("cb"));
+        without.stacks = Some(Vec::new());
+        let without = repo.create_person(without).await.unwrap();
+
+        let breakdown = repo.count_breakdown().await;
+        let count = repo.count().await;
+        forget(&repo, &[without.id]).await;
+        let breakdown = breakdown.unwrap();
+        assert_eq!(
+            breakdown.with_stacks + breakdown.without_stacks,
+            breakdown.total
+        );
+        assert!(breakdown.without_stacks >= 1);
+        assert_eq!(breakdown.total, count.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn batches_rejected_for_good_dead_letter_only_their_bad_people() {
+        let repo = postgres(&config()).await;
+        let ids: Vec<i64> =
+            sqlx::query_scalar("SELECT nextval('person_id_seq') FROM generate_series(1, 2)")
+                .fetch_all(&repo.pool)
+                .await
+                .unwrap();
+        let person = |id, nickname: String| Person {
+            id,
+            nickname,
+            name: "Ana Barros".to_owned(),
+            created_at: Some(Utc::now()),
+            updated_at: Some(Utc::now()),
+            ..Person::default()
+        };
+        // Postgres takes no NUL in text, however many times it is sent
+        let batch = vec![person(ids[0], unique("dl")), person(ids[1], unique("dl\0"))];
+
+        let outcome = tokio::time::timeout(
+            Duration::from_secs(5),
+            write_batch(&repo.pool, batch, StacksStorage::Array),
+        )
+        .await;
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM person WHERE id = ANY($1)")
+            .bind(&ids)
+            .fetch_one(&repo.pool)
+            .await
+            .unwrap();
+        forget(&repo, &ids).await;
+        let expected = WrittenBatch {
+            written: 1,
+            dead_lettered: 1,
+        };
+        assert_eq!(outcome.expect("not retried"), expected);
+        assert_eq!(stored, 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn write_behind_refuses_stored_nicknames() {
+        let config = config();
+        let repo = postgres(&config).await;
+        let stored = repo.create_person(payload(&unique("wb"))).await.unwrap();
+        let (write_behind, _writer) =
+            WriteBehind::spawn(repo.pool.clone(), config.stacks_storage, Duration::ZERO);
+
+        let err = write_behind
+            .create(&repo.pool, payload(&stored.nickname), config.stacks_storage)
+            .await;
+        forget(&repo, &[stored.id]).await;
+        assert!(matches!(
+            err,
+            Err(AppError::Repo(RepositoryError::UniqueViolation { .. }))
+        ));
+        assert!(write_behind.pending().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn soft_deleted_nicknames_are_free_for_new_people() {
+        let repo = postgres(&config()).await;
+        let nickname = unique("sd");
+        let deleted = repo.create_person(payload(&nickname)).await.unwrap();
+        repo.delete_person(deleted.id, None).await.unwrap();
+
+        let created = repo.create_person(payload(&nickname)).await;
+        let bulk = repo
+            .create_people_bulk(vec![payload(&nickname), payload(&unique("sd"))])
+            .await;
+        let mut ids = vec![deleted.id];
+        ids.extend(created.as_ref().map(|p| p.id));
+        ids.extend(bulk.iter().flatten().flatten().map(|p| p.id));
+        forget(&repo, &ids).await;
+        assert_eq!(created.unwrap().nickname, nickname);
+        // the active one still holds it
+        let bulk = bulk.unwrap();
+        assert!(bulk[0].is_none());
+        assert!(bulk[1].is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn search_terms_with_wildcards_match_literally() {
+        let repo = postgres(&config()).await;
+        let tag = unique("lk");
+        let mut literal = payload(&unique("lk"));
+        literal.name = format!("Ana {tag}%_\\x");
+        let literal = repo.create_person(literal).await.unwrap();
+        let mut lookalike = payload(&unique("lk"));
+        lookalike.name = format!("Ana {tag}ab\\x");
+        let lookalike = repo.create_person(lookalike).await.unwrap();
+
+        let wildcards = repo.search_person(format!("{tag}%_\\x"), 10, 0).await;
+        let backslash = repo.search_person(format!("{tag}ab\\x"), 10, 0).await;
+        forget(&repo, &[literal.id, lookalike.id]).await;
+        let ids = |found: Vec<Person>| found.into_iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids(wildcards.unwrap()), [literal.id]);
+        assert_eq!(ids(backslash.unwrap()), [lookalike.id]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn db_info_reports_the_latest_migration() {
+        let repo = postgres(&config()).await;
+        let latest: i64 = sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations")
+            .fetch_one(&repo.pool)
+            .await
+            .unwrap();
+
+        let info = repo.db_info().await.unwrap();
+        assert_eq!(info.migration_version, Some(latest));
+        assert!(!info.server_version.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn dry_run_imports_are_rolled_back() {
+        let repo = postgres(&config()).await;
+        let taken = repo.create_person(payload(&unique("dr"))).await.unwrap();
+        let nickname = unique("dr");
+        let (tx, rx) = mpsc::channel(1);
+        tx.send(vec![payload(&taken.nickname), payload(&nickname)])
+            .await
+            .unwrap();
+        drop(tx);
+        let (done, finished) = oneshot::channel();
+        done.send(()).unwrap();
+
+        let report = repo.import_people(rx, finished, true).await;
+        let found = repo.search_person(nickname, 10, 0).await;
+        forget(&repo, &[taken.id]).await;
+        assert_eq!(report.unwrap(), (2, 1));
+        assert_eq!(found.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn abandoned_imports_are_rolled_back() {
+        let repo = postgres(&config()).await;
+        let nickname = unique("ab");
+        let (tx, rx) = mpsc::channel(1);
+        tx.send(vec![payload(&nickname)]).await.unwrap();
+        // both closed without `finished` being sent, as when the import is dropped
+        drop(tx);
+        let (done, finished) = oneshot::channel::<()>();
+        drop(done);
+
+        let report = repo.import_people(rx, finished, false).await;
+        let found = repo.search_person(nickname, 10, 0).await;
+        assert!(report.is_err());
+        assert_eq!(found.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn tracked_reads_increment_the_view_count() {
+        let repo = postgres(&config()).await;
+        let person = repo.create_person(payload(&unique("vc"))).await.unwrap();
+
+        let plain = repo.get_person(person.id).await;
+        let first = repo.get_and_track(person.id).await;
+        let second = repo.get_and_track(person.id).await;
+        forget(&repo, &[person.id]).await;
+        assert_eq!(plain.unwrap().view_count, 0);
+        assert_eq!(first.unwrap().view_count, 1);
+        assert_eq!(second.unwrap().view_count, 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn deletes_of_a_stale_version_conflict() {
+        let repo = postgres(&config()).await;
+        let person = repo.create_person(payload(&unique("dv"))).await.unwrap();
+
+        let stale = repo
+            .delete_person(person.id, Some(person.version + 1))
+            .await;
+        let kept = repo.get_person(person.id).await;
+        let current = repo.delete_person(person.id, Some(person.version)).await;
+        let gone = repo.get_person(person.id).await;
+        forget(&repo, &[person.id]).await;
+        assert!(
+            matches!(
+                stale,
+                Err(AppError::Repo(RepositoryError::StaleVersion { .. }))
+            ),
+            "{stale:?}"
+        );
+        assert!(kept.is_ok());
+        current.unwrap();
+        assert!(gone.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn search_filter_is_served_by_the_trigram_index() {
+        let repo = postgres(&config()).await;
+        let mut tx = repo.pool.begin().await.unwrap();
+        // the test table may be small enough for a sequential scan to be the cheapest
+        sqlx::query("SET LOCAL enable_seqscan = off")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        let mut builder = QueryBuilder::new("EXPLAIN SELECT COUNT(*) FROM active_person");
+        // a term few rows match, the shared test table being full of rust people that the
+        // partial nickname index would find as cheaply
+        push_search_filter(&mut builder, &SearchTerms::parse("rustacean -java"), '\\');
+
+        let plan: Vec<String> = builder
+            .build_query_scalar()
+            .fetch_all(&mut *tx)
+            .await
+            .unwrap();
+        let plan = plan.join("\n");
+        assert!(plan.contains("person_search_trgm_idx"), "{plan}");
+        assert!(!plan.contains("Seq Scan"), "{plan}");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn stack_counts_join_their_metadata() {
+        let repo = postgres(&config()).await;
+        let stack = unique("st");
+        let mut person = payload(&unique("sm"));
+        person.stacks = Some(vec![Stack {
+            name: stack.clone(),
+            level: None,
+        }]);
+        let person = repo.create_person(person).await.unwrap();
+        let meta = StackMeta {
+            name: stack.clone(),
+            display_name: Some("Curated".to_owned()),
+            icon: None,
+        };
+
+        let put = repo.put_stack_meta(meta).await;
+        let got = repo.get_stack_meta(stack.clone()).await;
+        let trending = repo.trending_stacks(1, 10_000).await;
+        forget(&repo, &[person.id]).await;
+        sqlx::query("DELETE FROM stacks_meta WHERE name = $1")
+            .bind(&stack)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        put.unwrap();
+        assert_eq!(got.unwrap().display_name.as_deref(), Some("Curated"));
+        let trending = trending.unwrap();
+        let counted = trending.iter().find(|s| s.stack == stack).unwrap();
+        assert_eq!(counted.count, 1);
+        assert_eq!(counted.display_name.as_deref(), Some("Curated"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn schemas_missing_an_index_are_reported() {
+        let repo = postgres(&config()).await;
+        assert_eq!(repo.missing_indexes().await.unwrap(), Vec::<String>::new());
+
+        // DDL is transactional, the index is back once the transaction rolls back
+        let mut tx = repo.pool.begin().await.unwrap();
+        sqlx::query("DROP INDEX person_search_trgm_idx")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        let missing = missing_indexes_in(&mut *tx).await;
+        tx.rollback().await.unwrap();
+        assert_eq!(missing.unwrap(), ["person_search_trgm_idx"]);
+        assert_eq!(repo.missing_indexes().await.unwrap(), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn removing_stacks_keeps_the_order_of_the_others() {
+        let repo = postgres(&config()).await;
+        let mut person = payload(&unique("rs"));
+        person.stacks = Some(
+            ["rust", "go", "rust", "c"]
+                .map(|name| Stack {
+                    name: name.to_owned(),
+                    level: None,
+                })
+                .into(),
+        );
+        let person = repo.create_person(person).await.unwrap();
+
+        let removed = repo
+            .remove_stacks(person.id, vec!["rust".to_owned(), "cobol".to_owned()])
+            .await;
+        let missing = repo.remove_stacks(i64::MAX, vec!["go".to_owned()]).await;
+        forget(&repo, &[person.id]).await;
+        assert_eq!(removed.unwrap().stacks.unwrap(), ["go", "c"]);
+        assert!(matches!(
+            missing,
+            Err(AppError::Repo(RepositoryError::NotFound { .. }))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn advanced_search_matches_exact_stacks_and_nickname_prefixes() {
+        let repo = postgres(&config()).await;
+        let prefix = unique("am");
+        let mut ids = Vec::new();
+        for (suffix, stack) in [("a", "Rust"), ("b", "rustacean")] {
+            let mut person = payload(&format!("{prefix}{suffix}"));
+            person.stacks = Some(vec![Stack {
+                name: stack.to_owned(),
+                level: None,
+            }]);
+            ids.push(repo.create_person(person).await.unwrap().id);
+        }
+        let search = |stack_mode| AdvancedSearch {
+            nickname: Some(FieldMatch {
+                mode: MatchMode::Prefix,
+                value: prefix.to_uppercase(),
+            }),
+            stack: Some(FieldMatch {
+                mode: stack_mode,
+                value: "rust".to_owned(),
+            }),
+            limit: 10,
+            ..AdvancedSearch::default()
+        };
+
+        let exact = repo.advanced_search(search(MatchMode::Exact)).await;
+        let prefixed = repo.advanced_search(search(MatchMode::Prefix)).await;
+        forget(&repo, &ids).await;
+        let found = |people: Vec<Person>| people.into_iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(found(exact.unwrap()), [ids[0]]);
+        assert_eq!(found(prefixed.unwrap()), ids);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn related_stacks_count_each_person_once() {
+        let repo = postgres(&config()).await;
+        let tag = unique("rl");
+        let stack = |suffix: &str| Stack {
+            name: format!("{tag}{suffix}"),
+            level: None,
+        };
+        let mut ids = Vec::new();
+        for stacks in [
+            &["a", "b", "c"][..],
+            &["a", "b"],
+            &["a", "c", "c"],
+            &["b", "d"],
+        ] {
+            let mut person = payload(&unique("rl"));
+            person.stacks = Some(stacks.iter().map(|s| stack(s)).collect());
+            ids.push(repo.create_person(person).await.unwrap().id);
+        }
+
+        let related = repo.related_stacks(format!("{tag}a"), 10).await;
+        forget(&repo, &ids).await;
+        let related: Vec<(String, i64)> = related
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.stack, s.count))
+            .collect();
+        assert_eq!(related, [(format!("{tag}b"), 2), (format!("{tag}c"), 2)]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn fuzzy_search_tolerates_typos() {
+        let config = config();
+        let repo = postgres(&config).await;
+        let mut person = payload(&unique("fz"));
+        person.name = "Joaquim Krzyzanowski".to_owned();
+        let person = repo.create_person(person).await.unwrap();
+
+        let fuzzy = repo
+            .fuzzy_search("Krzyzanowsky".to_owned(), config.fuzzy_threshold, 50, 0)
+            .await;
+        let exact = repo.search_person("Krzyzanowsky".to_owned(), 50, 0).await;
+        forget(&repo, &[person.id]).await;
+        let fuzzy = fuzzy.unwrap();
+        assert!(fuzzy.iter().any(|p| p.id == person.id), "{fuzzy:?}");
+        assert!(exact.unwrap().iter().all(|p| p.id != person.id));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn fuzzy_nickname_hits_outrank_stack_hits() {
+        let mut config = config();
+        let term = unique("fw");
+        let mut stack_hit = payload(&unique("fs"));
+        stack_hit.stacks = Some(vec![Stack {
+            name: term.clone(),
+            level: None,
+        }]);
+        let repo = postgres(&config).await;
+        // created first, so only the weights can rank it below the other one
+        let stack_hit = repo.create_person(stack_hit).await.unwrap();
+        let nickname_hit = repo.create_person(payload(&term)).await.unwrap();
+
+        let weighted = repo
+            .fuzzy_search(term.clone(), config.fuzzy_threshold, 10, 0)
+            .await;
+        config.fuzzy_weights.nickname = 0.1;
+        config.fuzzy_weights.stack = 1.0;
+        let reweighted = postgres(&config)
+            .await
+            .fuzzy_search(term, config.fuzzy_threshold, 10, 0)
+            .await;
+        forget(&repo, &[stack_hit.id, nickname_hit.id]).await;
+        let ids = |found: Vec<Person>| found.into_iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids(weighted.unwrap()), [nickname_hit.id, stack_hit.id]);
+        assert_eq!(ids(reweighted.unwrap()), [stack_hit.id, nickname_hit.id]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn tagged_queries_succeed_under_their_route_name() {
+        let mut config = config();
+        config.tag_queries = true;
+        let repo = postgres(&config).await;
+
+        let application_name = repo
+            .tagged("search", |conn| {
+                Box::pin(
+                    sqlx::query_scalar::<_, String>("SELECT current_setting('application_name')")
+                        .fetch_one(conn),
+                )
+            })
+            .await
+            .unwrap();
+        assert_eq!(application_name, "person_api:search");
+
+        let nickname = unique("tg");
+        let person = repo.create_person(payload(&nickname)).await.unwrap();
+        let got = repo.get_person(person.id).await;
+        let found = repo.search_person(nickname.clone(), 10, 0).await;
+        let counted = repo.count_search(nickname).await;
+        forget(&repo, &[person.id]).await;
+        assert_eq!(got.unwrap().id, person.id);
+        assert_eq!(found.unwrap()[0].id, person.id);
+        assert_eq!(counted.unwrap(), 1);
+        // the setting was local to the tagging transaction
+        let reset = sqlx::query_scalar::<_, String>("SELECT current_setting('application_name')")
+            .fetch_one(&repo.pool)
+            .await
+            .unwrap();
+        assert_ne!(reset, "person_api:search");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn dropped_searches_cancel_their_statement() {
+        let mut config = config();
+        config.cancel_abandoned_searches = true;
+        let pool = pool_options(&config)
+            .connect(&config.database_url)
+            .await
+            .unwrap();
+        let repo = PostgresPersonRepository::new(pool.clone(), &config);
+        let running = || {
+            sqlx::query_scalar::<_, i64>(
+                "SELECT COUNT(*) FROM pg_stat_activity \
+                 WHERE state = 'active' AND query LIKE 'SELECT pg_sleep(30) -- abandoned%'",
+            )
+            .fetch_one(&pool)
+        };
+
+        let mut search = Box::pin(repo.cancellable("search", |conn| {
+            Box::pin(async move {
+                sqlx::query("SELECT pg_sleep(30) -- abandoned search")
+                    .execute(conn)
+                    .await
+                    .map(drop)
+            })
+        }));
+        let waited = tokio::time::timeout(Duration::from_millis(500), &mut search).await;
+        assert!(waited.is_err());
+        assert_eq!(running().await.unwrap(), 1);
+        // the client goes away while the statement runs
+        drop(search);
+
+        let mut cancelled = false;
+        for _ in 0..50 {
+            if running().await.unwrap() == 0 {
+                cancelled = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(cancelled, "the abandoned statement is still running");
+        repo.ping().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn implausible_birth_dates_are_excluded_from_the_age_stats() {
+        let repo = postgres(&config()).await;
+        let person = repo.create_person(payload(&unique("ag"))).await.unwrap();
+        let before = repo.dashboard_stats().await;
+        // legacy rows predate the validation, only raw SQL can write them now
+        sqlx::query("UPDATE person SET dob = '1800-01-01' WHERE id = $1")
+            .bind(person.id)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+
+        let after = repo.dashboard_stats().await;
+        forget(&repo, &[person.id]).await;
+        let (before, after) = (before.unwrap().age, after.unwrap().age);
+        assert_eq!(after.excluded, before.excluded + 1);
+        assert!(after.max.unwrap() < Utc::now().year() - MIN_DOB_YEAR + 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL"]
+    async fn idempotency_keys_of_abandoned_attempts_can_be_retried() {
+        let repo = Arc::new(postgres(&config()).await);
+        let (key, nickname) = (unique("ik"), unique("ik"));
+        // holds the nickname uncommitted, so the first attempt blocks after claiming the key
+        let mut blocker = repo.pool.begin().await.unwrap();
+        repo.insert_person(payload(&nickname))
+            .fetch_one(&mut *blocker)
+            .await
+            .unwrap();
+        let first = tokio::spawn({
+            let (repo, key, nickname) = (repo.clone(), key.clone(), nickname.clone());
+            async move {
+                repo.create_person_idempotently(key, "f".to_owned(), payload(&nickname))
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        first.abort();
+        let _ = first.await;
+        blocker.rollback().await.unwrap();
+
+        let retry = tokio::time::timeout(
+            Duration::from_secs(5),
+            repo.create_person_idempotently(key.clone(), "f".to_owned(), payload(&nickname)),
+        )
+        .await;
+        let replay = repo
+            .create_person_idempotently(key.clone(), "f".to_owned(), payload(&nickname))
+            .await;
+        let created = match retry {
+            Ok(Ok(IdempotentCreation::Created(person))) => Some(person),
+            _ => None,
+        };
+        if let Some(person) = &created {
+            forget(&repo, &[person.id]).await;
+        }
+        sqlx::query("DELETE FROM idempotency_key WHERE key = $1")
+            .bind(&key)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        let created = created.expect("the retry creates the person");
+        assert!(matches!(
+            replay.unwrap(),
+            IdempotentCreation::Existing(IdempotencyRecord { person_id, .. }) if person_id == created.id
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the Postgres of DATABASE_URL and the Redis of REDIS_URL"]
+    async fn replicas_sharing_redis_see_each_others_changes() {
+        let url =
+            std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_owned());
+        let ttl = Duration::from_secs(60);
+        let replica = || async {
+            CachedPersonRepository::with_cache(
+                postgres(&config()).await,
+                RedisCache::connect(&url, ttl).await.unwrap(),
+            )
+        };
+        let (a, b) = (replica().await, replica().await);
+        let nickname = unique("rc");
+        let person = a.create_person(payload(&nickname)).await.unwrap();
+
+        // created through a, b knows the nickname and the person without asking Postgres
+        let taken = b.cache.nickname_taken(&nickname).await;
+        let cached = b.cache.person(person.id).await;
+        let patch = serde_json::from_value(serde_json::json!({ "nome": "Ana Lima" })).unwrap();
+        let patched = b.patch_person(person.id, patch).await;
+        let read = a.get_person(person.id).await;
+        a.delete_person(person.id, None).await.unwrap();
+        let gone = b.get_person(person.id).await;
+        let freed = !b.cache.nickname_taken(&nickname).await;
+        forget(&a.inner, &[person.id]).await;
+
+        assert!(taken);
+        assert_eq!(cached.unwrap().nickname, nickname);
+        assert_eq!(patched.unwrap().name, "Ana Lima");
+        assert_eq!(read.unwrap().name, "Ana Lima");
+        assert!(matches!(
+            gone,
+            Err(AppError::Repo(RepositoryError::NotFound { .. }))
+        ));
+        assert!(freed);
+    }
+}
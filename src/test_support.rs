@@ -0,0 +1,295 @@
+//! Helpers shared by the unit tests.
+
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+use axum::async_trait;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::Request;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use tokio::sync::{mpsc, oneshot};
+use tower::ServiceExt;
+
+use crate::app::{app, AppState};
+use crate::config::Config;
+use crate::domain::{
+    AdvancedSearch, BirthYearCount, CountBreakdown, CreatePersonPayload, DashboardStats, DbInfo,
+    DuplicateCandidate, IdempotentCreation, Person, PersonMergePatch, StackCount, StackMeta,
+};
+use crate::error::AppError;
+use crate::repository::PersonRepository;
+
+/// Serializes the tests reading the process-wide environment.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// Runs `f` with `vars` set, e.g. `with_env(&[], Config::from_env)` for the default config.
+pub(crate) fn with_env<T>(vars: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+    struct Unset<'a>(&'a [(&'a str, &'a str)]);
+    impl Drop for Unset<'_> {
+        fn drop(&mut self) {
+            for (key, _) in self.0 {
+                std::env::remove_var(key);
+            }
+        }
+    }
+
+    let _lock = ENV_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    for (key, value) in vars {
+        std::env::set_var(key, value);
+    }
+    let _unset = Unset(vars);
+    f()
+}
+
+/// The config of an unconfigured environment, to adjust field by field.
+pub(crate) fn config() -> Config {
+    with_env(&[], Config::from_env).expect("default config is valid")
+}
+
+/// Serves `req` with [`app`] as if it came from `127.0.0.1`.
+pub(crate) async fn send(state: &AppState, mut req: Request<Body>) -> Response {
+    req.extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 54321))));
+    app(state.clone()).oneshot(req).await.unwrap()
+}
+
+pub(crate) async fn body_bytes(res: Response) -> Vec<u8> {
+    hyper::body::to_bytes(res.into_body())
+        .await
+        .unwrap()
+        .to_vec()
+}
+
+pub(crate) async fn body_json(res: Response) -> serde_json::Value {
+    serde_json::from_slice(&body_bytes(res).await).unwrap()
+}
+
+pub(crate) fn get(uri: &str) -> Request<Body> {
+    Request::get(uri).body(Body::empty()).unwrap()
+}
+
+pub(crate) fn post_json(uri: &str, body: serde_json::Value) -> Request<Body> {
+    Request::post(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// A repository failing every call with the error `self.0` makes, to check how
+/// the errors reach the clients.
+pub(crate) struct FailingRepository(pub(crate) fn() -> AppError);
+
+#[async_trait]
+impl PersonRepository for FailingRepository {
+    async fn create_person(&self, _person: CreatePersonPayload) -> Result<Person, AppError> {
+        Err((self.0)())
+    }
+
+    async fn create_person_idempotently(
+        &self,
+        _key: String,
+        _fingerprint: String,
+        _person: CreatePersonPayload,
+    ) -> Result<IdempotentCreation, AppError> {
+        Err((self.0)())
+    }
+
+    async fn create_people_bulk(
+        &self,
+        _people: Vec<CreatePersonPayload>,
+    ) -> Result<Vec<Option<Person>>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn import_people(
+        &self,
+        _batches: mpsc::Receiver<Vec<CreatePersonPayload>>,
+        _finished: oneshot::Receiver<()>,
+        _dry_run: bool,
+    ) -> Result<(u64, u64), AppError> {
+        Err((self.0)())
+    }
+
+    async fn get_person(&self, _id: i64) -> Result<Person, AppError> {
+        Err((self.0)())
+    }
+
+    async fn get_person_including_deleted(&self, _id: i64) -> Result<Person, AppError> {
+        Err((self.0)())
+    }
+
+    async fn get_and_track(&self, _id: i64) -> Result<Person, AppError> {
+        Err((self.0)())
+    }
+
+    async fn search_person(
+        &self,
+        _term: String,
+        _limit: i64,
+        _offset: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn count_search(&self, _term: String) -> Result<i64, AppError> {
+        Err((self.0)())
+    }
+
+    async fn fuzzy_search(
+        &self,
+        _term: String,
+        _threshold: f32,
+        _limit: i64,
+        _offset: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn advanced_search(&self, _search: AdvancedSearch) -> Result<Vec<Person>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn count(&self) -> Result<i64, AppError> {
+        Err((self.0)())
+    }
+
+    async fn count_breakdown(&self) -> Result<CountBreakdown, AppError> {
+        Err((self.0)())
+    }
+
+    async fn db_info(&self) -> Result<DbInfo, AppError> {
+        Err((self.0)())
+    }
+
+    async fn dashboard_stats(&self) -> Result<DashboardStats, AppError> {
+        Err((self.0)())
+    }
+
+    async fn estimated_count(&self) -> Result<i64, AppError> {
+        Err((self.0)())
+    }
+
+    async fn similar_by_stacks(&self, _id: i64, _limit: i64) -> Result<Vec<Person>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn random_person(&self) -> Result<Person, AppError> {
+        Err((self.0)())
+    }
+
+    async fn get_by_nicknames(&self, _nicknames: Vec<String>) -> Result<Vec<Person>, AppError> {
+        Err((self.0)())
+    }
+
+    fn stream_people(&self) -> BoxStream<'static, Result<Person, AppError>> {
+        futures::stream::once(futures::future::ready(Err((self.0)()))).boxed()
+    }
+
+    async fn list_people(
+        &self,
+        _after_id: Option<i64>,
+        _limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn people_in_id_range(
+        &self,
+        _from_id: i64,
+        _to_id: i64,
+        _limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn timeline(
+        &self,
+        _before: Option<(DateTime<Utc>, Option<i64>)>,
+        _limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn count_by_birth_year(&self) -> Result<Vec<BirthYearCount>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn suspicious_people(&self, _limit: i64) -> Result<Vec<Person>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn duplicate_candidates(
+        &self,
+        _threshold: f32,
+        _limit: i64,
+        _offset: i64,
+    ) -> Result<Vec<DuplicateCandidate>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn incomplete_people(
+        &self,
+        _after_id: Option<i64>,
+        _limit: i64,
+    ) -> Result<Vec<Person>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn trending_stacks(&self, _days: i32, _limit: i64) -> Result<Vec<StackCount>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn related_stacks(
+        &self,
+        _name: String,
+        _limit: i64,
+    ) -> Result<Vec<StackCount>, AppError> {
+        Err((self.0)())
+    }
+
+    async fn get_stack_meta(&self, _name: String) -> Result<StackMeta, AppError> {
+        Err((self.0)())
+    }
+
+    async fn put_stack_meta(&self, _meta: StackMeta) -> Result<StackMeta, AppError> {
+        Err((self.0)())
+    }
+
+    async fn purge_deleted(&self, _older_than_days: i32) -> Result<u64, AppError> {
+        Err((self.0)())
+    }
+
+    async fn set_photo(&self, _id: i64, _photo: String) -> Result<Person, AppError> {
+        Err((self.0)())
+    }
+
+    async fn remove_stacks(&self, _id: i64, _stacks: Vec<String>) -> Result<Person, AppError> {
+        Err((self.0)())
+    }
+
+    async fn patch_person(&self, _id: i64, _patch: PersonMergePatch) -> Result<Person, AppError> {
+        Err((self.0)())
+    }
+
+    async fn delete_person(&self, _id: i64, _version: Option<i64>) -> Result<(), AppError> {
+        Err((self.0)())
+    }
+
+    async fn reindex(&self) -> Result<(), AppError> {
+        Err((self.0)())
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        Err((self.0)())
+    }
+
+    async fn missing_indexes(&self) -> Result<Vec<String>, AppError> {
+        Err((self.0)())
+    }
+}